@@ -1,174 +1,245 @@
-// Generates mesh quads for a voxel chunk using a simple greedy algorithm.
-// Each invocation processes a slice of the chunk along one axis.
-// Results are stored in a vertex/index buffer.
+// Greedy meshing of up to SLOTS chunks per dispatch. Mirrors `mesh_chunk` in
+// `meshing.rs`: the same face visibility rules per pass, per-vertex ambient
+// occlusion, baked light and packed chunk-local vertices.
+//
+// One workgroup per (slot, face, pass); each of its N + 1 invocations meshes
+// one slice. Output goes to a fixed region per (slot, pass) with its vertex
+// and index counts in `counts`; a count past the region size tells the host to
+// mesh that chunk on the CPU instead.
 
 struct Params {
-    origin: vec3<f32>,
-    step: f32,
-    axis: u32,
-    dir: i32,
-    slice: u32,
-    _pad: u32,
+    slots: u32,
+    _pad0: u32,
+    _pad1: u32,
+    _pad2: u32,
 };
 
-struct Vertex {
-    pos: vec3<f32>,
-    normal: vec3<f32>,
-    uv: vec2<f32>,
-};
-
-@group(0) @binding(0) var<storage, read> voxels: array<u32>;
+@group(0) @binding(0) var<storage, read> cells: array<u32>;
 @group(0) @binding(1) var<uniform> params: Params;
-@group(0) @binding(2) var<storage, read_write> vertices: array<Vertex>;
+@group(0) @binding(2) var<storage, read_write> vertices: array<u32>;
 @group(0) @binding(3) var<storage, read_write> indices: array<u32>;
-@group(0) @binding(4) var<storage, read_write> counts: array<atomic<u32>, 2>;
+@group(0) @binding(4) var<storage, read_write> counts: array<atomic<u32>>;
+
+const N: i32 = 16;
+// Chunk edge including the one-voxel border.
+const B: i32 = 18;
+const CELLS: u32 = 5832u;
+// Must match `GPU_MESHING_SLOTS` and `MAX_QUADS` in `meshing_gpu.rs`.
+const SLOTS: u32 = 4u;
+const MAX_QUADS: u32 = 8192u;
+const MAX_VERTICES: u32 = MAX_QUADS * 4u;
+const MAX_INDICES: u32 = MAX_QUADS * 6u;
+
+const MASK_LEN: u32 = 256u;
+
+struct Cell {
+    present: bool,
+    transparent: bool,
+    light: u32,
+    w0: u32,
+    w1: u32,
+};
 
-const N: u32 = 16u;
+// `p` is chunk-local in -1..=N.
+fn load(slot: u32, p: vec3<i32>) -> Cell {
+    let q = p + vec3<i32>(1, 1, 1);
+    let i = (slot * CELLS + u32((q.x * B + q.y) * B + q.z)) * 2u;
+    let w0 = cells[i];
+    let w1 = cells[i + 1u];
+    return Cell(
+        (w1 & (1u << 21u)) != 0u,
+        (w1 & (1u << 20u)) != 0u,
+        (w1 >> 16u) & 0xfu,
+        w0,
+        w1,
+    );
+}
 
-const MASK_LEN: u32 = N * N;
+fn texture(c: Cell, face: u32) -> u32 {
+    if face < 4u {
+        return (c.w0 >> (face * 8u)) & 0xffu;
+    }
+    return (c.w1 >> ((face - 4u) * 8u)) & 0xffu;
+}
 
-fn voxel_index(p: vec3<i32>) -> u32 {
-    return u32(p.x) * N * N + u32(p.y) * N + u32(p.z);
+fn same_voxel(a: Cell, b: Cell) -> bool {
+    return a.w0 == b.w0 && (a.w1 & 0xffffu) == (b.w1 & 0xffffu);
 }
 
-fn voxel_filled(p: vec3<i32>) -> bool {
-    return p.x >= 0 && p.x < i32(N) && p.y >= 0 && p.y < i32(N) && p.z >= 0 && p.z < i32(N) && voxels[voxel_index(p)] != 0u;
+fn solid(slot: u32, p: vec3<i32>) -> u32 {
+    let c = load(slot, p);
+    return select(0u, 1u, c.present && !c.transparent);
 }
 
-@compute @workgroup_size(1)
-fn main(@builtin(global_invocation_id) id: vec3<u32>) {
-    var mask: array<bool, MASK_LEN>;
+@compute @workgroup_size(17)
+fn main(
+    @builtin(workgroup_id) group: vec3<u32>,
+    @builtin(local_invocation_id) local: vec3<u32>,
+) {
+    let slot = group.x;
+    let face = group.y;
+    let pass_idx = group.z;
+    if slot >= params.slots {
+        return;
+    }
+
+    let axis = face / 2u;
+    let dir = select(-1, 1, face % 2u == 1u);
+    var u_axis = 1u;
+    var v_axis = 2u;
+    if axis == 1u {
+        u_axis = 2u;
+        v_axis = 0u;
+    } else if axis == 2u {
+        u_axis = 0u;
+        v_axis = 1u;
+    }
+    let slice = i32(local.x);
+    let region = slot * 2u + pass_idx;
+
+    // Each entry is 0 (no face) or
+    // 1 << 31 | texture | ao0 << 8 | ao1 << 10 | ao2 << 12 | ao3 << 14 | light << 16;
+    // only identical entries merge.
+    var mask: array<u32, MASK_LEN>;
     var visited: array<bool, MASK_LEN>;
 
-    // Iterate over all axes and both face directions.
-    for (var axis: u32 = 0u; axis < 3u; axis = axis + 1u) {
-        for (var dir_idx: u32 = 0u; dir_idx < 2u; dir_idx = dir_idx + 1u) {
-            let dir: i32 = select(-1, 1, dir_idx == 1u);
-
-            for (var slice: u32 = 0u; slice < N; slice = slice + 1u) {
-                // Build mask for this slice.
-                for (var u: u32 = 0u; u < N; u = u + 1u) {
-                    for (var v: u32 = 0u; v < N; v = v + 1u) {
-                        var cell = vec3<i32>(0, 0, 0);
-                        var neighbor = vec3<i32>(0, 0, 0);
-
-                        if axis == 0u {
-                            cell = vec3<i32>(i32(slice), i32(u), i32(v));
-                            neighbor = cell + vec3<i32>(dir, 0, 0);
-                        } else if axis == 1u {
-                            cell = vec3<i32>(i32(v), i32(slice), i32(u));
-                            neighbor = cell + vec3<i32>(0, dir, 0);
-                        } else {
-                            cell = vec3<i32>(i32(u), i32(v), i32(slice));
-                            neighbor = cell + vec3<i32>(0, 0, dir);
-                        }
-
-                        let i = u * N + v;
-                        mask[i] = voxel_filled(cell) && !voxel_filled(neighbor);
-                        visited[i] = false;
-                    }
+    for (var u: i32 = 0; u < N; u = u + 1) {
+        for (var v: i32 = 0; v < N; v = v + 1) {
+            var cell = vec3<i32>(0, 0, 0);
+            cell[axis] = slice + select(0, -1, dir == 1);
+            cell[u_axis] = u;
+            cell[v_axis] = v;
+            var neighbor = cell;
+            neighbor[axis] = cell[axis] + dir;
+
+            let vox = load(slot, cell);
+            if !vox.present {
+                continue;
+            }
+            let other = load(slot, neighbor);
+            var visible = false;
+            if pass_idx == 0u {
+                visible = !vox.transparent && (!other.present || other.transparent);
+            } else {
+                visible = vox.transparent
+                    && (!other.present || (other.transparent && !same_voxel(vox, other)));
+            }
+            if !visible {
+                continue;
+            }
+
+            // Occluders live in the layer the face looks into; corners in the
+            // order base, base+u, base+u+v, base+v.
+            var du_corner = array<i32, 4>(-1, 1, 1, -1);
+            var dv_corner = array<i32, 4>(-1, -1, 1, 1);
+            var ao_bits = 0u;
+            for (var k: u32 = 0u; k < 4u; k = k + 1u) {
+                var pu = neighbor;
+                pu[u_axis] = pu[u_axis] + du_corner[k];
+                var pv = neighbor;
+                pv[v_axis] = pv[v_axis] + dv_corner[k];
+                var pc = pu;
+                pc[v_axis] = pc[v_axis] + dv_corner[k];
+                let side1 = solid(slot, pu);
+                let side2 = solid(slot, pv);
+                var ao = 0u;
+                if !(side1 == 1u && side2 == 1u) {
+                    ao = 3u - (side1 + side2 + solid(slot, pc));
                 }
+                ao_bits = ao_bits | (ao << (8u + k * 2u));
+            }
+
+            mask[u32(u * N + v)] = (1u << 31u) | texture(vox, face) | ao_bits | (other.light << 16u);
+        }
+    }
 
-                // Greedy merge.
-                for (var u0: u32 = 0u; u0 < N; u0 = u0 + 1u) {
-                    for (var v0: u32 = 0u; v0 < N; v0 = v0 + 1u) {
-                        let i0 = u0 * N + v0;
-                        if !mask[i0] || visited[i0] {
-                            continue;
-                        }
-
-                        var width: u32 = 1u;
-                        loop {
-                            if u0 + width >= N || !mask[u0 + width * N + v0] || visited[u0 + width * N + v0] {
-                                break;
-                            }
-                            width = width + 1u;
-                        }
-
-                        var height: u32 = 1u;
-                        loop {
-                            if v0 + height >= N {
-                                break;
-                            }
-                            var can_expand: bool = true;
-                            for (var du: u32 = 0u; du < width; du = du + 1u) {
-                                let idx = (u0 + du) * N + v0 + height;
-                                if !mask[idx] || visited[idx] {
-                                    can_expand = false;
-                                    break;
-                                }
-                            }
-                            if !can_expand {
-                                break;
-                            }
-                            height = height + 1u;
-                        }
-
-                        for (var du: u32 = 0u; du < width; du = du + 1u) {
-                            for (var dv: u32 = 0u; dv < height; dv = dv + 1u) {
-                                visited[(u0 + du) * N + v0 + dv] = true;
-                            }
-                        }
-
-                        // Compute base world-space position.
-                        var base = params.origin;
-                        if axis == 0u {
-                            base = base + vec3<f32>(f32(slice) + select(0.0, 1.0, dir > 0), f32(u0), f32(v0)) * params.step;
-                        } else if axis == 1u {
-                            base = base + vec3<f32>(f32(v0), f32(slice) + select(0.0, 1.0, dir > 0), f32(u0)) * params.step;
-                        } else {
-                            base = base + vec3<f32>(f32(u0), f32(v0), f32(slice) + select(0.0, 1.0, dir > 0)) * params.step;
-                        }
-
-                        let size = vec2<f32>(f32(width) * params.step, f32(height) * params.step);
-
-                        var normal = vec3<f32>(0.0, 0.0, 0.0);
-                        var u_unit = vec3<f32>(0.0, 0.0, 0.0);
-                        var v_unit = vec3<f32>(0.0, 0.0, 0.0);
-
-                        if axis == 0u {
-                            normal = vec3<f32>(f32(dir), 0.0, 0.0);
-                            u_unit = vec3<f32>(0.0, 1.0, 0.0);
-                            v_unit = vec3<f32>(0.0, 0.0, 1.0);
-                        } else if axis == 1u {
-                            normal = vec3<f32>(0.0, f32(dir), 0.0);
-                            u_unit = vec3<f32>(0.0, 0.0, 1.0);
-                            v_unit = vec3<f32>(1.0, 0.0, 0.0);
-                        } else {
-                            normal = vec3<f32>(0.0, 0.0, f32(dir));
-                            u_unit = vec3<f32>(1.0, 0.0, 0.0);
-                            v_unit = vec3<f32>(0.0, 1.0, 0.0);
-                        }
-
-                        let p0 = base;
-                        let p1 = base + u_unit * size.x;
-                        let p2 = base + u_unit * size.x + v_unit * size.y;
-                        let p3 = base + v_unit * size.y;
-
-                        let vi = atomicAdd(&counts[0], 4u);
-                        vertices[vi] = Vertex(p0, normal, vec2<f32>(0.0, 1.0));
-                        vertices[vi + 1u] = Vertex(p1, normal, vec2<f32>(1.0, 1.0));
-                        vertices[vi + 2u] = Vertex(p2, normal, vec2<f32>(1.0, 0.0));
-                        vertices[vi + 3u] = Vertex(p3, normal, vec2<f32>(0.0, 0.0));
-
-                        let ii = atomicAdd(&counts[1], 6u);
-                        if dir > 0 {
-                            indices[ii] = vi;
-                            indices[ii + 1u] = vi + 1u;
-                            indices[ii + 2u] = vi + 2u;
-                            indices[ii + 3u] = vi + 2u;
-                            indices[ii + 4u] = vi + 3u;
-                            indices[ii + 5u] = vi;
-                        } else {
-                            indices[ii] = vi;
-                            indices[ii + 1u] = vi + 3u;
-                            indices[ii + 2u] = vi + 2u;
-                            indices[ii + 3u] = vi + 2u;
-                            indices[ii + 4u] = vi + 1u;
-                            indices[ii + 5u] = vi;
-                        }
+    // Greedy merge the mask into maximal rectangles.
+    for (var u0: i32 = 0; u0 < N; u0 = u0 + 1) {
+        for (var v0: i32 = 0; v0 < N; v0 = v0 + 1) {
+            let i0 = u32(u0 * N + v0);
+            let key = mask[i0];
+            if key == 0u || visited[i0] {
+                continue;
+            }
+
+            var width = 1;
+            loop {
+                if u0 + width >= N {
+                    break;
+                }
+                let i = u32((u0 + width) * N + v0);
+                if mask[i] != key || visited[i] {
+                    break;
+                }
+                width = width + 1;
+            }
+
+            var height = 1;
+            loop {
+                if v0 + height >= N {
+                    break;
+                }
+                var can_expand = true;
+                for (var du = 0; du < width; du = du + 1) {
+                    let i = u32((u0 + du) * N + v0 + height);
+                    if mask[i] != key || visited[i] {
+                        can_expand = false;
+                        break;
                     }
                 }
+                if !can_expand {
+                    break;
+                }
+                height = height + 1;
+            }
+
+            for (var du = 0; du < width; du = du + 1) {
+                for (var dv = 0; dv < height; dv = dv + 1) {
+                    visited[u32((u0 + du) * N + v0 + dv)] = true;
+                }
+            }
+
+            let vi = atomicAdd(&counts[region * 2u], 4u);
+            let ii = atomicAdd(&counts[region * 2u + 1u], 6u);
+            if vi + 4u > MAX_VERTICES || ii + 6u > MAX_INDICES {
+                continue;
+            }
+
+            var base = vec3<i32>(0, 0, 0);
+            base[axis] = slice;
+            base[u_axis] = u0;
+            base[v_axis] = v0;
+            var u_vec = vec3<i32>(0, 0, 0);
+            u_vec[u_axis] = width;
+            var v_vec = vec3<i32>(0, 0, 0);
+            v_vec[v_axis] = height;
+
+            var corners = array<vec3<i32>, 4>(base, base + u_vec, base + u_vec + v_vec, base + v_vec);
+            var ao = array<u32, 4>(0u, 0u, 0u, 0u);
+            let light = (key >> 16u) & 0xfu;
+            let layer = key & 0xffu;
+            for (var k: u32 = 0u; k < 4u; k = k + 1u) {
+                ao[k] = (key >> (8u + k * 2u)) & 0x3u;
+                let p = vec3<u32>(corners[k]);
+                vertices[region * MAX_VERTICES + vi + k] = p.x
+                    | (p.y << 5u)
+                    | (p.z << 10u)
+                    | (face << 15u)
+                    | (ao[k] << 18u)
+                    | (light << 20u)
+                    | (layer << 24u);
+            }
+
+            // Split along the brighter diagonal; reverse the winding for faces
+            // with a negative normal.
+            var tris = array<u32, 6>(0u, 1u, 2u, 2u, 3u, 0u);
+            if ao[0] + ao[2] < ao[1] + ao[3] {
+                tris = array<u32, 6>(1u, 2u, 3u, 3u, 0u, 1u);
+            }
+            let out = region * MAX_INDICES + ii;
+            for (var k: u32 = 0u; k < 6u; k = k + 1u) {
+                let t = select(tris[5u - k], tris[k], face % 2u == 1u);
+                indices[out + k] = vi + t;
             }
         }
     }
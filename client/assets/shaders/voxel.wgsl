@@ -0,0 +1,77 @@
+// Voxel chunk shader. Every vertex is a single packed u32 (see
+// `material::PackedVertex`): chunk-local position, face, ambient occlusion,
+// light level and texture layer. Positions are in voxel units and the chunk
+// transform places them in the world; UVs are derived from the position so
+// textures repeat across greedy-merged quads.
+
+#import bevy_pbr::mesh_functions::{get_world_from_local, mesh_position_local_to_world}
+#import bevy_pbr::view_transformations::position_world_to_clip
+
+@group(2) @binding(0) var voxel_textures: texture_2d_array<f32>;
+@group(2) @binding(1) var voxel_sampler: sampler;
+
+const FACE_NORMALS = array<vec3<f32>, 6>(
+    vec3<f32>(-1.0, 0.0, 0.0),
+    vec3<f32>(1.0, 0.0, 0.0),
+    vec3<f32>(0.0, -1.0, 0.0),
+    vec3<f32>(0.0, 1.0, 0.0),
+    vec3<f32>(0.0, 0.0, -1.0),
+    vec3<f32>(0.0, 0.0, 1.0),
+);
+
+// Brightness for 0…3 unoccluded neighbours.
+const AO_CURVE = array<f32, 4>(0.35, 0.55, 0.78, 1.0);
+
+struct Vertex {
+    @builtin(instance_index) instance_index: u32,
+    @location(0) packed: u32,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) shade: f32,
+    @location(2) @interpolate(flat) layer: u32,
+};
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    let p = vertex.packed;
+    let local = vec3<f32>(
+        f32(p & 0x1fu),
+        f32((p >> 5u) & 0x1fu),
+        f32((p >> 10u) & 0x1fu),
+    );
+    let face = (p >> 15u) & 0x7u;
+    let ao = (p >> 18u) & 0x3u;
+    let light = (p >> 20u) & 0xfu;
+
+    var out: VertexOutput;
+    let world_from_local = get_world_from_local(vertex.instance_index);
+    let world_position = mesh_position_local_to_world(world_from_local, vec4<f32>(local, 1.0));
+    out.clip_position = position_world_to_clip(world_position.xyz);
+
+    // Project onto the face plane, keeping side textures upright.
+    switch face / 2u {
+        case 0u: { out.uv = vec2<f32>(local.z, -local.y); }
+        case 1u: { out.uv = vec2<f32>(local.x, local.z); }
+        default: { out.uv = vec2<f32>(local.x, -local.y); }
+    }
+
+    // Each light level is 20 % darker; fixed per-face shading keeps faces
+    // distinguishable at equal light.
+    var normals = FACE_NORMALS;
+    var ao_curve = AO_CURVE;
+    let n = normals[face];
+    let lit = max(pow(0.8, f32(15u - light)), 0.04);
+    let face_shade = 0.8 + 0.2 * n.y - 0.05 * abs(n.x);
+    out.shade = ao_curve[ao] * lit * face_shade;
+    out.layer = p >> 24u;
+    return out;
+}
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    let texel = textureSample(voxel_textures, voxel_sampler, in.uv, in.layer);
+    return vec4<f32>(texel.rgb * in.shade, texel.a);
+}
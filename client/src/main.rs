@@ -7,6 +7,7 @@ use std::fs;
 use crate::app::AppPlugin;
 use bevy::gizmos::{AppGizmoBuilder, GizmoPlugin};
 use bevy::log::info;
+use bevy::math::DVec3;
 use bevy::prelude::*;
 use bevy::render::settings::{Backends, RenderCreation, WgpuSettings};
 use bevy::render::RenderPlugin;
@@ -17,6 +18,9 @@ use big_space::plugin::BigSpaceDefaultPlugins;
 use toml;
 use crate::config::Config;
 use crate::plugins::big_space::big_space_plugin::BigSpaceIntegrationPlugin;
+use crate::plugins::environment::systems::voxels::formats::mesh_export::export_meshes;
+use crate::plugins::environment::systems::voxels::meshing_parity::run_parity;
+use crate::plugins::environment::systems::voxels::structure::SparseVoxelOctree;
 
 const TITLE: &str = "voxel-simulation";
 const RESOLUTION: (f32, f32) = (1920f32, 1080f32);
@@ -28,6 +32,10 @@ const PRESENT_MODE: PresentMode = PresentMode::AutoVsync;
 
 
 fn main() {
+    if let Some(code) = run_headless_export().or_else(run_mesh_parity) {
+        std::process::exit(code);
+    }
+
     let config_str = fs::read_to_string("Config.toml").expect("Failed to read config file");
     let config: Config = toml::from_str(&config_str).expect("Failed to parse config");
 
@@ -50,6 +58,82 @@ fn main() {
     app.run();
 }
 
+/// `voxel-simulation --export <file.gltf|file.obj> [--region x0 y0 z0 x1 y1 z1]`
+///
+/// Meshes the saved `octree.bin` with the CPU mesher and writes it to disk
+/// without opening a window. Returns the exit code when an export was requested.
+fn run_headless_export() -> Option<i32> {
+    let args: Vec<String> = std::env::args().collect();
+    let out = args.iter().position(|a| a == "--export").and_then(|i| args.get(i + 1))?;
+
+    let region = match args.iter().position(|a| a == "--region") {
+        Some(i) => {
+            let v: Vec<f64> = args
+                .get(i + 1..i + 7)
+                .unwrap_or_default()
+                .iter()
+                .map_while(|s| s.parse().ok())
+                .collect();
+            if v.len() != 6 {
+                eprintln!("usage: --export <file.gltf|file.obj> [--region x0 y0 z0 x1 y1 z1]");
+                return Some(2);
+            }
+            Some((DVec3::new(v[0], v[1], v[2]), DVec3::new(v[3], v[4], v[5])))
+        }
+        None => None,
+    };
+
+    let tree = match SparseVoxelOctree::load_from_file("octree.bin") {
+        Ok(tree) => tree,
+        Err(err) => {
+            eprintln!("failed to load octree.bin: {err}");
+            return Some(1);
+        }
+    };
+    match export_meshes(&tree, region, out) {
+        Ok(count) => {
+            println!("exported {count} chunks to {out}");
+            Some(0)
+        }
+        Err(err) => {
+            eprintln!("export failed: {err}");
+            Some(1)
+        }
+    }
+}
+
+/// `voxel-simulation --mesh-parity [random cases] [seed] [--cpu-only]`
+///
+/// Checks the CPU greedy mesher against an unmerged reference and, unless
+/// `--cpu-only` is given or no wgpu adapter exists, the WGSL mesher against the
+/// CPU one. Returns the exit code when the check was requested.
+fn run_mesh_parity() -> Option<i32> {
+    let args: Vec<String> = std::env::args().collect();
+    let i = args.iter().position(|a| a == "--mesh-parity")?;
+    let mut numbers = args[i + 1..].iter().map_while(|a| a.parse::<u64>().ok());
+    let random = numbers.next().unwrap_or(64) as usize;
+    let seed = numbers.next().unwrap_or(0);
+    let gpu = !args.iter().any(|a| a == "--cpu-only");
+
+    let report = run_parity(random, seed, gpu);
+    match &report.gpu {
+        Ok(adapter) => println!("gpu mesher ran on {adapter}"),
+        Err(reason) => println!("gpu half skipped: {reason}"),
+    }
+    for chunk in &report.overflowed {
+        println!("overflowed gpu output (meshed on cpu in game): {chunk}");
+    }
+    for failure in &report.failures {
+        println!("FAIL {failure}");
+    }
+    println!(
+        "{} chunks checked, {} failures",
+        report.cases,
+        report.failures.len()
+    );
+    Some(if report.failures.is_empty() { 0 } else { 1 })
+}
+
 #[derive(Resource)]
 pub struct InspectorVisible(bool);
 fn register_platform_plugins(app: &mut App) {
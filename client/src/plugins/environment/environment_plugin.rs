@@ -1,21 +1,31 @@
 use std::path::Path;
+use crate::plugins::environment::systems::voxels::body::{move_voxel_bodies, update_voxel_body_mass};
+use crate::plugins::environment::systems::voxels::culling::despawn_distant_chunks;
 use crate::plugins::environment::systems::voxels::debug::{draw_grid, visualize_octree_system};
+use crate::plugins::environment::systems::voxels::lighting::update_lighting;
 use crate::plugins::environment::systems::voxels::lod::update_chunk_lods;
+use crate::plugins::environment::systems::voxels::material::{VoxelMaterial, VoxelMaterials};
 use crate::plugins::environment::systems::voxels::meshing_gpu::{
-    GpuMeshingWorker, queue_gpu_meshing,
+    GpuMeshingQueue, GpuMeshingWorker, detect_gpu_meshing, queue_gpu_meshing,
 };
 use bevy_app_compute::prelude::{AppComputePlugin, AppComputeWorkerPlugin};
+use crate::plugins::environment::systems::voxels::occlusion::{
+    hide_occluded_chunks, update_chunk_occlusion,
+};
 use crate::plugins::environment::systems::voxels::queue_systems;
 use crate::plugins::environment::systems::voxels::queue_systems::{
-    enqueue_visible_chunks, process_chunk_queue,
+    adapt_chunk_budget, begin_chunk_frame, chunk_diagnostics, end_chunk_frame,
+    enqueue_visible_chunks, process_chunk_queue, track_streaming_camera, track_voxel_volumes,
 };
 use crate::plugins::environment::systems::voxels::render_chunks::rebuild_dirty_chunks;
 use crate::plugins::environment::systems::voxels::atlas::{VoxelTextureAtlas};
 use crate::plugins::environment::systems::voxels::structure::{
-    ChunkBudget, ChunkCullingCfg, ChunkQueue, MeshBufferPool, PrevCameraChunk, SparseVoxelOctree,
-    SpawnedChunks,
+    ChunkBudget, ChunkCullingCfg, ChunkMeshTasks, ChunkMeshingCfg, ChunkMeshingStats, ChunkQueue,
+    EmptyChunks, MeshBufferPool, MeshingBackend, PrevCameraChunk, SparseVoxelOctree,
+    SpawnedChunks, StreamingCamera,
 };
-use bevy::app::{App, Plugin, PreStartup, PreUpdate, Startup};
+use bevy::app::{App, First, Last, Plugin, PreStartup, PreUpdate, Startup};
+use bevy::diagnostic::RegisterDiagnostic;
 use bevy::prelude::*;
 
 pub struct EnvironmentPlugin;
@@ -31,14 +41,38 @@ impl Plugin for EnvironmentPlugin {
                 crate::plugins::environment::systems::voxel_system::setup,
             ),
         );
+        // Lighting is baked into the chunk vertices, so the voxel material
+        // needs neither the prepass nor shadow passes.
+        app.add_plugins(MaterialPlugin::<VoxelMaterial> {
+            prepass_enabled: false,
+            shadows_enabled: false,
+            ..default()
+        });
         app.add_plugins(AppComputePlugin);
         app.add_plugins(AppComputeWorkerPlugin::<GpuMeshingWorker>::default());
 
         let view_distance_chunks = 100;
         app.insert_resource(ChunkCullingCfg {
             view_distance_chunks,
+            unload_distance_chunks: view_distance_chunks + 8,
+            max_chunks: 16_384,
+            occlusion_distance_chunks: 16,
+        });
+        app.insert_resource(ChunkBudget {
+            per_frame: 20,
+            ..default()
+        });
+        for diagnostic in chunk_diagnostics() {
+            app.register_diagnostic(diagnostic);
+        }
+        // `MeshingBackend::Gpu` meshes on the compute worker instead; it is
+        // switched back to the CPU at startup when compute is unavailable.
+        app.insert_resource(ChunkMeshingCfg {
+            backend: MeshingBackend::Cpu,
         });
-        app.insert_resource(ChunkBudget { per_frame: 20 });
+        app.add_systems(Startup, detect_gpu_meshing);
+        app.add_systems(First, begin_chunk_frame);
+        app.add_systems(Last, end_chunk_frame);
         app.init_resource::<PrevCameraChunk>();
        /* app.add_systems(Update, log_mesh_count);*/
         app
@@ -47,19 +81,34 @@ impl Plugin for EnvironmentPlugin {
             // ------------------------------------------------------------------------
             .init_resource::<ChunkQueue>()
             .init_resource::<SpawnedChunks>()
+            .init_resource::<EmptyChunks>()
             .init_resource::<MeshBufferPool>()
+            .init_resource::<GpuMeshingQueue>()
+            .init_resource::<ChunkMeshTasks>()
+            .init_resource::<ChunkMeshingStats>()
+            .init_resource::<StreamingCamera>()
             // ------------------------------------------------------------------------
             // frame update
             // ------------------------------------------------------------------------
             .add_systems(
                 Update,
                 (
+                    /* ---------- lighting ---------------------- */
+                    update_lighting,
+                    update_voxel_body_mass.after(update_lighting),
                     /* ---------- culling & streaming ---------- */
-                    enqueue_visible_chunks,
+                    track_streaming_camera,
+                    move_voxel_bodies.after(track_streaming_camera),
+                    track_voxel_volumes.after(move_voxel_bodies),
+                    update_chunk_occlusion.after(track_voxel_volumes),
+                    despawn_distant_chunks.after(update_chunk_occlusion),
+                    enqueue_visible_chunks.after(despawn_distant_chunks),
                     process_chunk_queue.after(enqueue_visible_chunks),
                     update_chunk_lods.after(process_chunk_queue),
                     rebuild_dirty_chunks.after(process_chunk_queue), // 4.  (re)mesh dirty chunks
                     queue_gpu_meshing.after(rebuild_dirty_chunks),
+                    hide_occluded_chunks.after(rebuild_dirty_chunks),
+                    adapt_chunk_budget.after(queue_gpu_meshing),
                     /* ---------- optional debug drawing ------- */
                     visualize_octree_system
                         .run_if(should_visualize_octree)
@@ -80,20 +129,19 @@ impl Plugin for EnvironmentPlugin {
 }*/
 
 fn should_visualize_octree(octree_query: Query<&SparseVoxelOctree>) -> bool {
-    let Ok(octree) = octree_query.get_single() else {
-        return false;
-    };
-    octree.show_wireframe
+    octree_query.iter().any(|octree| octree.show_wireframe)
 }
 
 fn should_draw_grid(octree_query: Query<&SparseVoxelOctree>) -> bool {
-    let Ok(octree) = octree_query.get_single() else {
-        return false;
-    };
-    octree.show_world_grid
+    octree_query.iter().any(|octree| octree.show_world_grid)
 }
 
-fn setup_texture_atlas(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
+fn setup_texture_atlas(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+    mut materials: ResMut<Assets<VoxelMaterial>>,
+) {
     let atlas = VoxelTextureAtlas::generate(&mut images);
+    commands.insert_resource(VoxelMaterials::new(&atlas, &mut materials));
     commands.insert_resource(atlas);
 }
@@ -1,7 +1,13 @@
 use crate::plugins::big_space::big_space_plugin::RootGrid;
+use crate::plugins::environment::systems::voxels::features::{place_features, FeatureConfig};
+use crate::plugins::environment::systems::voxels::formats::heightmap::{
+    import_heightmap, Heightmap, HeightmapSettings,
+};
 use crate::plugins::environment::systems::voxels::structure::*;
+use bevy::math::DVec3;
 use bevy::prelude::*;
 use bevy::render::mesh::*;
+use big_space::prelude::{Grid, GridCell};
 use noise::{NoiseFn, Perlin};
 use rand::{thread_rng, Rng};
 use rayon::prelude::*;
@@ -20,11 +26,12 @@ pub fn setup(mut commands: Commands, root: Res<RootGrid>) {
     let handle = builder
         .spawn(move || {
             // Octree parameters
-            let unit_size = 1.0_f32;
+            let unit_size = 1.0_f64;
             let octree_base_size = 64.0 * unit_size;
             let octree_depth = 10;
 
             let path = Path::new("octree.bin");
+            let heightmap_path = Path::new("heightmap.png");
 
             let mut octree = if Path::new(path).exists() {
                 match SparseVoxelOctree::load_from_file(path) {
@@ -34,6 +41,20 @@ pub fn setup(mut commands: Commands, root: Res<RootGrid>) {
                         SparseVoxelOctree::new(octree_depth, octree_base_size, false, false, false)
                     }
                 }
+            } else if heightmap_path.exists() {
+                let mut tree =
+                    SparseVoxelOctree::new(octree_depth, octree_base_size, false, false, false);
+                match Heightmap::load(heightmap_path) {
+                    Ok(map) => {
+                        let settings = HeightmapSettings {
+                            voxel_size: unit_size as f32,
+                            ..Default::default()
+                        };
+                        import_heightmap(&mut tree, &map, &settings);
+                    }
+                    Err(err) => error!("failed to load heightmap: {err}"),
+                }
+                tree
             } else {
                 let mut tree =
                     SparseVoxelOctree::new(octree_depth, octree_base_size, false, false, false);
@@ -42,7 +63,7 @@ pub fn setup(mut commands: Commands, root: Res<RootGrid>) {
                 let mut rng = thread_rng();
 
                 for _ in 0..NUM_SPHERES {
-                    let center = Vec3::new(
+                    let center = DVec3::new(
                         rng.gen_range(-500.0..500.0),
                         rng.gen_range(-500.0..500.0),
                         rng.gen_range(-500.0..500.0),
@@ -54,6 +75,10 @@ pub fn setup(mut commands: Commands, root: Res<RootGrid>) {
                 }
 
                 /*generate_voxel_sphere(&mut tree, 200);*/
+
+                // Decorate the generated surfaces with trees, rocks and pillars
+                let keys: Vec<ChunkKey> = tree.occupied_chunks.iter().copied().collect();
+                place_features(&mut tree, &keys, &FeatureConfig::default());
                 tree
             };
 
@@ -64,19 +89,28 @@ pub fn setup(mut commands: Commands, root: Res<RootGrid>) {
 
     let octree = handle.expect("Failed to join octree build thread");
 
-    // Attach octree to the scene graph
+    // Attach octree to the scene graph as a volume with its own grid, its
+    // chunks are spawned as children in that grid
     commands.entity(root.0).with_children(|parent| {
-        parent.spawn((Transform::default(), octree));
+        parent.spawn((
+            Grid::default(),
+            GridCell::ZERO,
+            Transform::default(),
+            Visibility::default(),
+            // saved back to `octree.bin` by the F4 key
+            Name::new("octree"),
+            octree,
+        ));
     });
 }
 
 
-pub fn generate_voxel_sphere_parallel(octree: &mut SparseVoxelOctree, center: Vec3, radius: i32) {
+pub fn generate_voxel_sphere_parallel(octree: &mut SparseVoxelOctree, center: DVec3, radius: i32) {
     let step = octree.get_spacing_at_depth(octree.max_depth);
     let radius_sq = radius * radius;
 
     // 1. Collect voxel positions in parallel
-    let voxels: Vec<(Vec3, Voxel)> = (-radius..=radius)
+    let voxels: Vec<(DVec3, Voxel)> = (-radius..=radius)
         .into_par_iter()
         .flat_map_iter(|ix| {
             let dx2 = ix * ix;
@@ -91,11 +125,7 @@ pub fn generate_voxel_sphere_parallel(octree: &mut SparseVoxelOctree, center: Ve
                 let max_z = ((radius_sq - r2_xy) as f32).sqrt() as i32;
                 (-max_z..=max_z)
                     .map(move |iz| {
-                        let pos = Vec3::new(
-                            center.x + ix as f32 * step,
-                            center.y + iy as f32 * step,
-                            center.z + iz as f32 * step,
-                        );
+                        let pos = center + IVec3::new(ix, iy, iz).as_dvec3() * step;
                         (pos, Voxel::random_sides())
                     })
                     .collect::<Vec<_>>()
@@ -103,13 +133,11 @@ pub fn generate_voxel_sphere_parallel(octree: &mut SparseVoxelOctree, center: Ve
         })
         .collect();
 
-    // 2. Single-threaded insert (keeps `SparseVoxelOctree` API unchanged)
-    for (pos, voxel) in voxels {
-        octree.insert(pos, voxel);
-    }
+    // 2. Single batched insert
+    octree.insert_batch(&voxels);
 }
 
-fn generate_voxel_sphere(octree: &mut SparseVoxelOctree, center: Vec3, planet_radius: i32) {
+fn generate_voxel_sphere(octree: &mut SparseVoxelOctree, center: DVec3, planet_radius: i32) {
     // For simplicity, we center the sphere around (0,0,0).
     // We'll loop over a cubic region [-planet_radius, +planet_radius] in x, y, z
     let min = -planet_radius;
@@ -128,10 +156,10 @@ fn generate_voxel_sphere(octree: &mut SparseVoxelOctree, center: Vec3, planet_ra
                 let dist2 = x * x + y * y + z * z;
                 if dist2 <= planet_radius * planet_radius {
                     // Convert (x,y,z) to world space, stepping by `voxel_step`.
-                    let wx = x as f32 * step;
-                    let wy = y as f32 * step;
-                    let wz = z as f32 * step;
-                    let position = center + Vec3::new(wx, wy, wz);
+                    let wx = x as f64 * step;
+                    let wy = y as f64 * step;
+                    let wz = z as f64 * step;
+                    let position = center + DVec3::new(wx, wy, wz);
 
                     // Insert the voxel
                     let voxel = Voxel::random_sides();
@@ -155,18 +183,18 @@ fn generate_voxel_rect(octree: &mut SparseVoxelOctree) {
 
     // Triple-nested loop for each voxel in [0..16, 0..256, 0..16]
     for ix in 0..size_x {
-        let x = ix as f32;
+        let x = ix as f64;
         for iy in 0..size_y {
-            let y = iy as f32;
+            let y = iy as f64;
             for iz in 0..size_z {
-                let z = iz as f32;
+                let z = iz as f64;
 
                 // Convert (x,y,z) to world coordinates
                 let wx = x * step;
                 let wy = y * step;
                 let wz = z * step;
 
-                let position = Vec3::new(wx, wy, wz);
+                let position = DVec3::new(wx, wy, wz);
 
                 // Insert the voxel
                 let voxel = Voxel::random_sides();
@@ -183,9 +211,9 @@ fn generate_large_plane(octree: &mut SparseVoxelOctree, width: usize, depth: usi
     // Double-nested loop for each voxel in [0..width, 0..depth],
     // with y=0.
     for ix in 0..width {
-        let x = ix as f32;
+        let x = ix as f64;
         for iz in 0..depth {
-            let z = iz as f32;
+            let z = iz as f64;
             // y is always 0.
             let y = 0.0;
 
@@ -194,7 +222,7 @@ fn generate_large_plane(octree: &mut SparseVoxelOctree, width: usize, depth: usi
             let wy = y * step;
             let wz = z * step;
 
-            let position = Vec3::new(wx, wy, wz);
+            let position = DVec3::new(wx, wy, wz);
 
             // Insert the voxel
             let voxel = Voxel::random_sides();
@@ -227,11 +255,11 @@ pub fn generate_solid_plane_with_noise(
             // Height in world units
             let height_world = noise_val * amplitude;
             // Convert height to number of voxel layers
-            let max_layer = (height_world / step).ceil() as usize;
+            let max_layer = (height_world as f64 / step).ceil() as usize;
 
             // Fill from layer 0 up to max_layer
             for iy in 0..=max_layer {
-                let position = Vec3::new(x * step, iy as f32 * step, z * step);
+                let position = DVec3::new(x as f64, iy as f64, z as f64) * step;
 
                 let voxel = Voxel::random_sides();
                 octree.insert(position, voxel);
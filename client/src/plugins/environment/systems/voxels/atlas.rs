@@ -1,42 +1,66 @@
 use bevy::asset::RenderAssetUsages;
 use bevy::prelude::*;
-use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+use bevy::image::{ImageAddressMode, ImageFilterMode, ImageSampler, ImageSamplerDescriptor};
+use bevy::render::render_resource::{
+    Extent3d, TextureDimension, TextureFormat, TextureViewDescriptor, TextureViewDimension,
+};
+
+/// Solid colour of every tile in the procedural atlas, indexed by texture id.
+/// Tiles with alpha below 255 are translucent and go to the transparent pass.
+pub const ATLAS_COLORS: [[u8; 4]; 9] = [
+    [255, 0, 0, 255],     // 0: red
+    [0, 0, 0, 255],       // 1: black
+    [0, 255, 0, 255],     // 2: green
+    [0, 0, 255, 255],     // 3: blue
+    [255, 255, 0, 255],   // 4: yellow
+    [255, 0, 255, 255],   // 5: magenta
+    [200, 230, 255, 80],  // 6: glass
+    [30, 80, 220, 150],   // 7: water
+    [40, 150, 40, 200],   // 8: leaves
+];
+
+/// Whether the atlas tile `texture` is see-through.
+pub fn is_transparent_texture(texture: usize) -> bool {
+    ATLAS_COLORS
+        .get(texture)
+        .is_some_and(|c| c[3] < 255)
+}
+
+/// Opaque texture id whose atlas colour is closest to `rgb`.
+pub fn nearest_texture(rgb: [u8; 3]) -> usize {
+    ATLAS_COLORS
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !is_transparent_texture(*i))
+        .min_by_key(|(_, c)| {
+            (0..3)
+                .map(|i| (c[i] as i32 - rgb[i] as i32).pow(2))
+                .sum::<i32>()
+        })
+        .map(|(i, _)| i)
+        .unwrap_or(0)
+}
 
 /// Configuration and handle for the voxel texture atlas.
+///
+/// `handle` is the flat atlas used by exporters; `layers` holds the same tiles
+/// as a mipmapped 2D texture array (one layer per texture id) for rendering.
 #[derive(Resource, Clone)]
 pub struct VoxelTextureAtlas {
     pub handle: Handle<Image>,
+    pub layers: Handle<Image>,
     pub columns: usize,
     pub rows: usize,
 }
 
 impl VoxelTextureAtlas {
+    const TILE_SIZE: u32 = 16;
+    const COLUMNS: usize = 3;
+    const ROWS: usize = 3;
+
     /// Create a simple procedural atlas with solid colors.
     pub fn generate(images: &mut Assets<Image>) -> Self {
-        let tile_size = 16u32;
-        let columns = 2;
-        let rows = 3;
-        let width = tile_size * columns as u32;
-        let height = tile_size * rows as u32;
-        let mut data = vec![0u8; (width * height * 4) as usize];
-        let colors = [
-            [255, 0, 0, 255],   // 0: red
-            [0, 0, 0, 255],     // 1: black
-            [0, 255, 0, 255],   // 2: green
-            [0, 0, 255, 255],   // 3: blue
-            [255, 255, 0, 255], // 4: yellow
-            [255, 0, 255, 255], // 5: magenta
-        ];
-        for (i, col) in colors.iter().enumerate() {
-            let cx = (i % columns) as u32 * tile_size;
-            let cy = (i / columns) as u32 * tile_size;
-            for y in 0..tile_size {
-                for x in 0..tile_size {
-                    let idx = (((cy + y) * width + (cx + x)) * 4) as usize;
-                    data[idx..idx + 4].copy_from_slice(col);
-                }
-            }
-        }
+        let (width, height, data) = Self::pixels();
         let image = Image::new_fill(
             Extent3d {
                 width,
@@ -49,11 +73,103 @@ impl VoxelTextureAtlas {
             RenderAssetUsages::default(),
         );
         let handle = images.add(image);
+        let layers = images.add(Self::layer_image());
         Self {
             handle,
-            columns,
-            rows,
+            layers,
+            ..Self::headless()
+        }
+    }
+
+    /// Every tile as one layer of a 2D array texture with a full mip chain.
+    /// Sampling repeats, so UVs in voxel units tile across merged quads.
+    pub fn layer_image() -> Image {
+        let tile = Self::TILE_SIZE;
+        let mip_levels = tile.ilog2() + 1;
+
+        // Layer-major order: all mips of layer 0, then layer 1, …
+        let mut data = Vec::new();
+        for color in ATLAS_COLORS.iter() {
+            let mut level: Vec<u8> = color.repeat((tile * tile) as usize);
+            let mut size = tile;
+            data.extend_from_slice(&level);
+            for _ in 1..mip_levels {
+                // 2×2 box filter
+                let half = size / 2;
+                let mut next = vec![0u8; (half * half * 4) as usize];
+                for y in 0..half {
+                    for x in 0..half {
+                        for c in 0..4 {
+                            let texel = |dx: u32, dy: u32| {
+                                level[(((2 * y + dy) * size + 2 * x + dx) * 4 + c) as usize] as u32
+                            };
+                            next[((y * half + x) * 4 + c) as usize] =
+                                ((texel(0, 0) + texel(1, 0) + texel(0, 1) + texel(1, 1)) / 4) as u8;
+                        }
+                    }
+                }
+                data.extend_from_slice(&next);
+                level = next;
+                size = half;
+            }
+        }
+
+        let mut image = Image::new_uninit(
+            Extent3d {
+                width: tile,
+                height: tile,
+                depth_or_array_layers: ATLAS_COLORS.len() as u32,
+            },
+            TextureDimension::D2,
+            TextureFormat::Rgba8UnormSrgb,
+            RenderAssetUsages::default(),
+        );
+        image.data = Some(data);
+        image.texture_descriptor.mip_level_count = mip_levels;
+        image.texture_view_descriptor = Some(TextureViewDescriptor {
+            dimension: Some(TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        image.sampler = ImageSampler::Descriptor(ImageSamplerDescriptor {
+            address_mode_u: ImageAddressMode::Repeat,
+            address_mode_v: ImageAddressMode::Repeat,
+            mag_filter: ImageFilterMode::Nearest,
+            min_filter: ImageFilterMode::Linear,
+            mipmap_filter: ImageFilterMode::Linear,
+            ..Default::default()
+        });
+        image
+    }
+
+    /// Atlas layout without a GPU image, for meshing outside the app
+    /// (exporters, headless tools).
+    pub fn headless() -> Self {
+        Self {
+            handle: Handle::default(),
+            layers: Handle::default(),
+            columns: Self::COLUMNS,
+            rows: Self::ROWS,
+        }
+    }
+
+    /// RGBA8 pixels of the procedural atlas as `(width, height, data)`.
+    pub fn pixels() -> (u32, u32, Vec<u8>) {
+        let tile_size = Self::TILE_SIZE;
+        let columns = Self::COLUMNS;
+        let width = tile_size * columns as u32;
+        let height = tile_size * Self::ROWS as u32;
+        let mut data = vec![0u8; (width * height * 4) as usize];
+        for (i, col) in ATLAS_COLORS.iter().enumerate() {
+            let cx = (i % columns) as u32 * tile_size;
+            let cy = (i / columns) as u32 * tile_size;
+            for y in 0..tile_size {
+                for x in 0..tile_size {
+                    let idx = (((cy + y) * width + (cx + x)) * 4) as usize;
+                    data[idx..idx + 4].copy_from_slice(col);
+                }
+            }
         }
+        (width, height, data)
     }
 
     /// Compute UV coordinates for the given atlas index.
@@ -0,0 +1,41 @@
+use crate::plugins::environment::systems::voxels::structure::{SparseVoxelOctree, VoxelBody};
+use bevy::math::{DAffine3, DVec3};
+use bevy::prelude::*;
+
+/// Move and rotate every [`VoxelBody`] by its velocities.
+///
+/// The rotation turns the body around its centre of mass, so a spinning ship
+/// stays in place instead of swinging around its octree origin. Only the
+/// transform changes; big_space moves the body into another grid cell when it
+/// leaves its own.
+pub fn move_voxel_bodies(time: Res<Time>, mut bodies: Query<(&VoxelBody, &mut Transform)>) {
+    let dt = time.delta_secs();
+    for (body, mut tf) in &mut bodies {
+        // stay in f64 until the end, the centre of mass can be far from the
+        // octree origin
+        let translation = tf.translation.as_dvec3();
+        let pivot = DAffine3::from_scale_rotation_translation(
+            tf.scale.as_dvec3(),
+            tf.rotation.as_dquat(),
+            translation,
+        )
+        .transform_point3(body.center_of_mass);
+        let spin = Quat::from_scaled_axis(body.angular_velocity * dt);
+        let moved = pivot + spin.as_dquat() * (translation - pivot);
+        tf.rotation = (spin * tf.rotation).normalize();
+        tf.translation = (moved + body.linear_velocity * dt as f64).as_vec3();
+    }
+}
+
+/// Keep the mass and centre of mass of every [`VoxelBody`] current from the
+/// voxel count and position sum its octree updates on every edit.
+pub fn update_voxel_body_mass(mut bodies: Query<(&SparseVoxelOctree, &mut VoxelBody)>) {
+    for (tree, mut body) in &mut bodies {
+        body.mass = tree.voxel_count;
+        body.center_of_mass = if tree.voxel_count > 0 {
+            tree.voxel_sum / tree.voxel_count as f64
+        } else {
+            DVec3::ZERO
+        };
+    }
+}
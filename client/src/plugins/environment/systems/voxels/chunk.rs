@@ -1,32 +1,105 @@
+use bevy::math::DVec3;
 use bevy::prelude::*;
-use crate::plugins::environment::systems::voxels::structure::{ChunkKey, SparseVoxelOctree, Voxel, CHUNK_POW, CHUNK_SIZE};
+use crate::plugins::environment::systems::voxels::structure::{ChunkKey, ChunkSnapshot, OctreeNode, SparseVoxelOctree, CHUNK_POW, CHUNK_SIZE};
 
 /// Component attached to the entity that owns the mesh of one chunk.
 
 impl SparseVoxelOctree {
     pub fn chunk_has_any_voxel(&self, key: ChunkKey) -> bool {
-        // world-space centre of the chunk
-        let step = self.get_spacing_at_depth(self.max_depth);
+        // the octree node that exactly matches the chunk, and whether that
+        // node or any child contains voxels
+        self.chunk_node(key).is_some_and(|node| self.has_volume(node))
+    }
+
+    /// World-space position of the minimum corner of a chunk.
+    pub fn chunk_origin_world(&self, key: ChunkKey) -> DVec3 {
         let half = self.size * 0.5;
-        let centre = Vec3::new(
-            self.center.x - half + (key.0 as f32 + 0.5) * CHUNK_SIZE as f32 * step,
-            self.center.y - half + (key.1 as f32 + 0.5) * CHUNK_SIZE as f32 * step,
-            self.center.z - half + (key.2 as f32 + 0.5) * CHUNK_SIZE as f32 * step,
-        );
+        let chunk = CHUNK_SIZE as f64 * self.get_spacing_at_depth(self.max_depth);
+        self.center - DVec3::splat(half) + IVec3::new(key.0, key.1, key.2).as_dvec3() * chunk
+    }
 
-        // depth of the octree node that exactly matches one chunk
+    /// Octree node covering exactly one chunk, or `None` when the chunk lies
+    /// outside the root or nothing was ever inserted there.
+    fn chunk_node(&self, key: ChunkKey) -> Option<&OctreeNode> {
         let depth = self.max_depth.saturating_sub(CHUNK_POW);
+        let count = 1 << depth;
+        if [key.0, key.1, key.2].iter().any(|k| !(0..count).contains(k)) {
+            return None;
+        }
+        let norm = self.normalize_to_voxel_at_depth(self.chunk_center_world(key), depth);
+        Self::get_node_at_depth(&self.root, norm, depth)
+    }
+
+    /// Write the subtree of `node`, which spans `size` voxels from the
+    /// chunk-local corner `min`, into the snapshot cells it overlaps. Nodes of
+    /// `block` voxels are written whole from their representative voxel
+    /// instead of being descended into.
+    fn extract_node(node: &OctreeNode, min: IVec3, size: i32, block: i32, out: &mut ChunkSnapshot) {
+        let lo = min.max(IVec3::splat(-1));
+        let hi = (min + IVec3::splat(size)).min(IVec3::splat(CHUNK_SIZE + 1));
+        if lo.cmpge(hi).any() {
+            return;
+        }
+        let whole = size <= block;
+        let fill = if whole {
+            node.representative()
+        } else {
+            node.voxel.filter(|_| node.is_leaf)
+        };
+        if let Some(voxel) = fill {
+            for x in lo.x..hi.x {
+                for y in lo.y..hi.y {
+                    for z in lo.z..hi.z {
+                        out.voxels[ChunkSnapshot::index(IVec3::new(x, y, z))] = Some(voxel);
+                    }
+                }
+            }
+        }
+        if whole {
+            return;
+        }
+        if let Some(children) = &node.children {
+            let half = size / 2;
+            for (i, child) in children.iter().enumerate() {
+                let offset = IVec3::new((i & 1) as i32, (i >> 1 & 1) as i32, (i >> 2 & 1) as i32);
+                Self::extract_node(child, min + offset * half, half, block, out);
+            }
+        }
+    }
 
-        // normalised coordinates of that centre at the chosen depth
-        let norm  = self.normalize_to_voxel_at_depth(centre, depth);
+    /// Extract one chunk for meshing. The node at chunk depth is looked up once
+    /// for the chunk and each of its 26 neighbours, and their subtrees are
+    /// written straight into the padded snapshot.
+    ///
+    /// At `lod > 0` the tree is only descended to nodes of `2^lod` voxels,
+    /// whose aggregated LOD voxel fills the whole block. The border is taken at
+    /// the same level, so chunks sharing a LOD also agree on their seams.
+    pub fn extract_chunk(&self, key: ChunkKey, lod: u32) -> ChunkSnapshot {
+        let n = CHUNK_SIZE;
+        let block = 1 << lod.min(CHUNK_POW);
+        let mut snapshot = ChunkSnapshot::empty();
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                for dz in -1..=1 {
+                    let neighbour = ChunkKey(key.0 + dx, key.1 + dy, key.2 + dz);
+                    if let Some(node) = self.chunk_node(neighbour) {
+                        let min = IVec3::new(dx, dy, dz) * n;
+                        Self::extract_node(node, min, n, block, &mut snapshot);
+                    }
+                }
+            }
+        }
 
-        // walk the tree down to that node …
-        if let Some(node) =
-            Self::get_node_at_depth(&self.root, norm.x, norm.y, norm.z, depth)
-        {
-            // … and ask whether that node or any child contains voxels
-            return self.has_volume(node);
+        let step = self.get_spacing_at_depth(self.max_depth);
+        let base = self.world_to_voxel(self.chunk_origin_world(key) + DVec3::splat(step * 0.5));
+        for x in -1..=n {
+            for y in -1..=n {
+                for z in -1..=n {
+                    let p = IVec3::new(x, y, z);
+                    snapshot.light[ChunkSnapshot::index(p)] = self.light.level(base + p);
+                }
+            }
         }
-        false
+        snapshot
     }
-}
\ No newline at end of file
+}
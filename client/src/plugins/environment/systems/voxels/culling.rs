@@ -1,41 +1,103 @@
+use crate::plugins::environment::systems::voxels::meshing_gpu::GpuMeshingQueue;
+use crate::plugins::environment::systems::voxels::occlusion::{ChunkOcclusion, chunk_in_frustum};
 use crate::plugins::environment::systems::voxels::structure::*;
 use bevy::prelude::*;
-use std::collections::{HashMap, VecDeque};
-
-/// despawn (or hide) every chunk entity whose centre is farther away than the
-/// configured radius
+use bevy::render::primitives::Frustum;
+use std::collections::HashMap;
 
+/// despawn every chunk entity farther away than the unload radius, then the
+/// least recently visible chunks until at most `max_chunks` remain
+///
+/// Distances are measured in the octree space of each chunk's volume; chunks
+/// of a volume that no longer exists are unloaded too. Chunks in the frustum
+/// and not occluded have their [`ChunkLastVisible`] refreshed first, so chunks
+/// on screen are never evicted. Meshing still in flight for an unloaded chunk
+/// is dropped.
 pub fn despawn_distant_chunks(
     mut commands: Commands,
-    cam_q: Query<&GlobalTransform, With<Camera>>,
-    tree_q: Query<&SparseVoxelOctree>,
+    cam_q: Query<&Frustum, With<Camera>>,
+    mut volumes: Query<(Entity, &SparseVoxelOctree, &VolumeTransform, &mut ChunkOcclusion)>,
     mut spawned: ResMut<SpawnedChunks>,
-    chunk_q: Query<(Entity, &Chunk, &Mesh3d, &MeshMaterial3d<StandardMaterial>)>,
+    mut empty: ResMut<EmptyChunks>,
+    mut chunk_q: Query<(Entity, &Chunk, Option<&Mesh3d>, &mut ChunkLastVisible)>,
+    transparent_q: Query<(&TransparentChunkMesh, &Mesh3d)>,
     mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut tasks: ResMut<ChunkMeshTasks>,
+    mut gpu: ResMut<GpuMeshingQueue>,
     cfg: Res<ChunkCullingCfg>,
+    camera: Res<StreamingCamera>,
+    time: Res<Time>,
 ) {
-    let Ok(tree) = tree_q.get_single() else {
-        return;
-    };
-    let Ok(cam_tf) = cam_q.get_single() else {
+    let Ok(frustum) = cam_q.get_single() else {
         return;
     };
-    let cam = cam_tf.translation();
-    let centre = tree.world_to_chunk(cam);
+    let now = time.elapsed_secs();
+    let r = cfg.unload_distance_chunks;
+
+    // camera chunk of every volume
+    let centres: HashMap<Entity, ChunkKey> = volumes
+        .iter()
+        .map(|(ent, tree, volume, _)| (ent, tree.world_to_chunk(volume.to_local(camera.position))))
+        .collect();
 
-    for (ent, chunk, mesh3d, mat3d) in chunk_q.iter() {
+    // empty chunks have no entity; forget them with their volume or once out of range
+    empty.0.retain(|(volume, key)| {
+        centres.get(volume).is_some_and(|centre| {
+            (key.0 - centre.0).abs() <= r
+                && (key.1 - centre.1).abs() <= r
+                && (key.2 - centre.2).abs() <= r
+        })
+    });
+
+    let mut unload = Vec::new();
+    let mut kept = Vec::new();
+    for (ent, chunk, _, mut last_visible) in chunk_q.iter_mut() {
+        let (Some(centre), Ok((_, tree, volume, occlusion))) =
+            (centres.get(&chunk.volume), volumes.get(chunk.volume))
+        else {
+            unload.push(ent);
+            continue;
+        };
         let ChunkKey(x, y, z) = chunk.key;
-        if (x - centre.0).abs() > cfg.view_distance_chunks
-            || (y - centre.1).abs() > cfg.view_distance_chunks
-            || (z - centre.2).abs() > cfg.view_distance_chunks
+        if (x - centre.0).abs() > r || (y - centre.1).abs() > r || (z - centre.2).abs() > r {
+            unload.push(ent);
+            continue;
+        }
+        if !occlusion.is_occluded(chunk.key)
+            && chunk_in_frustum(tree, volume, chunk.key, frustum, camera.origin)
         {
-            // free assets – borrow, don't move
+            last_visible.0 = now;
+        }
+        kept.push((ent, last_visible.0));
+    }
+
+    if kept.len() > cfg.max_chunks {
+        kept.sort_by(|a, b| a.1.total_cmp(&b.1));
+        let excess = kept.len() - cfg.max_chunks;
+        unload.extend(kept[..excess].iter().filter(|(_, seen)| *seen < now).map(|(e, _)| *e));
+    }
+    if unload.is_empty() {
+        return;
+    }
+
+    let transparent: HashMap<(Entity, ChunkKey), &Mesh3d> =
+        transparent_q.iter().map(|(t, m)| ((t.volume, t.key), m)).collect();
+    for ent in unload {
+        let Ok((ent, chunk, mesh3d, _)) = chunk_q.get(ent) else {
+            continue;
+        };
+        // free meshes – the materials are shared by every chunk
+        let child = transparent.get(&(chunk.volume, chunk.key)).copied();
+        for mesh3d in mesh3d.into_iter().chain(child) {
             meshes.remove(&mesh3d.0);
-            materials.remove(&mat3d.0);
+        }
 
-            commands.entity(ent).despawn_recursive();
-            spawned.0.remove(&chunk.key);
+        commands.entity(ent).despawn_recursive();
+        spawned.remove(chunk.volume, chunk.key);
+        tasks.latest.remove(&(chunk.volume, chunk.key));
+        gpu.cancel(chunk.volume, chunk.key);
+        if let Ok((_, _, _, mut occlusion)) = volumes.get_mut(chunk.volume) {
+            occlusion.forget(chunk.key);
         }
     }
 }
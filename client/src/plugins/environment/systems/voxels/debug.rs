@@ -2,18 +2,22 @@ use crate::plugins::environment::systems::voxels::structure::*;
 use bevy::prelude::*;
 
 /// Visualize each node of the octree as a scaled cuboid, **center-based**.
-/// `octree_tf.translation` is the world-space center of the root bounding box.
+/// Cuboids are placed in octree space and drawn through the volume's
+/// `GlobalTransform`, so they follow the volume when it moves or rotates.
 pub fn visualize_octree_system(
     mut gizmos: Gizmos,
-    octree_query: Query<(&SparseVoxelOctree, &Transform)>,
+    octree_query: Query<(&SparseVoxelOctree, &GlobalTransform)>,
 ) {
     for (octree, octree_tf) in octree_query.iter() {
         // The root node covers [-size/2..+size/2], so half_size is:
-        let half_size = octree.size * 0.5;
+        let center = octree.center.as_vec3();
+        let size = octree.size as f32;
 
         // Draw a translucent cuboid for the root
         gizmos.cuboid(
-            Transform::from_translation(octree.center).with_scale(Vec3::splat(octree.size)),
+            octree_tf.mul_transform(
+                Transform::from_translation(center).with_scale(Vec3::splat(size)),
+            ),
             Color::srgba(1.0, 1.0, 0.0, 0.15),
         );
 
@@ -21,9 +25,10 @@ pub fn visualize_octree_system(
         // Start from depth=0. The node at depth=0 has bounding side = octree.size.
         visualize_recursive_center(
             &mut gizmos,
+            octree_tf,
             &octree.root,
-            octree.center, // center of root in world
-            octree.size,
+            center, // center of root in world
+            size,
             0,
             octree.max_depth,
         );
@@ -36,6 +41,7 @@ pub fn visualize_octree_system(
 /// i=1 => (+x,-y,-z), i=2 => (-x,+y,-z), etc.
 fn visualize_recursive_center(
     gizmos: &mut Gizmos,
+    volume_tf: &GlobalTransform,
     node: &OctreeNode,
     parent_center: Vec3,
     parent_size: f32,
@@ -60,13 +66,16 @@ fn visualize_recursive_center(
 
             // Draw the child bounding box
             gizmos.cuboid(
-                Transform::from_translation(child_center).with_scale(Vec3::splat(child_size)),
+                volume_tf.mul_transform(
+                    Transform::from_translation(child_center).with_scale(Vec3::splat(child_size)),
+                ),
                 Color::srgba(0.5, 1.0, 0.5, 0.15), // greenish
             );
 
             // Recurse
             visualize_recursive_center(
                 gizmos,
+                volume_tf,
                 child,
                 child_center,
                 child_size,
@@ -84,7 +93,10 @@ fn visualize_recursive_center(
 
                 // Draw a small cuboid at the same center as the parent node.
                 gizmos.cuboid(
-                    Transform::from_translation(parent_center).with_scale(Vec3::splat(leaf_size)),
+                    volume_tf.mul_transform(
+                        Transform::from_translation(parent_center)
+                            .with_scale(Vec3::splat(leaf_size)),
+                    ),
                     Color::WHITE,
                 );
             }
@@ -104,12 +116,12 @@ pub fn draw_grid(
     let camera_pos = camera_tf.translation;
 
     for (octree, _octree_tf) in octree_query.iter() {
-        let half_size = octree.size * 0.5;
-        let root_center = octree.center;
+        let half_size = octree.size as f32 * 0.5;
+        let root_center = octree.center.as_vec3();
 
         // Voxel spacing at max depth
-        let spacing = octree.get_spacing_at_depth(octree.max_depth);
-        let grid_count = (octree.size / spacing) as i32;
+        let spacing = octree.get_spacing_at_depth(octree.max_depth) as f32;
+        let grid_count = (octree.size as f32 / spacing) as i32;
 
         // We'll define the bounding region as [center-half_size .. center+half_size].
         // So the min corner is (root_center - half_size).
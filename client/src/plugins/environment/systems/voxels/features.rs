@@ -0,0 +1,377 @@
+use crate::plugins::environment::systems::voxels::structure::*;
+use bevy::math::DVec3;
+use bevy::prelude::*;
+use noise::{NoiseFn, Perlin};
+use rayon::prelude::*;
+
+/// Horizontal radius (in voxels) that no prefab may exceed. Every feature is
+/// kept inside its own placement cell, so features never overlap and the result
+/// does not depend on which chunk is decorated first.
+pub const FEATURE_MAX_RADIUS: i32 = 2;
+
+/// Coarse climate classification deciding which features may spawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Biome {
+    Plains,
+    Forest,
+    Desert,
+    Mountains,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FeatureKind {
+    Tree,
+    Boulder,
+    Pillar,
+}
+
+/// When and how often a feature is placed on a surface.
+#[derive(Debug, Clone)]
+pub struct FeatureRule {
+    pub kind: FeatureKind,
+    pub biomes: Vec<Biome>,
+    /// Steepest terrain (in degrees) the feature may stand on.
+    pub max_slope_deg: f32,
+    /// Probability that a placement cell spawns this feature.
+    pub chance: f32,
+}
+
+/// Settings for the feature placement stage.
+#[derive(Debug, Clone)]
+pub struct FeatureConfig {
+    pub seed: u32,
+    /// Edge length of a placement cell in voxels. At most one feature per cell.
+    pub cell_size: i32,
+    /// Frequency of the climate noise used to pick biomes.
+    pub biome_frequency: f64,
+    /// Surfaces above this voxel height always count as mountains.
+    pub mountain_height: i32,
+    /// Rules are tried in order, the first one that matches wins.
+    pub rules: Vec<FeatureRule>,
+}
+
+impl Default for FeatureConfig {
+    fn default() -> Self {
+        Self {
+            seed: 1337,
+            cell_size: 12,
+            biome_frequency: 0.004,
+            mountain_height: 400,
+            rules: vec![
+                FeatureRule {
+                    kind: FeatureKind::Tree,
+                    biomes: vec![Biome::Forest],
+                    max_slope_deg: 35.0,
+                    chance: 0.7,
+                },
+                FeatureRule {
+                    kind: FeatureKind::Tree,
+                    biomes: vec![Biome::Plains],
+                    max_slope_deg: 30.0,
+                    chance: 0.15,
+                },
+                FeatureRule {
+                    kind: FeatureKind::Boulder,
+                    biomes: vec![Biome::Plains, Biome::Mountains, Biome::Forest],
+                    max_slope_deg: 60.0,
+                    chance: 0.2,
+                },
+                FeatureRule {
+                    kind: FeatureKind::Pillar,
+                    biomes: vec![Biome::Desert],
+                    max_slope_deg: 20.0,
+                    chance: 0.25,
+                },
+            ],
+        }
+    }
+}
+
+impl Prefab {
+    /// Build the prefab for `kind`. `hash` picks the size variation so the same
+    /// anchor always produces the same model.
+    pub fn generate(kind: FeatureKind, hash: u32) -> Self {
+        let mut voxels = Vec::new();
+        match kind {
+            FeatureKind::Tree => {
+                let trunk = Voxel::new([1; 6]);
+                let leaves = Voxel::new([2; 6]);
+                let height = 4 + (hash % 3) as i32;
+                for y in 0..height {
+                    voxels.push((IVec3::new(0, y, 0), trunk));
+                }
+                let r = FEATURE_MAX_RADIUS;
+                for x in -r..=r {
+                    for y in -r..=r {
+                        for z in -r..=r {
+                            if x * x + y * y + z * z > r * r || (x == 0 && z == 0 && y < 0) {
+                                continue;
+                            }
+                            voxels.push((IVec3::new(x, height + y, z), leaves));
+                        }
+                    }
+                }
+            }
+            FeatureKind::Boulder => {
+                let stone = Voxel::new([3; 6]);
+                let r = 1 + (hash % FEATURE_MAX_RADIUS as u32) as i32;
+                for x in -r..=r {
+                    for y in 0..=r {
+                        for z in -r..=r {
+                            if x * x + y * y * 2 + z * z <= r * r + 1 {
+                                voxels.push((IVec3::new(x, y, z), stone));
+                            }
+                        }
+                    }
+                }
+            }
+            FeatureKind::Pillar => {
+                let sandstone = Voxel::new([4; 6]);
+                let height = 5 + (hash % 5) as i32;
+                let width = 1 + (hash >> 8) as i32 % 2;
+                for x in 0..width {
+                    for z in 0..width {
+                        for y in 0..height {
+                            voxels.push((IVec3::new(x, y, z), sandstone));
+                        }
+                    }
+                }
+            }
+        }
+        Self { voxels }
+    }
+
+    /// Height of the prefab in voxels above its anchor.
+    pub fn height(&self) -> i32 {
+        self.voxels.iter().map(|(p, _)| p.y + 1).max().unwrap_or(0)
+    }
+}
+
+/// Decorate the given chunks and insert every feature with one batched insert.
+///
+/// Placement is planned against the terrain first and only then written, so
+/// the outcome is the same for any chunk order.
+pub fn place_features(tree: &mut SparseVoxelOctree, keys: &[ChunkKey], cfg: &FeatureConfig) {
+    let climate = Perlin::new(cfg.seed);
+    let mut planned: Vec<(ChunkKey, Vec<(DVec3, Voxel)>)> = keys
+        .par_iter()
+        .map(|key| (*key, plan_chunk_features(tree, *key, cfg, &climate)))
+        .collect();
+    planned.sort_by_key(|(k, _)| (k.0, k.1, k.2));
+
+    let voxels: Vec<(DVec3, Voxel)> = planned.into_iter().flat_map(|(_, v)| v).collect();
+    if !voxels.is_empty() {
+        info!("placing {} feature voxels", voxels.len());
+        tree.insert_batch(&voxels);
+    }
+}
+
+/// Work out which features belong to the chunk `key` without touching the tree.
+/// A feature belongs to the chunk that contains its anchor surface voxel.
+pub fn plan_chunk_features(
+    tree: &SparseVoxelOctree,
+    key: ChunkKey,
+    cfg: &FeatureConfig,
+    climate: &Perlin,
+) -> Vec<(DVec3, Voxel)> {
+    let step = tree.get_spacing_at_depth(tree.max_depth);
+    let origin = tree.chunk_origin_world(key);
+    let min = tree.world_to_voxel(origin + DVec3::splat(step * 0.5));
+    let max = min + IVec3::splat(CHUNK_SIZE - 1);
+
+    let filled = |p: IVec3| tree.get_voxel_at_world_coords(tree.voxel_to_world(p)).is_some();
+
+    let cs = cfg.cell_size;
+    let span = (cs - 2 * FEATURE_MAX_RADIUS).max(1) as u32;
+    let mut out = Vec::new();
+
+    for cx in min.x.div_euclid(cs)..=max.x.div_euclid(cs) {
+        for cz in min.z.div_euclid(cs)..=max.z.div_euclid(cs) {
+            // jittered anchor that keeps the whole footprint inside the cell
+            let cell_hash = hash3(cfg.seed, cx, 0, cz);
+            let ax = cx * cs + FEATURE_MAX_RADIUS + (cell_hash % span) as i32;
+            let az = cz * cs + FEATURE_MAX_RADIUS + ((cell_hash >> 16) % span) as i32;
+            if ax < min.x || ax > max.x || az < min.z || az > max.z {
+                continue;
+            }
+
+            // topmost surface of this column inside the chunk
+            let Some(ay) = (min.y..=max.y)
+                .rev()
+                .find(|&y| filled(IVec3::new(ax, y, az)) && !filled(IVec3::new(ax, y + 1, az)))
+            else {
+                continue;
+            };
+
+            let slope = surface_slope_deg(&filled, IVec3::new(ax, ay, az));
+            let biome = biome_at(climate, cfg, ax, ay, az);
+            let anchor_hash = hash3(cfg.seed ^ 0x9E37_79B9, ax, ay, az);
+
+            let rule = cfg.rules.iter().enumerate().find(|(i, rule)| {
+                let roll = hash3(anchor_hash, *i as i32, 0, 0) as f32 / u32::MAX as f32;
+                rule.biomes.contains(&biome) && slope <= rule.max_slope_deg && roll < rule.chance
+            });
+            let Some((_, rule)) = rule else {
+                continue;
+            };
+
+            let prefab = Prefab::generate(rule.kind, anchor_hash);
+            let base = IVec3::new(ax, ay + 1, az);
+            let clear = (0..prefab.height()).all(|y| !filled(base + IVec3::new(0, y, 0)));
+            if !clear {
+                continue;
+            }
+
+            for (offset, voxel) in &prefab.voxels {
+                let p = base + *offset;
+                if !filled(p) {
+                    out.push((tree.voxel_to_world(p), *voxel));
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Estimate the terrain slope at a surface voxel from central differences of
+/// the surface heights `RADIUS` voxels away on either side along x and z.
+fn surface_slope_deg(filled: &impl Fn(IVec3) -> bool, surface: IVec3) -> f32 {
+    const RADIUS: i32 = 4;
+    const SEARCH: i32 = 4 * RADIUS;
+    // surface height of a column relative to `surface`; a column without a
+    // surface in reach counts as a cliff, up when it is buried and down otherwise
+    let height = |dx: i32, dz: i32| {
+        let column = IVec3::new(surface.x + dx, surface.y, surface.z + dz);
+        (-SEARCH..=SEARCH)
+            .rev()
+            .find(|dy| {
+                let p = column.with_y(surface.y + dy);
+                filled(p) && !filled(p + IVec3::Y)
+            })
+            .unwrap_or(if filled(column) { SEARCH + 1 } else { -SEARCH - 1 })
+    };
+    let gx = (height(RADIUS, 0) - height(-RADIUS, 0)) as f32 / (2 * RADIUS) as f32;
+    let gz = (height(0, RADIUS) - height(0, -RADIUS)) as f32 / (2 * RADIUS) as f32;
+    gx.hypot(gz).atan().to_degrees()
+}
+
+/// Pick the biome at a voxel position from two climate noise channels.
+fn biome_at(climate: &Perlin, cfg: &FeatureConfig, x: i32, y: i32, z: i32) -> Biome {
+    if y > cfg.mountain_height {
+        return Biome::Mountains;
+    }
+    let f = cfg.biome_frequency;
+    let temperature = climate.get([x as f64 * f, z as f64 * f]);
+    let moisture = climate.get([x as f64 * f + 1000.0, z as f64 * f - 1000.0]);
+    if temperature > 0.25 && moisture < 0.0 {
+        Biome::Desert
+    } else if moisture > 0.1 {
+        Biome::Forest
+    } else {
+        Biome::Plains
+    }
+}
+
+/// Small integer hash used for deterministic placement decisions.
+pub fn hash3(seed: u32, x: i32, y: i32, z: i32) -> u32 {
+    let mut h = seed
+        ^ (x as u32).wrapping_mul(0x8DA6_B343)
+        ^ (y as u32).wrapping_mul(0xD816_3841)
+        ^ (z as u32).wrapping_mul(0xCB1A_B31F);
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7FEB_352D);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x846C_A68B);
+    h ^= h >> 16;
+    h
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Flat ground at voxel height 0, spanning several chunks.
+    fn flat_tree() -> SparseVoxelOctree {
+        let mut tree = SparseVoxelOctree::new(6, 64.0, false, false, false);
+        let ground: Vec<(DVec3, Voxel)> = (-24..24)
+            .flat_map(|x| (-24..24).map(move |z| IVec3::new(x, 0, z)))
+            .map(|p| (tree.voxel_to_world(p), Voxel::new([0; 6])))
+            .collect();
+        tree.insert_batch(&ground);
+        tree
+    }
+
+    /// Trees everywhere, one per 5×5 cell, so anchors are fixed at 5c + 2.
+    fn dense_trees(seed: u32) -> FeatureConfig {
+        FeatureConfig {
+            seed,
+            cell_size: 2 * FEATURE_MAX_RADIUS + 1,
+            rules: vec![FeatureRule {
+                kind: FeatureKind::Tree,
+                biomes: vec![Biome::Plains, Biome::Forest, Biome::Desert, Biome::Mountains],
+                max_slope_deg: 90.0,
+                chance: 1.0,
+            }],
+            ..FeatureConfig::default()
+        }
+    }
+
+    fn voxels(tree: &SparseVoxelOctree) -> Vec<(IVec3, Voxel)> {
+        let half = DVec3::splat(tree.size * 0.5);
+        let mut voxels: Vec<(IVec3, Voxel)> = tree
+            .collect_voxels_in_region(tree.center - half, tree.center + half)
+            .into_iter()
+            .map(|(pos, voxel)| (tree.world_to_voxel(pos), voxel))
+            .collect();
+        voxels.sort_by_key(|(p, _)| (p.x, p.y, p.z));
+        voxels
+    }
+
+    #[test]
+    fn placement_is_deterministic_in_any_chunk_order() {
+        let cfg = dense_trees(7);
+        let ground = flat_tree();
+        let mut keys: Vec<ChunkKey> = ground.occupied_chunks.iter().copied().collect();
+        keys.sort_by_key(|k| (k.0, k.1, k.2));
+
+        let mut first = ground.clone();
+        place_features(&mut first, &keys, &cfg);
+        let mut again = ground.clone();
+        place_features(&mut again, &keys, &cfg);
+        keys.reverse();
+        let mut reversed = ground.clone();
+        place_features(&mut reversed, &keys, &cfg);
+
+        assert!(voxels(&first).len() > voxels(&ground).len());
+        assert_eq!(voxels(&first), voxels(&again));
+        assert_eq!(voxels(&first), voxels(&reversed));
+    }
+
+    #[test]
+    fn features_cross_chunk_borders() {
+        let cfg = dense_trees(7);
+        let tree = flat_tree();
+        // anchor at voxel x = -18 sits in the last column but one of its chunk,
+        // its leaves reach x = -16 in the next chunk
+        let anchor = tree.voxel_to_world(IVec3::new(-18, 0, -18));
+        let key = tree.world_to_chunk(anchor);
+        assert_ne!(tree.world_to_chunk(tree.voxel_to_world(IVec3::new(-16, 0, -18))), key);
+
+        let planned = plan_chunk_features(&tree, key, &cfg, &Perlin::new(cfg.seed));
+        let neighbour = ChunkKey(key.0 + 1, key.1, key.2);
+        assert!(planned.iter().any(|(pos, _)| tree.world_to_chunk(*pos) == neighbour));
+    }
+
+    #[test]
+    fn slope_follows_the_terrain() {
+        let flat = |p: IVec3| p.y <= 0;
+        assert_eq!(surface_slope_deg(&flat, IVec3::ZERO), 0.0);
+
+        let ramp = |p: IVec3| p.y <= p.x;
+        assert!((surface_slope_deg(&ramp, IVec3::ZERO) - 45.0).abs() < 0.01);
+
+        let gentle = |p: IVec3| p.y <= p.x.div_euclid(2);
+        let slope = surface_slope_deg(&gentle, IVec3::ZERO);
+        assert!(slope > 20.0 && slope < 35.0, "{slope}");
+    }
+}
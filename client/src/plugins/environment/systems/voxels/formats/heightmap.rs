@@ -0,0 +1,369 @@
+use crate::plugins::environment::systems::voxels::structure::*;
+use bevy::math::DVec3;
+use bevy::prelude::*;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+
+/// Sample layout of a headerless heightmap file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RawSampleFormat {
+    U8,
+    U16LittleEndian,
+    U16BigEndian,
+}
+
+/// A grid of height samples normalised to `0.0..=1.0`, stored row by row.
+#[derive(Debug, Clone)]
+pub struct Heightmap {
+    pub width: usize,
+    pub height: usize,
+    pub samples: Vec<f32>,
+}
+
+impl Heightmap {
+    /// Load a heightmap, choosing the decoder from the file extension.
+    /// `.png` files are decoded directly; `.r16` files are read as square
+    /// little-endian 16-bit maps and `.r8` as square 8-bit maps. Headerless
+    /// maps of any other shape go through [`Heightmap::load_raw`]; `.raw` is
+    /// left to dense volumes.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let ext = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        match ext.as_deref() {
+            Some("png") => Self::load_png(path),
+            Some("r16") => {
+                let side = square_side(std::fs::metadata(path)?.len() / 2)?;
+                Self::load_raw(path, side, side, RawSampleFormat::U16LittleEndian)
+            }
+            Some("r8") => {
+                let side = square_side(std::fs::metadata(path)?.len())?;
+                Self::load_raw(path, side, side, RawSampleFormat::U8)
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unsupported heightmap file: {}", path.display()),
+            )),
+        }
+    }
+
+    /// Decode an 8- or 16-bit PNG. Colour images use their first channel.
+    pub fn load_png<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut decoder = png::Decoder::new(BufReader::new(File::open(path)?));
+        decoder.set_transformations(png::Transformations::EXPAND);
+        let mut reader = decoder
+            .read_info()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let mut buf = vec![0u8; reader.output_buffer_size()];
+        let info = reader
+            .next_frame(&mut buf)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        let channels = info.color_type.samples();
+        let wide = info.bit_depth == png::BitDepth::Sixteen;
+        let bytes_per_pixel = channels * if wide { 2 } else { 1 };
+        let (width, height) = (info.width as usize, info.height as usize);
+
+        let mut samples = Vec::with_capacity(width * height);
+        for row in buf[..info.buffer_size()].chunks_exact(info.line_size) {
+            for px in row[..width * bytes_per_pixel].chunks_exact(bytes_per_pixel) {
+                samples.push(if wide {
+                    u16::from_be_bytes([px[0], px[1]]) as f32 / u16::MAX as f32
+                } else {
+                    px[0] as f32 / u8::MAX as f32
+                });
+            }
+        }
+
+        Ok(Self {
+            width,
+            height,
+            samples,
+        })
+    }
+
+    /// Read a headerless heightmap with known dimensions.
+    pub fn load_raw<P: AsRef<Path>>(
+        path: P,
+        width: usize,
+        height: usize,
+        format: RawSampleFormat,
+    ) -> io::Result<Self> {
+        let bytes = std::fs::read(path)?;
+        let sample_size = if format == RawSampleFormat::U8 { 1 } else { 2 };
+        if bytes.len() < width * height * sample_size {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "raw heightmap has {} bytes, expected {}",
+                    bytes.len(),
+                    width * height * sample_size
+                ),
+            ));
+        }
+
+        let samples = bytes
+            .chunks_exact(sample_size)
+            .take(width * height)
+            .map(|s| match format {
+                RawSampleFormat::U8 => s[0] as f32 / u8::MAX as f32,
+                RawSampleFormat::U16LittleEndian => {
+                    u16::from_le_bytes([s[0], s[1]]) as f32 / u16::MAX as f32
+                }
+                RawSampleFormat::U16BigEndian => {
+                    u16::from_be_bytes([s[0], s[1]]) as f32 / u16::MAX as f32
+                }
+            })
+            .collect();
+
+        Ok(Self {
+            width,
+            height,
+            samples,
+        })
+    }
+
+    /// Normalised sample at pixel `(x, z)`, clamped to the map edges.
+    pub fn sample(&self, x: i32, z: i32) -> f32 {
+        let x = x.clamp(0, self.width as i32 - 1) as usize;
+        let z = z.clamp(0, self.height as i32 - 1) as usize;
+        self.samples[z * self.width + x]
+    }
+}
+
+fn square_side(samples: u64) -> io::Result<usize> {
+    let side = (samples as f64).sqrt() as u64;
+    if side * side != samples {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "raw heightmap is not square, use Heightmap::load_raw with explicit dimensions",
+        ));
+    }
+    Ok(side as usize)
+}
+
+/// Picks the voxel for each cell of a column from its depth, height and slope.
+#[derive(Debug, Clone)]
+pub struct MaterialLayers {
+    /// Top voxel by surface height: the first band whose upper limit (world
+    /// units above `origin.y`) is not exceeded is used.
+    pub height_bands: Vec<(f32, Voxel)>,
+    /// Voxels directly below the top, down to `soil_depth` voxels.
+    pub soil: Voxel,
+    pub soil_depth: u32,
+    /// Everything deeper than the soil.
+    pub stone: Voxel,
+    /// Replaces top and soil on surfaces steeper than `cliff_slope_deg`.
+    pub cliff: Voxel,
+    pub cliff_slope_deg: f32,
+}
+
+impl Default for MaterialLayers {
+    fn default() -> Self {
+        Self {
+            height_bands: vec![
+                (8.0, Voxel::new([4; 6])),      // yellow sand near the bottom
+                (96.0, Voxel::new([2; 6])),     // green grass
+                (f32::MAX, Voxel::new([0; 6])), // red peaks
+            ],
+            soil: Voxel::new([5; 6]),
+            soil_depth: 3,
+            stone: Voxel::new([3; 6]),
+            cliff: Voxel::new([1; 6]),
+            cliff_slope_deg: 50.0,
+        }
+    }
+}
+
+impl MaterialLayers {
+    /// `depth` counts voxels below the column top (0 = surface voxel).
+    pub fn pick(&self, depth: u32, surface_height: f32, slope_deg: f32) -> Voxel {
+        if depth > self.soil_depth {
+            return self.stone;
+        }
+        if slope_deg > self.cliff_slope_deg {
+            return self.cliff;
+        }
+        if depth > 0 {
+            return self.soil;
+        }
+        self.height_bands
+            .iter()
+            .find(|(limit, _)| surface_height <= *limit)
+            .map(|(_, voxel)| *voxel)
+            .unwrap_or(self.soil)
+    }
+}
+
+/// How heightmap pixels are turned into voxel columns.
+#[derive(Debug, Clone)]
+pub struct HeightmapSettings {
+    /// World position of pixel `(0, 0)` at height zero.
+    pub origin: DVec3,
+    /// World height of a full-scale (white) sample.
+    pub vertical_scale: f32,
+    /// World size of one heightmap pixel and of one vertical layer. Rounded to
+    /// a whole number of octree voxels.
+    pub voxel_size: f32,
+    /// Only fill this many layers below the surface instead of the whole column.
+    pub fill_depth: Option<u32>,
+    /// Material layering; `None` uses `Voxel::random_sides` everywhere.
+    pub layers: Option<MaterialLayers>,
+}
+
+impl Default for HeightmapSettings {
+    fn default() -> Self {
+        Self {
+            origin: DVec3::ZERO,
+            vertical_scale: 256.0,
+            voxel_size: 1.0,
+            fill_depth: None,
+            layers: Some(MaterialLayers::default()),
+        }
+    }
+}
+
+/// Fill one voxel column per heightmap pixel into the octree.
+pub fn import_heightmap(
+    tree: &mut SparseVoxelOctree,
+    map: &Heightmap,
+    settings: &HeightmapSettings,
+) {
+    let step = tree.get_spacing_at_depth(tree.max_depth);
+    let sub = (settings.voxel_size as f64 / step).round().max(1.0) as i32;
+    let block = sub as f64 * step;
+
+    let height_at = |x: i32, z: i32| map.sample(x, z) * settings.vertical_scale;
+
+    // Insert row by row to keep the batch size bounded for large maps.
+    for pz in 0..map.height as i32 {
+        let mut row = Vec::new();
+        for px in 0..map.width as i32 {
+            let surface = height_at(px, pz);
+            let top = (surface as f64 / block).floor() as i32;
+
+            let dx = (height_at(px + 1, pz) - height_at(px - 1, pz)) * 0.5;
+            let dz = (height_at(px, pz + 1) - height_at(px, pz - 1)) * 0.5;
+            let slope_deg = ((dx * dx + dz * dz).sqrt() / block as f32)
+                .atan()
+                .to_degrees();
+
+            let bottom = match settings.fill_depth {
+                Some(depth) => top - depth as i32 + 1,
+                None => 0,
+            };
+
+            for layer in bottom..=top {
+                let voxel = match &settings.layers {
+                    Some(layers) => layers.pick((top - layer) as u32, surface, slope_deg),
+                    None => Voxel::random_sides(),
+                };
+                let corner = settings.origin + IVec3::new(px, layer, pz).as_dvec3() * block;
+                for sx in 0..sub {
+                    for sy in 0..sub {
+                        for sz in 0..sub {
+                            let offset = IVec3::new(sx, sy, sz).as_dvec3() + 0.5;
+                            row.push((corner + offset * step, voxel));
+                        }
+                    }
+                }
+            }
+        }
+        tree.insert_batch(&row);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Write `bytes` to a file of its own in the temp directory.
+    fn temp_file(name: &str, bytes: &[u8]) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("{}-{name}", std::process::id()));
+        std::fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    #[test]
+    fn load_raw_reads_both_byte_orders() {
+        let path = temp_file("heightmap-u16.bin", &[0xff, 0xff, 0x00, 0x00, 0x80, 0x00]);
+        let little = Heightmap::load_raw(&path, 3, 1, RawSampleFormat::U16LittleEndian).unwrap();
+        let big = Heightmap::load_raw(&path, 3, 1, RawSampleFormat::U16BigEndian).unwrap();
+        let short = Heightmap::load_raw(&path, 2, 2, RawSampleFormat::U16LittleEndian);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(little.samples, vec![1.0, 0.0, 128.0 / 65535.0]);
+        assert_eq!(big.samples, vec![1.0, 0.0, 32768.0 / 65535.0]);
+        assert_eq!(short.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn load_picks_the_decoder_from_the_extension() {
+        let square = temp_file("heightmap.r8", &[0, 51, 102, 255]);
+        let oblong = temp_file("heightmap-oblong.r8", &[0; 6]);
+        let raw = temp_file("heightmap.raw", &[0; 4]);
+        let map = Heightmap::load(&square);
+        let oblong_err = Heightmap::load(&oblong).unwrap_err();
+        let raw_err = Heightmap::load(&raw).unwrap_err();
+        for path in [square, oblong, raw] {
+            std::fs::remove_file(path).unwrap();
+        }
+
+        let map = map.unwrap();
+        assert_eq!((map.width, map.height), (2, 2));
+        assert_eq!(map.samples, vec![0.0, 0.2, 0.4, 1.0]);
+        assert_eq!(oblong_err.kind(), io::ErrorKind::InvalidData);
+        // .raw files are dense volumes, not heightmaps
+        assert_eq!(raw_err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn sample_clamps_to_the_edges() {
+        let map = Heightmap { width: 2, height: 2, samples: vec![0.0, 0.25, 0.5, 1.0] };
+        assert_eq!(map.sample(1, 0), 0.25);
+        assert_eq!(map.sample(-5, 1), 0.5);
+        assert_eq!(map.sample(9, 9), 1.0);
+        assert!(square_side(16).is_ok_and(|side| side == 4));
+        assert!(square_side(15).is_err());
+    }
+
+    #[test]
+    fn layers_follow_depth_height_and_slope() {
+        let layers = MaterialLayers::default();
+        let (sand, grass, peak) =
+            (layers.height_bands[0].1, layers.height_bands[1].1, layers.height_bands[2].1);
+
+        assert_eq!(layers.pick(0, 8.0, 0.0), sand);
+        assert_eq!(layers.pick(0, 8.5, 0.0), grass);
+        assert_eq!(layers.pick(0, 500.0, 0.0), peak);
+        assert_eq!(layers.pick(1, 500.0, 0.0), layers.soil);
+        assert_eq!(layers.pick(layers.soil_depth, 50.0, 0.0), layers.soil);
+        assert_eq!(layers.pick(layers.soil_depth + 1, 50.0, 0.0), layers.stone);
+        // cliffs replace top and soil but not the stone underneath
+        assert_eq!(layers.pick(0, 50.0, 60.0), layers.cliff);
+        assert_eq!(layers.pick(2, 50.0, 60.0), layers.cliff);
+        assert_eq!(layers.pick(layers.soil_depth + 1, 50.0, 60.0), layers.stone);
+    }
+
+    #[test]
+    fn import_fills_columns_up_to_the_surface() {
+        let map = Heightmap { width: 2, height: 2, samples: vec![0.0, 0.5, 0.25, 1.0] };
+        let mut settings = HeightmapSettings { vertical_scale: 4.0, ..default() };
+
+        // column tops at 0, 2, 1 and 4
+        let mut tree = SparseVoxelOctree::new(6, 64.0, false, false, false);
+        import_heightmap(&mut tree, &map, &settings);
+        assert_eq!(tree.voxel_count, 1 + 3 + 2 + 5);
+        assert!(tree.get_voxel_at_world_coords(DVec3::new(1.5, 4.5, 1.5)).is_some());
+        assert!(tree.get_voxel_at_world_coords(DVec3::new(1.5, 5.5, 1.5)).is_none());
+
+        settings.fill_depth = Some(2);
+        let mut tree = SparseVoxelOctree::new(6, 64.0, false, false, false);
+        import_heightmap(&mut tree, &map, &settings);
+        assert_eq!(tree.voxel_count, 4 * 2);
+        assert!(tree.get_voxel_at_world_coords(DVec3::new(1.5, 2.5, 1.5)).is_none());
+    }
+}
@@ -0,0 +1,318 @@
+use crate::plugins::environment::systems::voxels::atlas::VoxelTextureAtlas;
+use crate::plugins::environment::systems::voxels::material::{
+    ATTRIBUTE_PACKED_VOXEL, FACE_NORMALS, PackedVertex,
+};
+use crate::plugins::environment::systems::voxels::meshing::mesh_chunk;
+use crate::plugins::environment::systems::voxels::structure::*;
+use bevy::math::DVec3;
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, VertexAttributeValues};
+use serde_json::json;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// CPU-meshed geometry of one chunk, in world space.
+pub struct ExportedChunk {
+    pub key: ChunkKey,
+    pub pass: MeshPass,
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub uvs: Vec<[f32; 2]>,
+    pub indices: Vec<u32>,
+}
+
+/// Mesh every occupied chunk overlapping `region` (or the whole world when
+/// `None`) with the CPU greedy mesher.
+///
+/// The renderer's packed chunk-local vertices are decoded to world space here.
+/// It tiles a texture array in voxel units; exports point every vertex at the
+/// centre of its tile in the flat atlas instead, which is exact for the
+/// solid-colour tiles.
+pub fn mesh_region(tree: &SparseVoxelOctree, region: Option<(DVec3, DVec3)>) -> Vec<ExportedChunk> {
+    let atlas = VoxelTextureAtlas::headless();
+    let mut pool = MeshBufferPool::default();
+    let step = tree.get_spacing_at_depth(tree.max_depth);
+    let chunk_world = CHUNK_SIZE as f64 * step;
+
+    let mut keys: Vec<ChunkKey> = tree
+        .occupied_chunks
+        .iter()
+        .copied()
+        .filter(|key| {
+            let Some((min, max)) = region else {
+                return true;
+            };
+            let origin = tree.chunk_origin_world(*key);
+            AABB {
+                min: origin,
+                max: origin + DVec3::splat(chunk_world),
+            }
+            .intersects_aabb(&AABB { min, max })
+        })
+        .collect();
+    keys.sort_by_key(|k| (k.0, k.1, k.2));
+
+    let mut out = Vec::new();
+    for key in keys {
+        let snapshot = tree.extract_chunk(key, 0);
+        let origin = tree.chunk_origin_world(key);
+        for pass in [MeshPass::Opaque, MeshPass::Transparent] {
+            let Some(mesh) = mesh_chunk(&snapshot, &mut pool, pass) else {
+                continue;
+            };
+
+            let (Some(VertexAttributeValues::Uint32(packed)), Some(Indices::U32(indices))) =
+                (mesh.attribute(ATTRIBUTE_PACKED_VOXEL), mesh.indices())
+            else {
+                continue;
+            };
+            let vertices: Vec<PackedVertex> = packed.iter().map(|p| PackedVertex::unpack(*p)).collect();
+
+            out.push(ExportedChunk {
+                key,
+                pass,
+                positions: vertices
+                    .iter()
+                    .map(|v| (origin + v.position.as_dvec3() * step).as_vec3().to_array())
+                    .collect(),
+                normals: vertices
+                    .iter()
+                    .map(|v| FACE_NORMALS[v.face as usize].to_array())
+                    .collect(),
+                uvs: vertices
+                    .iter()
+                    .map(|v| {
+                        let [[u0, v1], _, [u1, v0], _] = atlas.uv_rect(v.layer as usize);
+                        [(u0 + u1) * 0.5, (v0 + v1) * 0.5]
+                    })
+                    .collect(),
+                indices: indices.clone(),
+            });
+        }
+    }
+    out
+}
+
+/// Mesh a region and write it to `path`. The format follows the extension:
+/// `.gltf` (with a sibling `.bin`) or `.obj` (with a sibling `.mtl`). The atlas
+/// texture is written next to it as `<name>_atlas.png`.
+/// Returns the number of exported chunks.
+pub fn export_meshes<P: AsRef<Path>>(
+    tree: &SparseVoxelOctree,
+    region: Option<(DVec3, DVec3)>,
+    path: P,
+) -> io::Result<usize> {
+    let path = path.as_ref();
+    let chunks = mesh_region(tree, region);
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("gltf") => write_gltf(&chunks, path)?,
+        Some("obj") => write_obj(&chunks, path)?,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unsupported export format: {}", path.display()),
+            ));
+        }
+    }
+    Ok(chunks.len())
+}
+
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("export");
+    path.with_file_name(format!("{stem}{suffix}"))
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .and_then(|s| s.to_str())
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn chunk_name(chunk: &ExportedChunk) -> String {
+    let ChunkKey(x, y, z) = chunk.key;
+    match chunk.pass {
+        MeshPass::Opaque => format!("chunk_{x}_{y}_{z}"),
+        MeshPass::Transparent => format!("chunk_{x}_{y}_{z}_transparent"),
+    }
+}
+
+/// Write the procedural atlas as PNG.
+fn write_atlas_png(path: &Path) -> io::Result<()> {
+    let (width, height, data) = VoxelTextureAtlas::pixels();
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder
+        .write_header()
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    writer
+        .write_image_data(&data)
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+}
+
+/// glTF 2.0 with one node per chunk, an external `.bin` buffer and the atlas
+/// as nearest-filtered base colour texture.
+pub fn write_gltf(chunks: &[ExportedChunk], path: &Path) -> io::Result<()> {
+    const ARRAY_BUFFER: u32 = 34962;
+    const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+    const FLOAT: u32 = 5126;
+    const UNSIGNED_INT: u32 = 5125;
+
+    // glTF forbids empty buffers
+    if chunks.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "nothing to export, no chunk produced a mesh",
+        ));
+    }
+
+    let bin_path = sibling(path, ".bin");
+    let atlas_path = sibling(path, "_atlas.png");
+
+    let mut bin: Vec<u8> = Vec::new();
+    let mut views = Vec::new();
+    let mut accessors = Vec::new();
+    let mut meshes = Vec::new();
+    let mut nodes = Vec::new();
+
+    // Append a tightly packed view and return its accessor index.
+    let mut push = |bytes: &[u8], target: u32, accessor: serde_json::Value| -> usize {
+        views.push(json!({
+            "buffer": 0,
+            "byteOffset": bin.len(),
+            "byteLength": bytes.len(),
+            "target": target,
+        }));
+        bin.extend_from_slice(bytes);
+        let mut accessor = accessor;
+        accessor["bufferView"] = json!(views.len() - 1);
+        accessors.push(accessor);
+        accessors.len() - 1
+    };
+
+    for chunk in chunks {
+        let (min, max) = chunk.positions.iter().fold(
+            ([f32::MAX; 3], [f32::MIN; 3]),
+            |(mut lo, mut hi), p| {
+                for i in 0..3 {
+                    lo[i] = lo[i].min(p[i]);
+                    hi[i] = hi[i].max(p[i]);
+                }
+                (lo, hi)
+            },
+        );
+        let count = chunk.positions.len();
+
+        let position = push(
+            bytemuck::cast_slice(&chunk.positions),
+            ARRAY_BUFFER,
+            json!({ "componentType": FLOAT, "count": count, "type": "VEC3", "min": min, "max": max }),
+        );
+        let normal = push(
+            bytemuck::cast_slice(&chunk.normals),
+            ARRAY_BUFFER,
+            json!({ "componentType": FLOAT, "count": count, "type": "VEC3" }),
+        );
+        let uv = push(
+            bytemuck::cast_slice(&chunk.uvs),
+            ARRAY_BUFFER,
+            json!({ "componentType": FLOAT, "count": count, "type": "VEC2" }),
+        );
+        let indices = push(
+            bytemuck::cast_slice(&chunk.indices),
+            ELEMENT_ARRAY_BUFFER,
+            json!({ "componentType": UNSIGNED_INT, "count": chunk.indices.len(), "type": "SCALAR" }),
+        );
+
+        meshes.push(json!({
+            "primitives": [{
+                "attributes": { "POSITION": position, "NORMAL": normal, "TEXCOORD_0": uv },
+                "indices": indices,
+                "material": (chunk.pass == MeshPass::Transparent) as usize,
+            }]
+        }));
+        nodes.push(json!({ "mesh": meshes.len() - 1, "name": chunk_name(chunk) }));
+    }
+
+    let gltf = json!({
+        "asset": { "version": "2.0", "generator": "voxel-simulation" },
+        "scene": 0,
+        "scenes": [{ "nodes": (0..nodes.len()).collect::<Vec<_>>() }],
+        "nodes": nodes,
+        "meshes": meshes,
+        "accessors": accessors,
+        "bufferViews": views,
+        "buffers": [{ "uri": file_name(&bin_path), "byteLength": bin.len() }],
+        "images": [{ "uri": file_name(&atlas_path) }],
+        // 9728 = NEAREST, 33071 = CLAMP_TO_EDGE
+        "samplers": [{ "magFilter": 9728, "minFilter": 9728, "wrapS": 33071, "wrapT": 33071 }],
+        "textures": [{ "source": 0, "sampler": 0 }],
+        "materials": [{
+            "name": "voxel_atlas",
+            "pbrMetallicRoughness": {
+                "baseColorTexture": { "index": 0 },
+                "metallicFactor": 0.0,
+                "roughnessFactor": 1.0,
+            }
+        }, {
+            "name": "voxel_atlas_transparent",
+            "alphaMode": "BLEND",
+            "pbrMetallicRoughness": {
+                "baseColorTexture": { "index": 0 },
+                "metallicFactor": 0.0,
+                "roughnessFactor": 1.0,
+            }
+        }],
+    });
+
+    std::fs::write(&bin_path, &bin)?;
+    write_atlas_png(&atlas_path)?;
+    let file = BufWriter::new(File::create(path)?);
+    serde_json::to_writer_pretty(file, &gltf).map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+}
+
+/// Wavefront OBJ with one object per chunk and an `.mtl` using the atlas.
+pub fn write_obj(chunks: &[ExportedChunk], path: &Path) -> io::Result<()> {
+    let mtl_path = sibling(path, ".mtl");
+    let atlas_path = sibling(path, "_atlas.png");
+
+    let mut mtl = BufWriter::new(File::create(&mtl_path)?);
+    writeln!(mtl, "newmtl voxel_atlas")?;
+    writeln!(mtl, "Kd 1.0 1.0 1.0")?;
+    writeln!(mtl, "map_Kd {}", file_name(&atlas_path))?;
+    writeln!(mtl, "newmtl voxel_atlas_transparent")?;
+    writeln!(mtl, "Kd 1.0 1.0 1.0")?;
+    writeln!(mtl, "map_Kd {}", file_name(&atlas_path))?;
+    writeln!(mtl, "map_d {}", file_name(&atlas_path))?;
+    mtl.flush()?;
+    write_atlas_png(&atlas_path)?;
+
+    let mut obj = BufWriter::new(File::create(path)?);
+    writeln!(obj, "mtllib {}", file_name(&mtl_path))?;
+    let mut base = 1u32;
+    for chunk in chunks {
+        writeln!(obj, "o {}", chunk_name(chunk))?;
+        match chunk.pass {
+            MeshPass::Opaque => writeln!(obj, "usemtl voxel_atlas")?,
+            MeshPass::Transparent => writeln!(obj, "usemtl voxel_atlas_transparent")?,
+        }
+        for p in &chunk.positions {
+            writeln!(obj, "v {} {} {}", p[0], p[1], p[2])?;
+        }
+        for uv in &chunk.uvs {
+            // OBJ puts v = 0 at the bottom of the image
+            writeln!(obj, "vt {} {}", uv[0], 1.0 - uv[1])?;
+        }
+        for n in &chunk.normals {
+            writeln!(obj, "vn {} {} {}", n[0], n[1], n[2])?;
+        }
+        for tri in chunk.indices.chunks_exact(3) {
+            let [a, b, c] = [tri[0] + base, tri[1] + base, tri[2] + base];
+            writeln!(obj, "f {a}/{a}/{a} {b}/{b}/{b} {c}/{c}/{c}")?;
+        }
+        base += chunk.positions.len() as u32;
+    }
+    obj.flush()
+}
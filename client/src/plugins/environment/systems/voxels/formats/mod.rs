@@ -0,0 +1,7 @@
+pub mod heightmap;
+pub mod mesh_export;
+pub mod point_cloud;
+pub mod schematic;
+pub mod volume;
+pub mod vox;
+pub mod voxelize;
@@ -0,0 +1,369 @@
+use crate::plugins::environment::systems::voxels::atlas::{nearest_texture, ATLAS_COLORS};
+use crate::plugins::environment::systems::voxels::structure::*;
+use bevy::math::DVec3;
+use bevy::prelude::*;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+/// A single scanned point with an optional RGB colour.
+#[derive(Debug, Clone, Copy)]
+pub struct CloudPoint {
+    pub position: DVec3,
+    pub color: Option<[u8; 3]>,
+}
+
+/// Read `.ply` (ASCII or binary) or `.xyz` depending on the extension.
+pub fn load_point_cloud<P: AsRef<Path>>(path: P) -> io::Result<Vec<CloudPoint>> {
+    let path = path.as_ref();
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("ply") => load_ply(path),
+        Some("xyz") | Some("txt") => load_xyz(path),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("unsupported point cloud: {}", path.display()),
+        )),
+    }
+}
+
+/// `x y z [r g b]` per line. Colours may be 0‥255 integers or 0‥1 floats.
+pub fn load_xyz(path: &Path) -> io::Result<Vec<CloudPoint>> {
+    let mut points = Vec::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        let values: Vec<f64> = line
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|s| !s.is_empty())
+            .filter_map(|s| s.parse().ok())
+            .collect();
+        if values.len() < 3 {
+            continue;
+        }
+        let color = (values.len() >= 6).then(|| {
+            let unit = values[3..6].iter().all(|c| *c <= 1.0);
+            let to_u8 = |c: f64| if unit { (c * 255.0) as u8 } else { c as u8 };
+            [to_u8(values[3]), to_u8(values[4]), to_u8(values[5])]
+        });
+        points.push(CloudPoint {
+            position: DVec3::new(values[0], values[1], values[2]),
+            color,
+        });
+    }
+    Ok(points)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum PlyEncoding {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+/// Reads the `vertex` element of a PLY file. Other elements are skipped when
+/// they come after the vertices; properties other than position and colour
+/// are ignored.
+pub fn load_ply(path: &Path) -> io::Result<Vec<CloudPoint>> {
+    read_ply(BufReader::new(File::open(path)?))
+}
+
+/// [`load_ply`] from any buffered reader.
+pub fn read_ply(mut reader: impl BufRead) -> io::Result<Vec<CloudPoint>> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, format!("ply: {msg}"));
+
+    let mut encoding = PlyEncoding::Ascii;
+    let mut vertex_count = 0usize;
+    let mut in_vertex = false;
+    // (name, type) of every vertex property, in file order
+    let mut properties: Vec<(String, String)> = Vec::new();
+
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(invalid("missing end_header"));
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["format", "ascii", ..] => encoding = PlyEncoding::Ascii,
+            ["format", "binary_little_endian", ..] => encoding = PlyEncoding::BinaryLittleEndian,
+            ["format", "binary_big_endian", ..] => encoding = PlyEncoding::BinaryBigEndian,
+            ["element", name, count] => {
+                in_vertex = *name == "vertex";
+                if in_vertex {
+                    vertex_count = count.parse().map_err(|_| invalid("bad vertex count"))?;
+                }
+            }
+            ["property", "list", ..] if in_vertex => {
+                return Err(invalid("list properties on vertices are not supported"));
+            }
+            ["property", ty, name] if in_vertex => properties.push((name.to_string(), ty.to_string())),
+            ["end_header"] => break,
+            _ => {}
+        }
+    }
+
+    let find = |names: &[&str]| properties.iter().position(|(n, _)| names.contains(&n.as_str()));
+    let (Some(ix), Some(iy), Some(iz)) = (find(&["x"]), find(&["y"]), find(&["z"])) else {
+        return Err(invalid("vertex element has no x/y/z"));
+    };
+    let rgb = match (
+        find(&["red", "r", "diffuse_red"]),
+        find(&["green", "g", "diffuse_green"]),
+        find(&["blue", "b", "diffuse_blue"]),
+    ) {
+        (Some(r), Some(g), Some(b)) => Some([r, g, b]),
+        _ => None,
+    };
+
+    // the header count is untrusted, so the points are not preallocated
+    let mut points = Vec::new();
+    let mut values = vec![0f64; properties.len()];
+    for _ in 0..vertex_count {
+        if encoding == PlyEncoding::Ascii {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                return Err(invalid("fewer vertices than declared"));
+            }
+            let mut words = line.split_whitespace();
+            for value in values.iter_mut() {
+                let word = words.next().ok_or_else(|| invalid("missing vertex property"))?;
+                *value = word.parse().map_err(|_| invalid("bad ascii value"))?;
+            }
+        } else {
+            let big = encoding == PlyEncoding::BinaryBigEndian;
+            for (value, (_, ty)) in values.iter_mut().zip(&properties) {
+                *value = read_binary_scalar(&mut reader, ty, big)?;
+            }
+        }
+
+        let color = rgb.map(|channels| {
+            channels.map(|i| {
+                let ty = properties[i].1.as_str();
+                let v = values[i];
+                if ty.starts_with("float") || ty.starts_with("double") {
+                    (v.clamp(0.0, 1.0) * 255.0) as u8
+                } else if ty.contains("16") || ty.contains("short") {
+                    (v / 257.0) as u8
+                } else {
+                    v as u8
+                }
+            })
+        });
+        points.push(CloudPoint {
+            position: DVec3::new(values[ix], values[iy], values[iz]),
+            color,
+        });
+    }
+    Ok(points)
+}
+
+fn read_binary_scalar(reader: &mut impl Read, ty: &str, big: bool) -> io::Result<f64> {
+    macro_rules! read {
+        ($t:ty) => {{
+            let mut b = [0u8; std::mem::size_of::<$t>()];
+            reader.read_exact(&mut b)?;
+            (if big { <$t>::from_be_bytes(b) } else { <$t>::from_le_bytes(b) }) as f64
+        }};
+    }
+    Ok(match ty {
+        "char" | "int8" => read!(i8),
+        "uchar" | "uint8" => read!(u8),
+        "short" | "int16" => read!(i16),
+        "ushort" | "uint16" => read!(u16),
+        "int" | "int32" => read!(i32),
+        "uint" | "uint32" => read!(u32),
+        "float" | "float32" => read!(f32),
+        "double" | "float64" => read!(f64),
+        other => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("ply: unknown property type {other}"),
+            ));
+        }
+    })
+}
+
+/// Bin points into voxels at the octree's `max_depth` spacing. Each occupied
+/// voxel takes the atlas colour nearest to the average colour of its points,
+/// or `default` when the cloud has no colours.
+pub fn voxelize_points(
+    tree: &SparseVoxelOctree,
+    points: &[CloudPoint],
+    default: Voxel,
+) -> Vec<(DVec3, Voxel)> {
+    let mut bins: HashMap<IVec3, ([u32; 3], u32)> = HashMap::new();
+    for point in points {
+        let entry = bins.entry(tree.world_to_voxel(point.position)).or_default();
+        if let Some(c) = point.color {
+            for i in 0..3 {
+                entry.0[i] += c[i] as u32;
+            }
+            entry.1 += 1;
+        }
+    }
+
+    bins.into_iter()
+        .map(|(voxel, (sum, count))| {
+            let voxel_type = if count == 0 {
+                default
+            } else {
+                let avg = sum.map(|s| (s / count) as u8);
+                Voxel::new([nearest_texture(avg); 6])
+            };
+            (tree.voxel_to_world(voxel), voxel_type)
+        })
+        .collect()
+}
+
+/// Load a point cloud, offset it by `position` and insert it into the octree.
+/// Returns the number of voxels inserted.
+pub fn import_point_cloud<P: AsRef<Path>>(
+    tree: &mut SparseVoxelOctree,
+    path: P,
+    position: DVec3,
+    default: Voxel,
+) -> io::Result<usize> {
+    let mut points = load_point_cloud(path)?;
+    for point in &mut points {
+        point.position += position;
+    }
+    let voxels = voxelize_points(tree, &points, default);
+    tree.insert_batch(&voxels);
+    Ok(voxels.len())
+}
+
+/// Write every voxel centre from `traverse` as a binary little-endian PLY,
+/// coloured with the atlas colour of the voxel's top face.
+pub fn export_ply<P: AsRef<Path>>(tree: &SparseVoxelOctree, path: P) -> io::Result<usize> {
+    let voxels = tree.traverse();
+    let mut out = BufWriter::new(File::create(path)?);
+    write!(
+        out,
+        "ply\nformat binary_little_endian 1.0\ncomment voxel-simulation export\n\
+         element vertex {}\nproperty float x\nproperty float y\nproperty float z\n\
+         property uchar red\nproperty uchar green\nproperty uchar blue\nend_header\n",
+        voxels.len()
+    )?;
+    for (pos, _depth) in &voxels {
+        let texture = tree
+            .get_voxel_at_world_coords(*pos)
+            .map_or(0, |v| v.textures[3] % ATLAS_COLORS.len());
+        for c in pos.as_vec3().to_array() {
+            out.write_all(&c.to_le_bytes())?;
+        }
+        out.write_all(&ATLAS_COLORS[texture][..3])?;
+    }
+    out.flush()?;
+    Ok(voxels.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(format: &str, count: u64, properties: &[&str]) -> Vec<u8> {
+        let mut out = format!("ply\nformat {format} 1.0\nelement vertex {count}\n");
+        for property in properties {
+            out += &format!("property {property}\n");
+        }
+        out += "element face 0\nproperty list uchar int vertex_indices\nend_header\n";
+        out.into_bytes()
+    }
+
+    const XYZ_RGB: [&str; 7] = [
+        "float x",
+        "float y",
+        "float z",
+        "float intensity",
+        "uchar red",
+        "uchar green",
+        "uchar blue",
+    ];
+
+    #[test]
+    fn reads_ascii_ply() {
+        let mut ply = header("ascii", 2, &XYZ_RGB);
+        ply.extend(b"1.5 -2 3 0.7 255 128 0\n-4 5.25 6 0.1 0 0 255\n");
+        let points = read_ply(&ply[..]).unwrap();
+
+        assert_eq!(points.len(), 2);
+        assert_eq!(points[0].position, DVec3::new(1.5, -2.0, 3.0));
+        assert_eq!(points[0].color, Some([255, 128, 0]));
+        assert_eq!(points[1].position, DVec3::new(-4.0, 5.25, 6.0));
+        assert_eq!(points[1].color, Some([0, 0, 255]));
+    }
+
+    #[test]
+    fn reads_binary_ply_in_both_byte_orders() {
+        let properties = ["double x", "double y", "double z", "ushort r", "ushort g", "ushort b"];
+        for (format, big) in [("binary_little_endian", false), ("binary_big_endian", true)] {
+            let mut ply = header(format, 1, &properties);
+            for v in [1.0f64, 2.0, -3.0] {
+                ply.extend(if big { v.to_be_bytes() } else { v.to_le_bytes() });
+            }
+            for c in [65535u16, 257 * 10, 0] {
+                ply.extend(if big { c.to_be_bytes() } else { c.to_le_bytes() });
+            }
+            let points = read_ply(&ply[..]).unwrap();
+
+            assert_eq!(points.len(), 1, "{format}");
+            assert_eq!(points[0].position, DVec3::new(1.0, 2.0, -3.0), "{format}");
+            assert_eq!(points[0].color, Some([255, 10, 0]), "{format}");
+        }
+    }
+
+    #[test]
+    fn short_ascii_bodies_are_an_error() {
+        // fewer lines than declared
+        let mut ply = header("ascii", 3, &XYZ_RGB[..3]);
+        ply.extend(b"0 0 0\n1 1 1\n");
+        let err = read_ply(&ply[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // a line missing a property
+        let mut ply = header("ascii", 2, &XYZ_RGB[..3]);
+        ply.extend(b"0 0 0\n1 1\n");
+        let err = read_ply(&ply[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn huge_vertex_counts_fail_on_the_body() {
+        // nothing is allocated up front, so a lying header fails at the first
+        // missing vertex instead of on a multi-gigabyte allocation
+        let mut ply = header("ascii", u32::MAX as u64, &XYZ_RGB[..3]);
+        ply.extend(b"0 0 0\n");
+        assert!(read_ply(&ply[..]).is_err());
+
+        let mut ply = header("binary_little_endian", u32::MAX as u64, &XYZ_RGB[..3]);
+        ply.extend([0; 14]);
+        let err = read_ply(&ply[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn malformed_headers_are_an_error() {
+        assert!(read_ply(&b"ply\nformat ascii 1.0\nelement vertex 1\n"[..]).is_err());
+        assert!(read_ply(&header("ascii", 1, &["float x", "float y"])[..]).is_err());
+        assert!(read_ply(&b"ply\nelement vertex -1\nend_header\n"[..]).is_err());
+    }
+
+    #[test]
+    fn voxelize_averages_colours_per_voxel() {
+        let tree = SparseVoxelOctree::new(6, 64.0, false, false, false);
+        let red = ATLAS_COLORS[0];
+        let points = [
+            CloudPoint { position: DVec3::new(0.2, 0.2, 0.2), color: Some([red[0], red[1], 10]) },
+            CloudPoint { position: DVec3::new(0.8, 0.7, 0.9), color: Some([red[0], red[1], 0]) },
+            CloudPoint { position: DVec3::new(3.5, 0.5, 0.5), color: None },
+        ];
+        let default = Voxel::new([9; 6]);
+        let mut voxels = voxelize_points(&tree, &points, default);
+        voxels.sort_by(|a, b| a.0.x.total_cmp(&b.0.x));
+
+        assert_eq!(voxels.len(), 2);
+        assert_eq!(voxels[0], (DVec3::splat(0.5), Voxel::new([0; 6])));
+        assert_eq!(voxels[1], (DVec3::new(3.5, 0.5, 0.5), default));
+    }
+}
@@ -0,0 +1,627 @@
+use crate::plugins::environment::systems::voxels::structure::*;
+use bevy::math::DVec3;
+use bevy::prelude::*;
+use flate2::read::GzDecoder;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// Clockwise rotation about the Y axis applied while pasting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SchematicRotation {
+    #[default]
+    None,
+    Cw90,
+    Cw180,
+    Cw270,
+}
+
+impl SchematicRotation {
+    fn turns(self) -> u32 {
+        match self {
+            Self::None => 0,
+            Self::Cw90 => 1,
+            Self::Cw180 => 2,
+            Self::Cw270 => 3,
+        }
+    }
+
+    /// Rotate a horizontal offset, +X turning into +Z for one quarter turn.
+    fn apply(self, p: IVec3) -> IVec3 {
+        (0..self.turns()).fold(p, |p, _| IVec3::new(-p.z, p.y, p.x))
+    }
+
+    /// Move face textures along with the rotated block.
+    fn rotate_voxel(self, voxel: Voxel) -> Voxel {
+        // face order: left, right, bottom, top, back, front
+        const DIRS: [IVec3; 6] = [
+            IVec3::NEG_X,
+            IVec3::X,
+            IVec3::NEG_Y,
+            IVec3::Y,
+            IVec3::NEG_Z,
+            IVec3::Z,
+        ];
+        let mut textures = voxel.textures;
+        for (face, dir) in DIRS.iter().enumerate() {
+            let rotated = self.apply(*dir);
+            let target = DIRS.iter().position(|d| *d == rotated).unwrap();
+            textures[target] = voxel.textures[face];
+        }
+        Voxel::new(textures)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum BlockTextures {
+    All(usize),
+    Faces([usize; 6]),
+}
+
+#[derive(Debug, Deserialize)]
+struct BlockMappingFile {
+    placeholder: Option<usize>,
+    blocks: HashMap<String, BlockTextures>,
+}
+
+/// Maps Minecraft block states to voxels.
+///
+/// Lookups try the full state (`minecraft:oak_log[axis=y]`) first and then the
+/// bare block name (`minecraft:oak_log`). Unknown blocks become `placeholder`.
+#[derive(Debug, Clone)]
+pub struct BlockMapping {
+    pub entries: HashMap<String, Voxel>,
+    pub placeholder: Voxel,
+}
+
+impl Default for BlockMapping {
+    fn default() -> Self {
+        let all = |t: usize| Voxel::new([t; 6]);
+        let entries = [
+            ("minecraft:stone", all(3)),
+            ("minecraft:cobblestone", all(3)),
+            ("minecraft:dirt", all(5)),
+            ("minecraft:grass_block", Voxel::new([5, 5, 5, 2, 5, 5])),
+            ("minecraft:sand", all(4)),
+            ("minecraft:sandstone", all(4)),
+            ("minecraft:oak_log", Voxel::new([1, 1, 4, 4, 1, 1])),
+            ("minecraft:oak_planks", all(4)),
+            ("minecraft:oak_leaves", all(8)),
+            ("minecraft:glass", all(6)),
+            ("minecraft:water", all(7)),
+            ("minecraft:red_wool", all(0)),
+            ("minecraft:obsidian", all(1)),
+        ]
+        .into_iter()
+        .map(|(name, voxel)| (name.to_string(), voxel))
+        .collect();
+        Self {
+            entries,
+            placeholder: all(5),
+        }
+    }
+}
+
+impl BlockMapping {
+    /// Load a mapping table from TOML:
+    ///
+    /// ```toml
+    /// placeholder = 5
+    /// [blocks]
+    /// "minecraft:stone" = 3
+    /// "minecraft:grass_block" = [5, 5, 5, 2, 5, 5]
+    /// ```
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        let file: BlockMappingFile =
+            toml::from_str(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let to_voxel = |t: &BlockTextures| match t {
+            BlockTextures::All(t) => Voxel::new([*t; 6]),
+            BlockTextures::Faces(f) => Voxel::new(*f),
+        };
+        Ok(Self {
+            entries: file
+                .blocks
+                .iter()
+                .map(|(name, t)| (name.clone(), to_voxel(t)))
+                .collect(),
+            placeholder: Voxel::new([file.placeholder.unwrap_or(5); 6]),
+        })
+    }
+
+    /// `None` for air, otherwise the mapped voxel and whether it was known.
+    pub fn resolve(&self, state: &str) -> Option<(Voxel, bool)> {
+        let name = state.split('[').next().unwrap_or(state);
+        if matches!(
+            name,
+            "minecraft:air" | "minecraft:cave_air" | "minecraft:void_air" | "minecraft:structure_void"
+        ) {
+            return None;
+        }
+        Some(
+            self.entries
+                .get(state)
+                .or_else(|| self.entries.get(name))
+                .map(|v| (*v, true))
+                .unwrap_or((self.placeholder, false)),
+        )
+    }
+}
+
+/// Parsed Sponge schematic (versions 1–3).
+#[derive(Debug, Clone)]
+pub struct Schematic {
+    pub size: IVec3,
+    /// Palette index → block state string.
+    pub palette: Vec<String>,
+    /// Palette index per block, ordered `x + z * width + y * width * length`.
+    pub blocks: Vec<u32>,
+}
+
+/// What happened while pasting a schematic.
+#[derive(Debug, Clone, Default)]
+pub struct SchematicReport {
+    pub inserted: usize,
+    /// Block states that fell back to the placeholder, with their counts.
+    pub unknown: HashMap<String, usize>,
+}
+
+impl Schematic {
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::from_bytes(&std::fs::read(path)?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        let mut data = Vec::new();
+        if bytes.starts_with(&[0x1f, 0x8b]) {
+            GzDecoder::new(bytes).read_to_end(&mut data)?;
+        } else {
+            data.extend_from_slice(bytes);
+        }
+
+        let root = Nbt::read_root(&data)?;
+        // v3 wraps everything in a "Schematic" compound
+        let root = root.get("Schematic").unwrap_or(&root);
+        let blocks = root.get("Blocks").unwrap_or(root);
+
+        let dim = |key: &str| match root.get(key) {
+            Some(Nbt::Short(v)) => Ok(*v as u16 as i32),
+            _ => Err(invalid(&format!("missing {key}"))),
+        };
+        let size = IVec3::new(dim("Width")?, dim("Height")?, dim("Length")?);
+
+        let Some(Nbt::Compound(palette_tag)) = blocks.get("Palette") else {
+            return Err(invalid("missing Palette"));
+        };
+        // indices run below the declared PaletteMax, which cannot exceed the
+        // number of entries
+        let limit = match root.get("PaletteMax") {
+            Some(Nbt::Int(max)) => (*max).clamp(0, palette_tag.len() as i32) as usize,
+            _ => palette_tag.len(),
+        };
+        let mut palette = vec![String::new(); limit];
+        for (state, index) in palette_tag {
+            let Nbt::Int(index) = index else {
+                return Err(invalid("palette index is not an int"));
+            };
+            if *index < 0 || *index as usize >= limit {
+                return Err(invalid(&format!("palette index {index} out of range")));
+            }
+            palette[*index as usize] = state.clone();
+        }
+
+        let Some(Nbt::ByteArray(data)) = blocks.get("BlockData").or_else(|| blocks.get("Data"))
+        else {
+            return Err(invalid("missing BlockData"));
+        };
+
+        // every block takes at least one byte of BlockData
+        let volume = size.x as u64 * size.y as u64 * size.z as u64;
+        if volume > data.len() as u64 {
+            return Err(invalid("BlockData is shorter than the schematic volume"));
+        }
+
+        // Palette indices are stored as unsigned LEB128 varints.
+        let mut block_ids = Vec::with_capacity(volume as usize);
+        let (mut value, mut shift) = (0u32, 0u32);
+        for byte in data {
+            if shift >= 32 {
+                return Err(invalid("BlockData varint too long"));
+            }
+            value |= ((byte & 0x7f) as u32) << shift;
+            if byte & 0x80 == 0 {
+                block_ids.push(value);
+                value = 0;
+                shift = 0;
+            } else {
+                shift += 7;
+            }
+        }
+        if (block_ids.len() as u64) < volume {
+            return Err(invalid("BlockData is shorter than the schematic volume"));
+        }
+
+        Ok(Self {
+            size,
+            palette,
+            blocks: block_ids,
+        })
+    }
+
+    /// Convert to a prefab with the minimum corner at the anchor, rotated about
+    /// Y. Unknown block states are counted in `report`.
+    pub fn to_prefab(
+        &self,
+        mapping: &BlockMapping,
+        rotation: SchematicRotation,
+        report: &mut SchematicReport,
+    ) -> Prefab {
+        let (w, l) = (self.size.x, self.size.z);
+        let mut resolved: HashMap<u32, Option<(Voxel, bool)>> = HashMap::new();
+        let mut voxels = Vec::new();
+
+        // Rotating about the origin moves the footprint; shift it back so the
+        // minimum corner stays at the anchor.
+        let corners = [IVec3::ZERO, IVec3::new(w - 1, 0, l - 1)].map(|c| rotation.apply(c));
+        let shift = -corners[0].min(corners[1]);
+
+        let volume = w as usize * l as usize * self.size.y as usize;
+        for (i, id) in self.blocks.iter().enumerate().take(volume) {
+            let entry = *resolved.entry(*id).or_insert_with(|| {
+                let state = self.palette.get(*id as usize).map(String::as_str).unwrap_or("");
+                mapping.resolve(state)
+            });
+            let Some((voxel, known)) = entry else {
+                continue;
+            };
+            if !known {
+                let state = self.palette.get(*id as usize).cloned().unwrap_or_default();
+                *report.unknown.entry(state).or_default() += 1;
+            }
+
+            let i = i as i32;
+            let p = IVec3::new(i % w, i / (w * l), (i / w) % l);
+            voxels.push((rotation.apply(p) + shift, rotation.rotate_voxel(voxel)));
+        }
+        Prefab { voxels }
+    }
+}
+
+/// Paste a `.schem` file with its minimum corner at `position`.
+pub fn import_schematic<P: AsRef<Path>>(
+    tree: &mut SparseVoxelOctree,
+    path: P,
+    position: DVec3,
+    rotation: SchematicRotation,
+    mapping: &BlockMapping,
+) -> io::Result<SchematicReport> {
+    let schematic = Schematic::load(path)?;
+    let mut report = SchematicReport::default();
+    let prefab = schematic.to_prefab(mapping, rotation, &mut report);
+    tree.insert_prefab(position, &prefab);
+    report.inserted = prefab.voxels.len();
+
+    for (state, count) in &report.unknown {
+        warn!("schematic: unknown block {state} ({count}x) replaced by placeholder");
+    }
+    Ok(report)
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("schematic: {msg}"))
+}
+
+/// Minimal reader for Minecraft's big-endian NBT format. Every tag is decoded
+/// so the stream stays aligned, even the ones the importer never looks at.
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+enum Nbt {
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    ByteArray(Vec<u8>),
+    String(String),
+    List(Vec<Nbt>),
+    Compound(HashMap<String, Nbt>),
+    IntArray(Vec<i32>),
+    LongArray(Vec<i64>),
+}
+
+impl Nbt {
+    fn get(&self, key: &str) -> Option<&Nbt> {
+        match self {
+            Nbt::Compound(map) => map.get(key),
+            _ => None,
+        }
+    }
+
+    fn read_root(data: &[u8]) -> io::Result<Nbt> {
+        let mut r = NbtReader { data, pos: 0 };
+        if r.u8()? != 10 {
+            return Err(invalid("root tag is not a compound"));
+        }
+        let _name = r.string()?;
+        r.payload(10)
+    }
+}
+
+struct NbtReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> NbtReader<'a> {
+    fn bytes(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.data.len())
+            .ok_or_else(|| invalid("unexpected end of NBT data"))?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn take<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        Ok(self.bytes(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    fn i32(&mut self) -> io::Result<i32> {
+        Ok(i32::from_be_bytes(self.take()?))
+    }
+
+    fn len(&mut self) -> io::Result<usize> {
+        let len = self.i32()?;
+        if len < 0 || len as usize > self.data.len() {
+            return Err(invalid("bad NBT array length"));
+        }
+        Ok(len as usize)
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let len = u16::from_be_bytes(self.take()?) as usize;
+        Ok(String::from_utf8_lossy(self.bytes(len)?).into_owned())
+    }
+
+    fn payload(&mut self, tag: u8) -> io::Result<Nbt> {
+        Ok(match tag {
+            1 => Nbt::Byte(self.u8()? as i8),
+            2 => Nbt::Short(i16::from_be_bytes(self.take()?)),
+            3 => Nbt::Int(self.i32()?),
+            4 => Nbt::Long(i64::from_be_bytes(self.take()?)),
+            5 => Nbt::Float(f32::from_be_bytes(self.take()?)),
+            6 => Nbt::Double(f64::from_be_bytes(self.take()?)),
+            7 => {
+                let len = self.len()?;
+                Nbt::ByteArray(self.bytes(len)?.to_vec())
+            }
+            8 => Nbt::String(self.string()?),
+            9 => {
+                let item = self.u8()?;
+                let len = self.len()?;
+                Nbt::List((0..len).map(|_| self.payload(item)).collect::<io::Result<_>>()?)
+            }
+            10 => {
+                let mut map = HashMap::new();
+                loop {
+                    let child = self.u8()?;
+                    if child == 0 {
+                        break;
+                    }
+                    let name = self.string()?;
+                    map.insert(name, self.payload(child)?);
+                }
+                Nbt::Compound(map)
+            }
+            11 => {
+                let len = self.len()?;
+                Nbt::IntArray((0..len).map(|_| self.i32()).collect::<io::Result<_>>()?)
+            }
+            12 => {
+                let len = self.len()?;
+                Nbt::LongArray(
+                    (0..len)
+                        .map(|_| Ok(i64::from_be_bytes(self.take()?)))
+                        .collect::<io::Result<_>>()?,
+                )
+            }
+            other => return Err(invalid(&format!("unknown NBT tag {other}"))),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+
+    fn named(out: &mut Vec<u8>, tag: u8, name: &str) {
+        out.push(tag);
+        out.extend((name.len() as u16).to_be_bytes());
+        out.extend(name.as_bytes());
+    }
+
+    /// Palette compound and block data, under the v2 or v3 names.
+    fn blocks(out: &mut Vec<u8>, palette: &[(&str, i32)], data: &[u8], data_name: &str) {
+        named(out, 10, "Palette");
+        for (state, index) in palette {
+            named(out, 3, state);
+            out.extend(index.to_be_bytes());
+        }
+        out.push(0);
+        named(out, 7, data_name);
+        out.extend((data.len() as i32).to_be_bytes());
+        out.extend(data);
+    }
+
+    fn dims(out: &mut Vec<u8>, size: [i16; 3]) {
+        for (name, v) in ["Width", "Height", "Length"].into_iter().zip(size) {
+            named(out, 2, name);
+            out.extend(v.to_be_bytes());
+        }
+    }
+
+    fn sponge_v2(
+        size: [i16; 3],
+        palette: &[(&str, i32)],
+        max: Option<i32>,
+        data: &[u8],
+    ) -> Vec<u8> {
+        let mut out = Vec::new();
+        named(&mut out, 10, "Schematic");
+        named(&mut out, 3, "Version");
+        out.extend(2i32.to_be_bytes());
+        dims(&mut out, size);
+        if let Some(max) = max {
+            named(&mut out, 3, "PaletteMax");
+            out.extend(max.to_be_bytes());
+        }
+        blocks(&mut out, palette, data, "BlockData");
+        out.push(0);
+        out
+    }
+
+    fn sponge_v3(size: [i16; 3], palette: &[(&str, i32)], data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        named(&mut out, 10, "");
+        named(&mut out, 10, "Schematic");
+        named(&mut out, 3, "Version");
+        out.extend(3i32.to_be_bytes());
+        dims(&mut out, size);
+        named(&mut out, 10, "Blocks");
+        blocks(&mut out, palette, data, "Data");
+        out.extend([0, 0, 0]);
+        out
+    }
+
+    /// 2 × 1 × 3 with an unknown block, a log, stone and air.
+    const PALETTE: [(&str, i32); 4] = [
+        ("minecraft:air", 0),
+        ("minecraft:stone", 1),
+        ("minecraft:oak_log[axis=y]", 2),
+        ("mod:mystery", 3),
+    ];
+    const DATA: [u8; 6] = [1, 2, 0, 3, 1, 1];
+
+    fn sorted(mut voxels: Vec<(IVec3, Voxel)>) -> Vec<(IVec3, Voxel)> {
+        voxels.sort_by_key(|(p, _)| (p.x, p.y, p.z));
+        voxels
+    }
+
+    #[test]
+    fn reads_v2_v3_and_gzip() {
+        let v2 = sponge_v2([2, 1, 3], &PALETTE, Some(4), &DATA);
+        let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+        gz.write_all(&v2).unwrap();
+        let gz = gz.finish().unwrap();
+
+        for bytes in [v2, gz, sponge_v3([2, 1, 3], &PALETTE, &DATA)] {
+            let schematic = Schematic::from_bytes(&bytes).unwrap();
+            assert_eq!(schematic.size, IVec3::new(2, 1, 3));
+            assert_eq!(schematic.palette[2], "minecraft:oak_log[axis=y]");
+            assert_eq!(schematic.blocks, DATA.map(u32::from));
+
+            let mapping = BlockMapping::default();
+            let mut report = SchematicReport::default();
+            let prefab = schematic.to_prefab(&mapping, SchematicRotation::None, &mut report);
+            let stone = mapping.entries["minecraft:stone"];
+            let log = mapping.entries["minecraft:oak_log"];
+            let expected = vec![
+                (IVec3::new(0, 0, 0), stone),
+                (IVec3::new(1, 0, 0), log),
+                (IVec3::new(1, 0, 1), mapping.placeholder),
+                (IVec3::new(0, 0, 2), stone),
+                (IVec3::new(1, 0, 2), stone),
+            ];
+            assert_eq!(sorted(prefab.voxels), sorted(expected));
+            assert_eq!(report.unknown.get("mod:mystery"), Some(&1));
+        }
+    }
+
+    #[test]
+    fn rotation_keeps_the_footprint_at_the_anchor() {
+        use SchematicRotation::{Cw90, Cw180, Cw270};
+        let bytes = sponge_v2([2, 1, 3], &PALETTE, None, &DATA);
+        let schematic = Schematic::from_bytes(&bytes).unwrap();
+        let mapping = BlockMapping::default();
+        let mut report = SchematicReport::default();
+
+        for rotation in [Cw90, Cw180, Cw270] {
+            let prefab = schematic.to_prefab(&mapping, rotation, &mut report);
+            assert_eq!(prefab.voxels.len(), 5);
+            let (w, l) = if rotation == Cw180 { (2, 3) } else { (3, 2) };
+            for (p, _) in &prefab.voxels {
+                assert!((0..w).contains(&p.x) && p.y == 0 && (0..l).contains(&p.z), "{p}");
+            }
+        }
+
+        // one quarter turn moves +X to +Z: the log at (1, 0, 0) lands at
+        // (2, 0, 1) once the footprint is shifted back to the anchor
+        let prefab = schematic.to_prefab(&mapping, SchematicRotation::Cw90, &mut report);
+        let log = SchematicRotation::Cw90.rotate_voxel(mapping.entries["minecraft:oak_log"]);
+        assert!(prefab.voxels.contains(&(IVec3::new(2, 0, 1), log)));
+
+        let faces = Voxel::new([0, 1, 2, 3, 4, 5]);
+        assert_eq!(SchematicRotation::Cw90.rotate_voxel(faces), Voxel::new([5, 4, 2, 3, 0, 1]));
+        assert_eq!(SchematicRotation::Cw180.rotate_voxel(faces), Voxel::new([1, 0, 2, 3, 5, 4]));
+        assert_eq!(SchematicRotation::None.rotate_voxel(faces), faces);
+    }
+
+    #[test]
+    fn truncated_nbt_is_an_error() {
+        let bytes = sponge_v3([2, 1, 3], &PALETTE, &DATA);
+        for len in 0..bytes.len() {
+            assert!(Schematic::from_bytes(&bytes[..len]).is_err(), "{len} bytes");
+        }
+
+        // a byte array claiming more data than there is
+        let mut bytes = sponge_v2([1, 1, 1], &PALETTE[..1], None, &[0]);
+        let at = bytes.len() - 6;
+        bytes[at..at + 4].copy_from_slice(&i32::MAX.to_be_bytes());
+        assert!(Schematic::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn oversized_dimensions_are_an_error() {
+        // 65535³ blocks would overflow an i32 volume and cannot fit three bytes
+        let bytes = sponge_v2([-1, -1, -1], &PALETTE, None, &DATA[..3]);
+        let err = Schematic::from_bytes(&bytes).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let bytes = sponge_v2([2, 1, 3], &PALETTE, None, &DATA[..5]);
+        assert!(Schematic::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn bad_palette_indices_are_an_error() {
+        for index in [-1, 4, i32::MAX] {
+            let palette = [("minecraft:stone", 0), ("minecraft:dirt", index)];
+            let bytes = sponge_v2([1, 1, 1], &palette, None, &[0]);
+            assert!(Schematic::from_bytes(&bytes).is_err(), "index {index}");
+        }
+        // indices must stay below PaletteMax
+        let bytes = sponge_v2([1, 1, 1], &PALETTE, Some(3), &[0]);
+        assert!(Schematic::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn overlong_varints_are_an_error() {
+        let bytes = sponge_v2([1, 1, 1], &PALETTE, None, &[0xff; 6]);
+        assert!(Schematic::from_bytes(&bytes).is_err());
+
+        // 300 fits in two bytes
+        let bytes = sponge_v2([1, 1, 1], &PALETTE, None, &[0xac, 0x02]);
+        assert_eq!(Schematic::from_bytes(&bytes).unwrap().blocks, vec![300]);
+    }
+}
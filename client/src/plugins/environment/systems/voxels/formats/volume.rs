@@ -0,0 +1,268 @@
+use crate::plugins::environment::systems::voxels::structure::*;
+use bevy::math::DVec3;
+use bevy::prelude::*;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VolumeSampleType {
+    U8,
+    U16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endianness {
+    Little,
+    Big,
+}
+
+/// Layout of a headerless dense volume. Samples are stored with X varying
+/// fastest, then Y, then Z.
+#[derive(Debug, Clone, Copy)]
+pub struct VolumeDesc {
+    pub dims: UVec3,
+    pub sample_type: VolumeSampleType,
+    pub endianness: Endianness,
+    /// Bytes to skip before the first sample.
+    pub header_bytes: u64,
+}
+
+impl VolumeDesc {
+    fn sample_size(&self) -> usize {
+        match self.sample_type {
+            VolumeSampleType::U8 => 1,
+            VolumeSampleType::U16 => 2,
+        }
+    }
+}
+
+/// Maps raw sample values to voxels. The first range containing a value wins;
+/// values outside every range stay empty.
+#[derive(Debug, Clone, Default)]
+pub struct TransferFunction {
+    pub ranges: Vec<(u16, u16, Voxel)>,
+}
+
+impl TransferFunction {
+    /// Everything at or above `min` becomes `voxel`.
+    pub fn threshold(min: u16, voxel: Voxel) -> Self {
+        Self {
+            ranges: vec![(min, u16::MAX, voxel)],
+        }
+    }
+
+    pub fn with_range(mut self, min: u16, max: u16, voxel: Voxel) -> Self {
+        self.ranges.push((min, max, voxel));
+        self
+    }
+
+    pub fn map(&self, value: u16) -> Option<Voxel> {
+        self.ranges
+            .iter()
+            .find(|(min, max, _)| (*min..=*max).contains(&value))
+            .map(|(_, _, voxel)| *voxel)
+    }
+
+    /// Smallest value that maps to a voxel, used to skip empty bricks quickly.
+    fn lowest(&self) -> u16 {
+        self.ranges.iter().map(|(min, _, _)| *min).min().unwrap_or(u16::MAX)
+    }
+}
+
+/// Import a dense volume, logging progress every 10 %.
+pub fn import_volume<P: AsRef<Path>>(
+    tree: &mut SparseVoxelOctree,
+    path: P,
+    desc: &VolumeDesc,
+    transfer: &TransferFunction,
+    position: DVec3,
+) -> io::Result<usize> {
+    let mut last_decile = 0;
+    import_volume_with_progress(tree, path, desc, transfer, position, |done, total| {
+        let decile = done * 10 / total.max(1);
+        if decile > last_decile {
+            last_decile = decile;
+            info!("volume import: {}%", decile * 10);
+        }
+    })
+}
+
+/// Import a dense volume with its minimum corner at `position`.
+///
+/// The file is streamed one chunk-thick slab of Z slices at a time. Each slab
+/// is split into 16³ bricks and bricks without a single mapped sample are
+/// skipped before anything touches the octree. `progress` receives the number
+/// of processed and total Z slices. Returns the number of voxels inserted.
+pub fn import_volume_with_progress<P: AsRef<Path>>(
+    tree: &mut SparseVoxelOctree,
+    path: P,
+    desc: &VolumeDesc,
+    transfer: &TransferFunction,
+    position: DVec3,
+    mut progress: impl FnMut(u64, u64),
+) -> io::Result<usize> {
+    let mut file = BufReader::new(File::open(path)?);
+    let expected = desc.header_bytes
+        + desc.dims.x as u64 * desc.dims.y as u64 * desc.dims.z as u64 * desc.sample_size() as u64;
+    let actual = file.get_ref().metadata()?.len();
+    if actual < expected {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("volume has {actual} bytes, expected {expected}"),
+        ));
+    }
+    file.seek(SeekFrom::Start(desc.header_bytes))?;
+
+    let step = tree.get_spacing_at_depth(tree.max_depth);
+    let (dx, dy, dz) = (desc.dims.x as usize, desc.dims.y as usize, desc.dims.z as usize);
+    let brick = CHUNK_SIZE as usize;
+    let lowest = transfer.lowest();
+    let slice_bytes = dx * dy * desc.sample_size();
+
+    let mut raw = Vec::new();
+    let mut samples: Vec<u16> = Vec::new();
+    let mut inserted = 0;
+
+    for z0 in (0..dz).step_by(brick) {
+        let depth = brick.min(dz - z0);
+        raw.resize(slice_bytes * depth, 0);
+        file.read_exact(&mut raw)?;
+
+        samples.clear();
+        samples.extend(raw.chunks_exact(desc.sample_size()).map(|b| {
+            match (desc.sample_type, desc.endianness) {
+                (VolumeSampleType::U8, _) => b[0] as u16,
+                (VolumeSampleType::U16, Endianness::Little) => u16::from_le_bytes([b[0], b[1]]),
+                (VolumeSampleType::U16, Endianness::Big) => u16::from_be_bytes([b[0], b[1]]),
+            }
+        }));
+        let sample = |x: usize, y: usize, z: usize| samples[(z * dy + y) * dx + x];
+
+        let mut slab = Vec::new();
+        for y0 in (0..dy).step_by(brick) {
+            for x0 in (0..dx).step_by(brick) {
+                let (x1, y1) = ((x0 + brick).min(dx), (y0 + brick).min(dy));
+
+                let occupied = (0..depth).any(|z| {
+                    (y0..y1).any(|y| (x0..x1).any(|x| sample(x, y, z) >= lowest))
+                });
+                if !occupied {
+                    continue;
+                }
+
+                for z in 0..depth {
+                    for y in y0..y1 {
+                        for x in x0..x1 {
+                            if let Some(voxel) = transfer.map(sample(x, y, z)) {
+                                let offset =
+                                    DVec3::new(x as f64, y as f64, (z0 + z) as f64) + 0.5;
+                                slab.push((position + offset * step, voxel));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        inserted += slab.len();
+        tree.insert_batch(&slab);
+        progress((z0 + depth) as u64, dz as u64);
+    }
+
+    Ok(inserted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Write `bytes` to a file of its own in the temp directory.
+    fn temp_volume(name: &str, bytes: &[u8]) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("{name}-{}.raw", std::process::id()));
+        std::fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    #[test]
+    fn transfer_function_takes_the_first_matching_range() {
+        let bone = Voxel::new([1; 6]);
+        let tissue = Voxel::new([2; 6]);
+        let transfer = TransferFunction::threshold(100, bone)
+            .with_range(50, 99, tissue)
+            .with_range(0, 200, Voxel::new([3; 6]));
+
+        assert_eq!(transfer.map(99), Some(tissue));
+        assert_eq!(transfer.map(100), Some(bone));
+        assert_eq!(transfer.map(150), Some(bone));
+        assert_eq!(transfer.map(u16::MAX), Some(bone));
+        assert_eq!(transfer.map(20), Some(Voxel::new([3; 6])));
+        assert_eq!(transfer.lowest(), 0);
+
+        let threshold = TransferFunction::threshold(100, bone);
+        assert_eq!(threshold.map(99), None);
+        assert_eq!(threshold.lowest(), 100);
+        assert_eq!(TransferFunction::default().map(0), None);
+    }
+
+    #[test]
+    fn import_maps_samples_across_bricks() {
+        // 20 × 3 × 17 big-endian u16 samples behind a 4 byte header, so the
+        // volume spans two bricks along x and two slabs along z
+        let desc = VolumeDesc {
+            dims: UVec3::new(20, 3, 17),
+            sample_type: VolumeSampleType::U16,
+            endianness: Endianness::Big,
+            header_bytes: 4,
+        };
+        let solid = [IVec3::new(0, 0, 0), IVec3::new(17, 2, 3), IVec3::new(19, 1, 16)];
+        let mut bytes = vec![0xff; 4];
+        for z in 0..17 {
+            for y in 0..3 {
+                for x in 0..20 {
+                    let value: u16 = if solid.contains(&IVec3::new(x, y, z)) { 1000 } else { 999 };
+                    bytes.extend(value.to_be_bytes());
+                }
+            }
+        }
+        let path = temp_volume("import_maps_samples_across_bricks", &bytes);
+
+        let bone = Voxel::new([7; 6]);
+        let mut tree = SparseVoxelOctree::new(6, 64.0, false, false, false);
+        let position = DVec3::new(-10.0, 0.0, 0.0);
+        let inserted = import_volume(
+            &mut tree,
+            &path,
+            &desc,
+            &TransferFunction::threshold(1000, bone),
+            position,
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(inserted.unwrap(), solid.len());
+        assert_eq!(tree.voxel_count, solid.len() as u64);
+        for p in solid {
+            let world = position + p.as_dvec3() + 0.5;
+            assert_eq!(tree.get_voxel_at_world_coords(world), Some(&bone));
+        }
+    }
+
+    #[test]
+    fn truncated_volumes_are_an_error() {
+        let desc = VolumeDesc {
+            dims: UVec3::new(4, 4, 4),
+            sample_type: VolumeSampleType::U8,
+            endianness: Endianness::Little,
+            header_bytes: 0,
+        };
+        let path = temp_volume("truncated_volumes_are_an_error", &[255; 63]);
+
+        let mut tree = SparseVoxelOctree::new(6, 64.0, false, false, false);
+        let transfer = TransferFunction::threshold(1, Voxel::default());
+        let result = import_volume(&mut tree, &path, &desc, &transfer, DVec3::ZERO);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(tree.voxel_count, 0);
+    }
+}
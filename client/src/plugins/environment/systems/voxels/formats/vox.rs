@@ -0,0 +1,573 @@
+use crate::plugins::environment::systems::voxels::atlas::{nearest_texture, ATLAS_COLORS};
+use crate::plugins::environment::systems::voxels::structure::*;
+use bevy::math::DVec3;
+use bevy::prelude::*;
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+/// Largest model edge MagicaVoxel accepts.
+pub const VOX_MAX_MODEL_SIZE: i32 = 256;
+
+/// One model of a `.vox` file.
+#[derive(Debug, Clone)]
+pub struct VoxModel {
+    pub size: IVec3,
+    /// Position of the model center in scene space (MagicaVoxel `_t`).
+    pub translation: IVec3,
+    /// `(x, y, z, color index)` in MagicaVoxel's Z-up model space.
+    pub voxels: Vec<[u8; 4]>,
+}
+
+/// In-memory contents of a `.vox` file.
+#[derive(Debug, Clone)]
+pub struct VoxScene {
+    pub models: Vec<VoxModel>,
+    /// RGBA colour of palette index `i + 1` at position `i`.
+    pub palette: [[u8; 4]; 256],
+    /// `false` when the file had no `RGBA` chunk.
+    pub has_palette: bool,
+}
+
+/// Maps `.vox` palette indices to voxels. Unmapped indices fall back to the
+/// atlas texture closest to the palette colour.
+#[derive(Debug, Clone, Default)]
+pub struct VoxPaletteMapping {
+    pub entries: HashMap<u8, Voxel>,
+}
+
+impl VoxPaletteMapping {
+    pub fn resolve(&self, index: u8, scene: &VoxScene) -> Voxel {
+        if let Some(voxel) = self.entries.get(&index) {
+            return *voxel;
+        }
+        let texture = if scene.has_palette {
+            let [r, g, b, _] = scene.palette[index.wrapping_sub(1) as usize];
+            nearest_texture([r, g, b])
+        } else {
+            index as usize % ATLAS_COLORS.len()
+        };
+        Voxel::new([texture; 6])
+    }
+}
+
+/// Scene-space (Z up) to voxel-space (Y up) conversion, keeping handedness.
+fn vox_to_voxel(p: IVec3) -> IVec3 {
+    IVec3::new(p.x, p.z, -p.y)
+}
+
+fn voxel_to_vox(p: IVec3) -> IVec3 {
+    IVec3::new(p.x, -p.z, p.y)
+}
+
+impl VoxScene {
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::from_bytes(&std::fs::read(path)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        std::fs::write(path, self.to_bytes())
+    }
+
+    /// Flatten every model into a prefab in voxel space, using scene positions.
+    pub fn to_prefab(&self, mapping: &VoxPaletteMapping) -> Prefab {
+        let mut voxels = Vec::new();
+        for model in &self.models {
+            let min = model.translation - model.size / 2;
+            for [x, y, z, index] in &model.voxels {
+                let p = min + IVec3::new(*x as i32, *y as i32, *z as i32);
+                voxels.push((vox_to_voxel(p), mapping.resolve(*index, self)));
+            }
+        }
+        Prefab { voxels }
+    }
+
+    /// Build a scene from voxels in integer voxel coordinates. Regions larger
+    /// than 256³ are split into several models. The returned mapping restores
+    /// the original voxels when the scene is imported again. Fails when there
+    /// are more distinct voxels than palette entries.
+    pub fn from_voxels(voxels: &[(IVec3, Voxel)]) -> io::Result<(Self, VoxPaletteMapping)> {
+        let mut palette = [[0u8; 4]; 256];
+        let mut mapping = VoxPaletteMapping::default();
+        let mut lookup: HashMap<[usize; 6], u8> = HashMap::new();
+
+        let mut index_of = |voxel: &Voxel| -> io::Result<u8> {
+            if let Some(index) = lookup.get(&voxel.textures) {
+                return Ok(*index);
+            }
+            // sharing an entry would break the exact restore through `mapping`
+            if lookup.len() >= 255 {
+                return Err(invalid("more than 255 distinct voxels do not fit the palette"));
+            }
+            let top = voxel.textures[3] % ATLAS_COLORS.len();
+            let index = lookup.len() as u8 + 1;
+            palette[index as usize - 1] = ATLAS_COLORS[top];
+            lookup.insert(voxel.textures, index);
+            mapping.entries.insert(index, *voxel);
+            Ok(index)
+        };
+
+        let points: Vec<(IVec3, u8)> = voxels
+            .iter()
+            .map(|(p, v)| Ok((voxel_to_vox(*p), index_of(v)?)))
+            .collect::<io::Result<_>>()?;
+
+        let mut tiles: HashMap<IVec3, Vec<(IVec3, u8)>> = HashMap::new();
+        if let Some(min) = points.iter().map(|(p, _)| *p).reduce(IVec3::min) {
+            for (p, index) in &points {
+                let tile = (*p - min) / VOX_MAX_MODEL_SIZE;
+                tiles.entry(tile).or_default().push((*p - min - tile * VOX_MAX_MODEL_SIZE, *index));
+            }
+            let mut tile_keys: Vec<IVec3> = tiles.keys().copied().collect();
+            tile_keys.sort_by_key(|t| (t.z, t.y, t.x));
+
+            let models = tile_keys
+                .into_iter()
+                .map(|tile| {
+                    let local = &tiles[&tile];
+                    let size = local.iter().map(|(p, _)| *p).fold(IVec3::ZERO, IVec3::max)
+                        + IVec3::ONE;
+                    let origin = min + tile * VOX_MAX_MODEL_SIZE;
+                    VoxModel {
+                        size,
+                        translation: origin + size / 2,
+                        voxels: local
+                            .iter()
+                            .map(|(p, i)| [p.x as u8, p.y as u8, p.z as u8, *i])
+                            .collect(),
+                    }
+                })
+                .collect();
+
+            return Ok((
+                Self {
+                    models,
+                    palette,
+                    has_palette: true,
+                },
+                mapping,
+            ));
+        }
+
+        Ok((
+            Self {
+                models: Vec::new(),
+                palette,
+                has_palette: true,
+            },
+            mapping,
+        ))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        let mut r = Reader { bytes, pos: 0 };
+        if r.take(4)? != b"VOX " {
+            return Err(invalid("missing VOX header"));
+        }
+        let _version = r.i32()?;
+        if r.take(4)? != b"MAIN" {
+            return Err(invalid("missing MAIN chunk"));
+        }
+        let main_content = r.i32()? as usize;
+        let _main_children = r.i32()?;
+        r.take(main_content)?;
+
+        let mut sizes = Vec::new();
+        let mut models: Vec<VoxModel> = Vec::new();
+        let mut palette = [[0u8; 4]; 256];
+        let mut has_palette = false;
+        let mut nodes: HashMap<i32, SceneNode> = HashMap::new();
+
+        while r.pos < bytes.len() {
+            let id: [u8; 4] = r.take(4)?.try_into().unwrap();
+            let content = r.i32()? as usize;
+            let children = r.i32()? as usize;
+            let mut c = Reader {
+                bytes: r.take(content)?,
+                pos: 0,
+            };
+            r.take(children)?;
+
+            match &id {
+                b"SIZE" => {
+                    let size = IVec3::new(c.i32()?, c.i32()?, c.i32()?);
+                    if size.min_element() <= 0 {
+                        return Err(invalid("model size must be positive"));
+                    }
+                    sizes.push(size);
+                }
+                b"XYZI" => {
+                    let count = c.i32()?;
+                    // four bytes per voxel must fit the rest of the chunk
+                    if count < 0 || count as usize > (c.bytes.len() - c.pos) / 4 {
+                        return Err(invalid("voxel count exceeds the XYZI chunk"));
+                    }
+                    let count = count as usize;
+                    let mut voxels: Vec<[u8; 4]> = Vec::with_capacity(count);
+                    for _ in 0..count {
+                        voxels.push(c.take(4)?.try_into().unwrap());
+                    }
+                    let size = sizes.get(models.len()).copied().unwrap_or(IVec3::ONE);
+                    models.push(VoxModel {
+                        size,
+                        translation: size / 2,
+                        voxels,
+                    });
+                }
+                b"RGBA" => {
+                    for entry in palette.iter_mut() {
+                        *entry = c.take(4)?.try_into().unwrap();
+                    }
+                    has_palette = true;
+                }
+                b"nTRN" => {
+                    let node_id = c.i32()?;
+                    let _attributes = c.dict()?;
+                    let child = c.i32()?;
+                    let _reserved = c.i32()?;
+                    let _layer = c.i32()?;
+                    let frames = c.i32()?;
+                    let mut translation = IVec3::ZERO;
+                    for frame in 0..frames {
+                        let dict = c.dict()?;
+                        if frame == 0 {
+                            if let Some(t) = dict.get("_t") {
+                                let v: Vec<i32> =
+                                    t.split_whitespace().filter_map(|s| s.parse().ok()).collect();
+                                if v.len() == 3 {
+                                    translation = IVec3::new(v[0], v[1], v[2]);
+                                }
+                            }
+                            if dict.contains_key("_r") {
+                                warn!("vox: ignoring rotation on transform node {node_id}");
+                            }
+                        }
+                    }
+                    nodes.insert(node_id, SceneNode::Transform { child, translation });
+                }
+                b"nGRP" => {
+                    let node_id = c.i32()?;
+                    let _attributes = c.dict()?;
+                    let count = c.i32()?;
+                    let children = (0..count).map(|_| c.i32()).collect::<io::Result<_>>()?;
+                    nodes.insert(node_id, SceneNode::Group { children });
+                }
+                b"nSHP" => {
+                    let node_id = c.i32()?;
+                    let _attributes = c.dict()?;
+                    let count = c.i32()?;
+                    let mut shape_models = Vec::new();
+                    for _ in 0..count {
+                        shape_models.push(c.i32()? as usize);
+                        let _model_attributes = c.dict()?;
+                    }
+                    nodes.insert(node_id, SceneNode::Shape { models: shape_models });
+                }
+                _ => {}
+            }
+        }
+
+        // Resolve model positions through the scene graph, if there is one.
+        if nodes.contains_key(&0) {
+            let mut placed = Vec::new();
+            let mut stack = vec![(0, IVec3::ZERO)];
+            while let Some((id, offset)) = stack.pop() {
+                match nodes.get(&id) {
+                    Some(SceneNode::Transform { child, translation }) => {
+                        stack.push((*child, offset + *translation))
+                    }
+                    Some(SceneNode::Group { children }) => {
+                        stack.extend(children.iter().map(|c| (*c, offset)))
+                    }
+                    Some(SceneNode::Shape { models: ids }) => {
+                        for model in ids {
+                            if let Some(m) = models.get(*model) {
+                                placed.push(VoxModel {
+                                    translation: offset,
+                                    ..m.clone()
+                                });
+                            }
+                        }
+                    }
+                    None => {}
+                }
+            }
+            models = placed;
+        }
+
+        Ok(Self {
+            models,
+            palette,
+            has_palette,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut children = Vec::new();
+
+        for model in &self.models {
+            let mut size = Vec::new();
+            for v in model.size.to_array() {
+                size.extend_from_slice(&v.to_le_bytes());
+            }
+            write_chunk(&mut children, b"SIZE", &size);
+
+            let mut xyzi = Vec::with_capacity(4 + model.voxels.len() * 4);
+            xyzi.extend_from_slice(&(model.voxels.len() as i32).to_le_bytes());
+            for voxel in &model.voxels {
+                xyzi.extend_from_slice(voxel);
+            }
+            write_chunk(&mut children, b"XYZI", &xyzi);
+        }
+
+        // Scene graph: root transform -> group -> (transform -> shape) per model
+        let mut root = Vec::new();
+        write_i32s(&mut root, &[0]);
+        write_dict(&mut root, &[]);
+        write_i32s(&mut root, &[1, -1, -1, 1]);
+        write_dict(&mut root, &[]);
+        write_chunk(&mut children, b"nTRN", &root);
+
+        let mut group = Vec::new();
+        write_i32s(&mut group, &[1]);
+        write_dict(&mut group, &[]);
+        write_i32s(&mut group, &[self.models.len() as i32]);
+        for i in 0..self.models.len() as i32 {
+            write_i32s(&mut group, &[2 + i * 2]);
+        }
+        write_chunk(&mut children, b"nGRP", &group);
+
+        for (i, model) in self.models.iter().enumerate() {
+            let node = 2 + i as i32 * 2;
+            let t = model.translation;
+            let mut transform = Vec::new();
+            write_i32s(&mut transform, &[node]);
+            write_dict(&mut transform, &[]);
+            write_i32s(&mut transform, &[node + 1, -1, 0, 1]);
+            let t = format!("{} {} {}", t.x, t.y, t.z);
+            write_dict(&mut transform, &[("_t", t.as_str())]);
+            write_chunk(&mut children, b"nTRN", &transform);
+
+            let mut shape = Vec::new();
+            write_i32s(&mut shape, &[node + 1]);
+            write_dict(&mut shape, &[]);
+            write_i32s(&mut shape, &[1, i as i32]);
+            write_dict(&mut shape, &[]);
+            write_chunk(&mut children, b"nSHP", &shape);
+        }
+
+        let palette: Vec<u8> = self.palette.iter().flatten().copied().collect();
+        write_chunk(&mut children, b"RGBA", &palette);
+
+        let mut out = Vec::with_capacity(children.len() + 20);
+        out.extend_from_slice(b"VOX ");
+        out.extend_from_slice(&150i32.to_le_bytes());
+        out.extend_from_slice(b"MAIN");
+        out.extend_from_slice(&0i32.to_le_bytes());
+        out.extend_from_slice(&(children.len() as i32).to_le_bytes());
+        out.extend_from_slice(&children);
+        out
+    }
+}
+
+enum SceneNode {
+    Transform { child: i32, translation: IVec3 },
+    Group { children: Vec<i32> },
+    Shape { models: Vec<usize> },
+}
+
+/// Insert every model of a `.vox` file with its scene origin at `position`.
+/// Returns the number of voxels inserted.
+pub fn import_vox<P: AsRef<Path>>(
+    tree: &mut SparseVoxelOctree,
+    path: P,
+    position: DVec3,
+    mapping: &VoxPaletteMapping,
+) -> io::Result<usize> {
+    let prefab = VoxScene::load(path)?.to_prefab(mapping);
+    tree.insert_prefab(position, &prefab);
+    Ok(prefab.voxels.len())
+}
+
+/// Export every voxel between `min` and `max` (world space) to a `.vox` file.
+/// The returned mapping turns the file back into the exact same voxels; regions
+/// with more than 255 distinct voxels are rejected.
+pub fn export_vox_region<P: AsRef<Path>>(
+    tree: &SparseVoxelOctree,
+    min: DVec3,
+    max: DVec3,
+    path: P,
+) -> io::Result<VoxPaletteMapping> {
+    let voxels: Vec<(IVec3, Voxel)> = tree
+        .collect_voxels_in_region(min, max)
+        .into_iter()
+        .map(|(pos, voxel)| (tree.world_to_voxel(pos), voxel))
+        .collect();
+    let (scene, mapping) = VoxScene::from_voxels(&voxels)?;
+    scene.save(path)?;
+    Ok(mapping)
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("vox: {msg}"))
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        let end = self
+            .pos
+            .checked_add(n)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| invalid("unexpected end of file"))?;
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn i32(&mut self) -> io::Result<i32> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let len = self.i32()? as usize;
+        Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+
+    fn dict(&mut self) -> io::Result<HashMap<String, String>> {
+        let count = self.i32()?;
+        let mut dict = HashMap::new();
+        for _ in 0..count {
+            let key = self.string()?;
+            dict.insert(key, self.string()?);
+        }
+        Ok(dict)
+    }
+}
+
+fn write_chunk(out: &mut Vec<u8>, id: &[u8; 4], content: &[u8]) {
+    out.extend_from_slice(id);
+    out.extend_from_slice(&(content.len() as i32).to_le_bytes());
+    out.extend_from_slice(&0i32.to_le_bytes());
+    out.extend_from_slice(content);
+}
+
+fn write_i32s(out: &mut Vec<u8>, values: &[i32]) {
+    for v in values {
+        out.extend_from_slice(&v.to_le_bytes());
+    }
+}
+
+fn write_dict(out: &mut Vec<u8>, pairs: &[(&str, &str)]) {
+    write_i32s(out, &[pairs.len() as i32]);
+    for (key, value) in pairs {
+        for s in [key, value] {
+            write_i32s(out, &[s.len() as i32]);
+            out.extend_from_slice(s.as_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(mut voxels: Vec<(IVec3, Voxel)>) -> Vec<(IVec3, Voxel)> {
+        voxels.sort_by_key(|(p, _)| (p.x, p.y, p.z));
+        voxels
+    }
+
+    fn round_trip(voxels: &[(IVec3, Voxel)]) -> (VoxScene, VoxScene, VoxPaletteMapping) {
+        let (scene, mapping) = VoxScene::from_voxels(voxels).unwrap();
+        let decoded = VoxScene::from_bytes(&scene.to_bytes()).unwrap();
+        (scene, decoded, mapping)
+    }
+
+    #[test]
+    fn round_trip_restores_voxels_and_mapping() {
+        let voxels: Vec<(IVec3, Voxel)> = (0..4)
+            .flat_map(|x| (-2..2).flat_map(move |y| (0..3).map(move |z| IVec3::new(x, y, z))))
+            .enumerate()
+            .map(|(i, p)| (p, Voxel::new([i % 3, 1, 2, i % 5, 4, i % 2])))
+            .collect();
+        let (scene, decoded, mapping) = round_trip(&voxels);
+
+        assert_eq!(decoded.models.len(), 1);
+        assert_eq!(decoded.palette, scene.palette);
+        for (index, voxel) in &mapping.entries {
+            assert_eq!(mapping.resolve(*index, &decoded), *voxel);
+        }
+        assert_eq!(sorted(decoded.to_prefab(&mapping).voxels), sorted(voxels));
+    }
+
+    #[test]
+    fn round_trip_splits_large_regions_into_models() {
+        let stone = Voxel::new([2; 6]);
+        let grass = Voxel::new([0, 0, 1, 3, 0, 0]);
+        let voxels = vec![
+            (IVec3::new(0, 0, 0), stone),
+            (IVec3::new(300, 0, 0), grass),
+            (IVec3::new(0, 600, 0), stone),
+            (IVec3::new(-10, 5, 400), grass),
+            (IVec3::new(255, 255, 255), stone),
+        ];
+        let (scene, decoded, mapping) = round_trip(&voxels);
+
+        assert!(scene.models.len() > 1);
+        assert_eq!(decoded.models.len(), scene.models.len());
+        for model in &decoded.models {
+            assert!(model.size.max_element() <= VOX_MAX_MODEL_SIZE);
+        }
+        assert_eq!(sorted(decoded.to_prefab(&mapping).voxels), sorted(voxels));
+    }
+
+    #[test]
+    fn full_palette_is_an_error() {
+        let voxels: Vec<(IVec3, Voxel)> = (0..256)
+            .map(|i| (IVec3::new(i, 0, 0), Voxel::new([i as usize, 0, 0, 0, 0, 0])))
+            .collect();
+        assert!(VoxScene::from_voxels(&voxels).is_err());
+    }
+
+    #[test]
+    fn negative_lengths_are_an_error() {
+        let mut bytes = VoxScene::from_voxels(&[(IVec3::ZERO, Voxel::default())])
+            .unwrap()
+            .0
+            .to_bytes();
+        // content length of the first child chunk
+        bytes[24..28].copy_from_slice(&(-1i32).to_le_bytes());
+        assert!(VoxScene::from_bytes(&bytes).is_err());
+    }
+
+    /// Offset of the first chunk of type `id` in `bytes`.
+    fn chunk_at(bytes: &[u8], id: &[u8; 4]) -> usize {
+        bytes.windows(4).position(|w| w == id).unwrap()
+    }
+
+    #[test]
+    fn oversized_voxel_counts_are_an_error() {
+        let scene = VoxScene::from_voxels(&[(IVec3::ZERO, Voxel::default())]).unwrap().0;
+        for count in [-1i32, i32::MAX, 2] {
+            let mut bytes = scene.to_bytes();
+            let xyzi = chunk_at(&bytes, b"XYZI");
+            bytes[xyzi + 12..xyzi + 16].copy_from_slice(&count.to_le_bytes());
+            assert!(VoxScene::from_bytes(&bytes).is_err(), "count {count}");
+        }
+    }
+
+    #[test]
+    fn non_positive_sizes_are_an_error() {
+        let scene = VoxScene::from_voxels(&[(IVec3::ZERO, Voxel::default())]).unwrap().0;
+        for size in [0i32, -4] {
+            let mut bytes = scene.to_bytes();
+            let chunk = chunk_at(&bytes, b"SIZE");
+            bytes[chunk + 16..chunk + 20].copy_from_slice(&size.to_le_bytes());
+            assert!(VoxScene::from_bytes(&bytes).is_err(), "size {size}");
+        }
+    }
+}
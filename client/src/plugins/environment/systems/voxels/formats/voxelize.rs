@@ -0,0 +1,417 @@
+use crate::plugins::environment::systems::voxels::atlas::nearest_texture;
+use crate::plugins::environment::systems::voxels::structure::*;
+use bevy::math::DVec3;
+use bevy::prelude::*;
+use std::collections::VecDeque;
+use std::io;
+use std::path::Path;
+
+/// RGBA8 texture sampled while colouring voxels.
+#[derive(Debug, Clone)]
+pub struct TextureData {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+}
+
+impl TextureData {
+    /// Nearest-neighbour lookup with repeating UVs.
+    pub fn sample(&self, uv: Vec2) -> [u8; 3] {
+        let x = (uv.x.rem_euclid(1.0) * self.width as f32) as u32 % self.width;
+        let y = (uv.y.rem_euclid(1.0) * self.height as f32) as u32 % self.height;
+        let i = ((y * self.width + x) * 4) as usize;
+        [self.rgba[i], self.rgba[i + 1], self.rgba[i + 2]]
+    }
+
+    /// Expand 1–4 channel 8-bit pixels to RGBA8.
+    fn from_channels(width: u32, height: u32, channels: usize, pixels: &[u8]) -> Self {
+        let rgba = pixels
+            .chunks_exact(channels)
+            .flat_map(|px| match channels {
+                1 => [px[0], px[0], px[0], 255],
+                2 => [px[0], px[0], px[0], px[1]],
+                3 => [px[0], px[1], px[2], 255],
+                _ => [px[0], px[1], px[2], px[3]],
+            })
+            .collect();
+        Self {
+            width,
+            height,
+            rgba,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MeshMaterial {
+    pub color: [f32; 3],
+    pub texture: Option<TextureData>,
+}
+
+/// Triangle soup with optional UVs and per-triangle materials.
+#[derive(Debug, Clone, Default)]
+pub struct TriangleMesh {
+    pub positions: Vec<Vec3>,
+    pub uvs: Vec<Vec2>,
+    pub indices: Vec<u32>,
+    /// Material of each triangle (`indices.len() / 3` entries).
+    pub triangle_materials: Vec<Option<usize>>,
+    pub materials: Vec<MeshMaterial>,
+}
+
+impl TriangleMesh {
+    /// Load `.obj`, `.gltf` or `.glb` depending on the extension.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("obj") => Self::load_obj(path),
+            Some("gltf") | Some("glb") => Self::load_gltf(path),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unsupported mesh file: {}", path.display()),
+            )),
+        }
+    }
+
+    pub fn load_obj(path: &Path) -> io::Result<Self> {
+        let (models, materials) = tobj::load_obj(path, &tobj::GPU_LOAD_OPTIONS)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let dir = path.parent().unwrap_or(Path::new("."));
+
+        let mut mesh = Self::default();
+        for material in materials.unwrap_or_default() {
+            let texture = material
+                .diffuse_texture
+                .and_then(|name| load_png_texture(&dir.join(name)).ok());
+            mesh.materials.push(MeshMaterial {
+                color: material.diffuse.unwrap_or([1.0; 3]),
+                texture,
+            });
+        }
+
+        for model in models {
+            let m = &model.mesh;
+            let base = mesh.positions.len() as u32;
+            let count = m.positions.len() / 3;
+            mesh.positions.extend(
+                m.positions.chunks_exact(3).map(|p| Vec3::new(p[0], p[1], p[2])),
+            );
+            if m.texcoords.len() / 2 == count {
+                mesh.uvs.extend(
+                    // OBJ puts v = 0 at the bottom of the image
+                    m.texcoords.chunks_exact(2).map(|t| Vec2::new(t[0], 1.0 - t[1])),
+                );
+            } else {
+                mesh.uvs.extend(std::iter::repeat(Vec2::ZERO).take(count));
+            }
+            mesh.indices.extend(m.indices.iter().map(|i| i + base));
+            mesh.triangle_materials
+                .extend(std::iter::repeat(m.material_id).take(m.indices.len() / 3));
+        }
+        Ok(mesh)
+    }
+
+    pub fn load_gltf(path: &Path) -> io::Result<Self> {
+        let (document, buffers, images) =
+            gltf::import(path).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        let mut mesh = Self::default();
+        for material in document.materials() {
+            let pbr = material.pbr_metallic_roughness();
+            let [r, g, b, _] = pbr.base_color_factor();
+            let texture = pbr.base_color_texture().and_then(|info| {
+                let image = images.get(info.texture().source().index())?;
+                let channels = match image.format {
+                    gltf::image::Format::R8 => 1,
+                    gltf::image::Format::R8G8 => 2,
+                    gltf::image::Format::R8G8B8 => 3,
+                    gltf::image::Format::R8G8B8A8 => 4,
+                    _ => return None,
+                };
+                Some(TextureData::from_channels(
+                    image.width,
+                    image.height,
+                    channels,
+                    &image.pixels,
+                ))
+            });
+            mesh.materials.push(MeshMaterial {
+                color: [r, g, b],
+                texture,
+            });
+        }
+
+        let mut stack: Vec<(gltf::Node, Mat4)> = document
+            .default_scene()
+            .or_else(|| document.scenes().next())
+            .map(|scene| scene.nodes().map(|n| (n, Mat4::IDENTITY)).collect())
+            .unwrap_or_default();
+
+        while let Some((node, parent)) = stack.pop() {
+            let transform = parent * Mat4::from_cols_array_2d(&node.transform().matrix());
+            stack.extend(node.children().map(|c| (c, transform)));
+
+            let Some(node_mesh) = node.mesh() else {
+                continue;
+            };
+            for primitive in node_mesh.primitives() {
+                if primitive.mode() != gltf::mesh::Mode::Triangles {
+                    continue;
+                }
+                let reader = primitive.reader(|b| Some(&buffers[b.index()]));
+                let Some(positions) = reader.read_positions() else {
+                    continue;
+                };
+
+                let base = mesh.positions.len() as u32;
+                mesh.positions.extend(
+                    positions.map(|p| transform.transform_point3(Vec3::from_array(p))),
+                );
+                let count = mesh.positions.len() - base as usize;
+                match reader.read_tex_coords(0) {
+                    Some(uvs) => mesh.uvs.extend(uvs.into_f32().map(Vec2::from_array)),
+                    None => mesh.uvs.extend(std::iter::repeat(Vec2::ZERO).take(count)),
+                }
+
+                let indices: Vec<u32> = match reader.read_indices() {
+                    Some(indices) => indices.into_u32().collect(),
+                    None => (0..count as u32).collect(),
+                };
+                mesh.triangle_materials.extend(
+                    std::iter::repeat(primitive.material().index()).take(indices.len() / 3),
+                );
+                mesh.indices.extend(indices.into_iter().map(|i| i + base));
+            }
+        }
+        Ok(mesh)
+    }
+
+    fn triangle(&self, t: usize) -> [Vec3; 3] {
+        let i = &self.indices[t * 3..t * 3 + 3];
+        [
+            self.positions[i[0] as usize],
+            self.positions[i[1] as usize],
+            self.positions[i[2] as usize],
+        ]
+    }
+
+    /// Colour of triangle `t` at the barycentric point `bary`.
+    fn color_at(&self, t: usize, bary: Vec3) -> [u8; 3] {
+        let Some(material) = self.triangle_materials[t].and_then(|m| self.materials.get(m)) else {
+            return [255; 3];
+        };
+        let to_u8 = |c: f32| (c.clamp(0.0, 1.0) * 255.0) as u8;
+        let [r, g, b] = material.color;
+        let Some(texture) = &material.texture else {
+            return [to_u8(r), to_u8(g), to_u8(b)];
+        };
+        let i = &self.indices[t * 3..t * 3 + 3];
+        let uv = self.uvs[i[0] as usize] * bary.x
+            + self.uvs[i[1] as usize] * bary.y
+            + self.uvs[i[2] as usize] * bary.z;
+        let [tr, tg, tb] = texture.sample(uv);
+        [
+            (tr as f32 * r) as u8,
+            (tg as f32 * g) as u8,
+            (tb as f32 * b) as u8,
+        ]
+    }
+}
+
+/// How voxels produced by the voxelizer are coloured.
+#[derive(Debug, Clone, Copy)]
+pub enum VoxelFill {
+    /// Every voxel uses the same block.
+    Block(Voxel),
+    /// Surface voxels take the atlas colour closest to the mesh colour at
+    /// that point; interior voxels use the given block.
+    SampleTexture { interior: Voxel },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct VoxelizeSettings {
+    /// Number of voxels along the longest axis of the mesh bounds.
+    pub resolution: u32,
+    /// Flood fill from the outside and fill whatever is enclosed.
+    pub fill_interior: bool,
+    pub fill: VoxelFill,
+}
+
+impl Default for VoxelizeSettings {
+    fn default() -> Self {
+        Self {
+            resolution: 64,
+            fill_interior: true,
+            fill: VoxelFill::SampleTexture {
+                interior: Voxel::new([3; 6]),
+            },
+        }
+    }
+}
+
+const EMPTY: u8 = 0;
+const SURFACE: u8 = 1;
+const OUTSIDE: u8 = 2;
+
+/// Voxelize a triangle mesh into a prefab whose minimum corner is `(0, 0, 0)`.
+pub fn voxelize(mesh: &TriangleMesh, settings: &VoxelizeSettings) -> Prefab {
+    if mesh.indices.len() < 3 {
+        return Prefab::default();
+    }
+
+    let (min, max) = mesh
+        .positions
+        .iter()
+        .fold((Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)), |(lo, hi), p| {
+            (lo.min(*p), hi.max(*p))
+        });
+    let scale = settings.resolution.max(1) as f32 / (max - min).max_element().max(f32::EPSILON);
+
+    // One voxel of padding on every side so the flood fill can go around.
+    let dims = ((max - min) * scale).ceil().as_ivec3() + IVec3::splat(3);
+    let index = |p: IVec3| (p.x * dims.y * dims.z + p.y * dims.z + p.z) as usize;
+    let mut state = vec![EMPTY; (dims.x * dims.y * dims.z) as usize];
+    let mut surface: Vec<(IVec3, Voxel)> = Vec::new();
+
+    let to_grid = |p: Vec3| (p - min) * scale + Vec3::ONE;
+    for t in 0..mesh.indices.len() / 3 {
+        let tri = mesh.triangle(t).map(to_grid);
+        let lo = tri[0].min(tri[1]).min(tri[2]).floor().as_ivec3().max(IVec3::ZERO);
+        let hi = tri[0].max(tri[1]).max(tri[2]).floor().as_ivec3().min(dims - 1);
+
+        for x in lo.x..=hi.x {
+            for y in lo.y..=hi.y {
+                for z in lo.z..=hi.z {
+                    let p = IVec3::new(x, y, z);
+                    let center = p.as_vec3() + 0.5;
+                    if state[index(p)] == SURFACE || !tri_box_overlap(center, Vec3::splat(0.5), tri)
+                    {
+                        continue;
+                    }
+                    state[index(p)] = SURFACE;
+                    let voxel = match settings.fill {
+                        VoxelFill::Block(voxel) => voxel,
+                        VoxelFill::SampleTexture { .. } => {
+                            let color = mesh.color_at(t, closest_barycentric(center, tri));
+                            Voxel::new([nearest_texture(color); 6])
+                        }
+                    };
+                    surface.push((p - IVec3::ONE, voxel));
+                }
+            }
+        }
+    }
+
+    let mut voxels = surface;
+    if settings.fill_interior {
+        let interior = match settings.fill {
+            VoxelFill::Block(voxel) => voxel,
+            VoxelFill::SampleTexture { interior } => interior,
+        };
+
+        let mut queue = VecDeque::from([IVec3::ZERO]);
+        state[0] = OUTSIDE;
+        while let Some(p) = queue.pop_front() {
+            for offset in [IVec3::X, IVec3::NEG_X, IVec3::Y, IVec3::NEG_Y, IVec3::Z, IVec3::NEG_Z] {
+                let n = p + offset;
+                if n.cmplt(IVec3::ZERO).any() || n.cmpge(dims).any() {
+                    continue;
+                }
+                if state[index(n)] == EMPTY {
+                    state[index(n)] = OUTSIDE;
+                    queue.push_back(n);
+                }
+            }
+        }
+
+        for x in 0..dims.x {
+            for y in 0..dims.y {
+                for z in 0..dims.z {
+                    let p = IVec3::new(x, y, z);
+                    if state[index(p)] == EMPTY {
+                        voxels.push((p - IVec3::ONE, interior));
+                    }
+                }
+            }
+        }
+    }
+
+    Prefab { voxels }
+}
+
+/// Load and voxelize a mesh file, inserting it with its minimum corner at
+/// `position`. Returns the number of voxels inserted.
+pub fn import_mesh<P: AsRef<Path>>(
+    tree: &mut SparseVoxelOctree,
+    path: P,
+    position: DVec3,
+    settings: &VoxelizeSettings,
+) -> io::Result<usize> {
+    let mesh = TriangleMesh::load(path)?;
+    let prefab = voxelize(&mesh, settings);
+    tree.insert_prefab(position, &prefab);
+    Ok(prefab.voxels.len())
+}
+
+fn load_png_texture(path: &Path) -> io::Result<TextureData> {
+    let mut decoder = png::Decoder::new(io::BufReader::new(std::fs::File::open(path)?));
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder
+        .read_info()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let mut buf = vec![0u8; reader.output_buffer_size()];
+    let info = reader
+        .next_frame(&mut buf)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    buf.truncate(info.buffer_size());
+    Ok(TextureData::from_channels(
+        info.width,
+        info.height,
+        info.color_type.samples(),
+        &buf,
+    ))
+}
+
+/// Barycentric coordinates of the point on the triangle closest to `p`.
+fn closest_barycentric(p: Vec3, [a, b, c]: [Vec3; 3]) -> Vec3 {
+    let n = (b - a).cross(c - a);
+    let area = n.length_squared();
+    if area < f32::EPSILON {
+        return Vec3::splat(1.0 / 3.0);
+    }
+    let q = p - n * (p - a).dot(n) / area;
+    let u = (c - b).cross(q - b).dot(n) / area;
+    let v = (a - c).cross(q - c).dot(n) / area;
+    let w = Vec3::new(u, v, 1.0 - u - v).max(Vec3::ZERO);
+    w / w.element_sum().max(f32::EPSILON)
+}
+
+/// Separating axis test between a triangle and an axis-aligned box
+/// (Akenine-Möller).
+fn tri_box_overlap(center: Vec3, half: Vec3, tri: [Vec3; 3]) -> bool {
+    let v = tri.map(|p| p - center);
+    let edges = [v[1] - v[0], v[2] - v[1], v[0] - v[2]];
+
+    let separated = |axis: Vec3| {
+        if axis.length_squared() < 1e-12 {
+            return false;
+        }
+        let p = v.map(|p| p.dot(axis));
+        let r = half.dot(axis.abs());
+        p[0].min(p[1]).min(p[2]) > r || p[0].max(p[1]).max(p[2]) < -r
+    };
+
+    for edge in edges {
+        for axis in [Vec3::X, Vec3::Y, Vec3::Z] {
+            if separated(axis.cross(edge)) {
+                return false;
+            }
+        }
+    }
+    for axis in [Vec3::X, Vec3::Y, Vec3::Z] {
+        if separated(axis) {
+            return false;
+        }
+    }
+    !separated(edges[0].cross(edges[1]))
+}
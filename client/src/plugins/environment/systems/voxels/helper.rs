@@ -1,4 +1,5 @@
 use crate::plugins::environment::systems::voxels::structure::*;
+use bevy::math::DVec3;
 use bevy::prelude::*;
 
 impl SparseVoxelOctree {
@@ -17,35 +18,35 @@ impl SparseVoxelOctree {
     }
 
     /// Returns the size of one voxel at the given depth.
-    pub fn get_spacing_at_depth(&self, depth: u32) -> f32 {
+    pub fn get_spacing_at_depth(&self, depth: u32) -> f64 {
         let effective = depth.min(self.max_depth);
-        self.size / (2_u32.pow(effective)) as f32
+        self.size / (2_u64.pow(effective)) as f64
     }
 
     /// Center-based: [-size/2..+size/2]. Shift +half_size => [0..size], floor, shift back.
-    pub fn normalize_to_voxel_at_depth(&self, position: Vec3, depth: u32) -> Vec3 {
+    pub fn normalize_to_voxel_at_depth(&self, position: DVec3, depth: u32) -> DVec3 {
         // Convert world coordinate to normalized [0,1] space.
         let half_size = self.size * 0.5;
         // Shift to [0, self.size] taking the octree centre into account
-        let shifted = (position - (self.center - Vec3::splat(half_size))) / self.size;
+        let shifted = (position - (self.center - DVec3::splat(half_size))) / self.size;
         // Determine the number of voxels along an edge at the given depth.
-        let voxel_count = 2_u32.pow(depth) as f32;
+        let voxel_count = 2_u64.pow(depth) as f64;
         // Get the voxel index (as a float) and then compute the center in normalized space.
         let voxel_index = (shifted * voxel_count).floor();
-        let voxel_center = (voxel_index + Vec3::splat(0.5)) / voxel_count;
+        let voxel_center = (voxel_index + DVec3::splat(0.5)) / voxel_count;
         voxel_center
     }
-    pub fn denormalize_voxel_center(&self, voxel_center: Vec3) -> Vec3 {
+    pub fn denormalize_voxel_center(&self, voxel_center: DVec3) -> DVec3 {
         let half_size = self.size * 0.5;
         // Convert the normalized voxel center back to world space.
-        voxel_center * self.size - Vec3::splat(half_size) + self.center
+        voxel_center * self.size - DVec3::splat(half_size) + self.center
     }
 
     /// Convert a world position to the key of the chunk containing it.
-    pub fn world_to_chunk(&self, pos: Vec3) -> ChunkKey {
+    pub fn world_to_chunk(&self, pos: DVec3) -> ChunkKey {
         let step = self.get_spacing_at_depth(self.max_depth);
         let half = self.size * 0.5;
-        let scale = CHUNK_SIZE as f32 * step; // metres per chunk
+        let scale = CHUNK_SIZE as f64 * step; // metres per chunk
         ChunkKey(
             ((pos.x - self.center.x + half) / scale).floor() as i32,
             ((pos.y - self.center.y + half) / scale).floor() as i32,
@@ -53,15 +54,23 @@ impl SparseVoxelOctree {
         )
     }
 
-    /// Calculate the world-space center for a given chunk.
-    pub fn chunk_center_world(&self, key: ChunkKey) -> Vec3 {
-        let half = self.size * 0.5;
+    /// Convert a world position to integer voxel coordinates at `max_depth`.
+    /// Voxel `(0, 0, 0)` starts at the world origin.
+    pub fn world_to_voxel(&self, pos: DVec3) -> IVec3 {
         let step = self.get_spacing_at_depth(self.max_depth);
-        Vec3::new(
-            self.center.x - half + (key.0 as f32 + 0.5) * CHUNK_SIZE as f32 * step,
-            self.center.y - half + (key.1 as f32 + 0.5) * CHUNK_SIZE as f32 * step,
-            self.center.z - half + (key.2 as f32 + 0.5) * CHUNK_SIZE as f32 * step,
-        )
+        (pos / step).floor().as_ivec3()
+    }
+
+    /// World-space center of the voxel with the given integer coordinates.
+    pub fn voxel_to_world(&self, voxel: IVec3) -> DVec3 {
+        let step = self.get_spacing_at_depth(self.max_depth);
+        (voxel.as_dvec3() + DVec3::splat(0.5)) * step
+    }
+
+    /// Calculate the world-space center for a given chunk.
+    pub fn chunk_center_world(&self, key: ChunkKey) -> DVec3 {
+        let half_chunk = CHUNK_SIZE as f64 * self.get_spacing_at_depth(self.max_depth) * 0.5;
+        self.chunk_origin_world(key) + DVec3::splat(half_chunk)
     }
 
     pub fn compute_child_bounds(&self, bounds: &AABB, index: usize) -> AABB {
@@ -79,8 +88,8 @@ impl SparseVoxelOctree {
         let z_max = if (index & 4) == 0 { center.z } else { max.z };
 
         let child_bounds = AABB {
-            min: Vec3::new(x_min, y_min, z_min),
-            max: Vec3::new(x_max, y_max, z_max),
+            min: DVec3::new(x_min, y_min, z_min),
+            max: DVec3::new(x_max, y_max, z_max),
         };
 
         child_bounds
@@ -90,10 +99,10 @@ impl SparseVoxelOctree {
         &self,
         ray: &Ray,
         aabb: &AABB,
-    ) -> Option<(f32, f32, Vec3)> {
+    ) -> Option<(f64, f64, Vec3)> {
         // Define a safe inverse function to avoid division by zero.
-        let safe_inv = |d: f32| if d.abs() < 1e-6 { 1e6 } else { 1.0 / d };
-        let inv_dir = Vec3::new(
+        let safe_inv = |d: f64| if d.abs() < 1e-9 { 1e9 } else { 1.0 / d };
+        let inv_dir = DVec3::new(
             safe_inv(ray.direction.x),
             safe_inv(ray.direction.y),
             safe_inv(ray.direction.z),
@@ -109,7 +118,7 @@ impl SparseVoxelOctree {
         let t_exit = tmax.min_element();
 
         if t_enter <= t_exit && t_exit >= 0.0 {
-            let epsilon = 1e-6;
+            let epsilon = 1e-9;
             let mut normal = Vec3::ZERO;
             // Determine which face was hit by comparing t_enter to the computed values.
             if (t_enter - t1.x).abs() < epsilon || (t_enter - t2.x).abs() < epsilon {
@@ -126,32 +135,43 @@ impl SparseVoxelOctree {
     }
 
     /// Checks if (x,y,z) is within [-size/2..+size/2].
-    pub fn contains(&self, x: f32, y: f32, z: f32) -> bool {
+    pub fn contains(&self, x: f64, y: f64, z: f64) -> bool {
         let half_size = self.size / 2.0;
-        let eps = 1e-6;
+        let eps = 1e-9;
         (x >= self.center.x - half_size - eps && x < self.center.x + half_size + eps)
             && (y >= self.center.y - half_size - eps && y < self.center.y + half_size + eps)
             && (z >= self.center.z - half_size - eps && z < self.center.z + half_size + eps)
     }
 
     /// Retrieve a voxel at world coordinates by normalizing and looking up.
-    pub fn get_voxel_at_world_coords(&self, position: Vec3) -> Option<&Voxel> {
+    pub fn get_voxel_at_world_coords(&self, position: DVec3) -> Option<&Voxel> {
         let aligned = self.normalize_to_voxel_at_depth(position, self.max_depth);
-        self.get_voxel_at(aligned.x, aligned.y, aligned.z)
+        self.get_voxel_at(aligned)
     }
 
-    pub fn local_to_world(&self, local_pos: Vec3) -> Vec3 {
+    pub fn local_to_world(&self, local_pos: DVec3) -> DVec3 {
         // Half the total octree size, used to shift the center to the origin.
         // Convert normalized coordinate to world space, accounting for the octree centre
-        (local_pos - Vec3::splat(0.5)) * self.size + self.center
+        (local_pos - DVec3::splat(0.5)) * self.size + self.center
+    }
+
+    /// Child slot containing the normalized `position`, and the position
+    /// rescaled to that child's `[0, 1]` space.
+    ///
+    /// Normalized positions are voxel centres, `(index + 0.5) / 2^depth`, which
+    /// are exact in `f64` and rescale exactly, so no epsilon is needed even
+    /// tens of levels deep.
+    pub(crate) fn child_slot(position: DVec3) -> (usize, DVec3) {
+        let upper = position.cmpge(DVec3::splat(0.5));
+        let index = upper.x as usize + upper.y as usize * 2 + upper.z as usize * 4;
+        let child = DVec3::select(upper, position - DVec3::splat(0.5), position) * 2.0;
+        (index, child)
     }
 
     /// Helper function to recursively traverse the octree to a specific depth.
     pub(crate) fn get_node_at_depth(
         node: &OctreeNode,
-        x: f32,
-        y: f32,
-        z: f32,
+        position: DVec3,
         depth: u32,
     ) -> Option<&OctreeNode> {
         if depth == 0 {
@@ -159,28 +179,9 @@ impl SparseVoxelOctree {
         }
 
         if let Some(ref children) = node.children {
-            // Determine which child to traverse into
-            let epsilon = 1e-6;
-            let index = ((x >= 0.5 - epsilon) as usize)
-                + ((y >= 0.5 - epsilon) as usize * 2)
-                + ((z >= 0.5 - epsilon) as usize * 4);
-
-            let adjust_coord = |coord: f32| {
-                if coord >= 0.5 - epsilon {
-                    (coord - 0.5) * 2.0
-                } else {
-                    coord * 2.0
-                }
-            };
-
-            // Recurse into the correct child
-            Self::get_node_at_depth(
-                &children[index],
-                adjust_coord(x),
-                adjust_coord(y),
-                adjust_coord(z),
-                depth - 1,
-            )
+            // Recurse into the child containing the position
+            let (index, child) = Self::child_slot(position);
+            Self::get_node_at_depth(&children[index], child, depth - 1)
         } else {
             None // Node has no children at this depth
         }
@@ -267,17 +268,17 @@ impl AABB {
             && self.max.z >= other.min.z
     }
 
-    pub fn center(&self) -> Vec3 {
+    pub fn center(&self) -> DVec3 {
         (self.min + self.max) * 0.5
     }
 }
 
 impl SparseVoxelOctree {
-    pub fn collect_voxels_in_region(&self, min: Vec3, max: Vec3) -> Vec<(Vec3, Voxel)> {
+    pub fn collect_voxels_in_region(&self, min: DVec3, max: DVec3) -> Vec<(DVec3, Voxel)> {
         let half_size = self.size * 0.5;
         let root_bounds = AABB {
-            min: self.center - Vec3::splat(half_size),
-            max: self.center + Vec3::splat(half_size),
+            min: self.center - DVec3::splat(half_size),
+            max: self.center + DVec3::splat(half_size),
         };
         let mut voxels = Vec::new();
         self.collect_voxels_in_region_recursive(&self.root, root_bounds, min, max, &mut voxels);
@@ -288,9 +289,9 @@ impl SparseVoxelOctree {
         &self,
         node: &OctreeNode,
         node_bounds: AABB,
-        min: Vec3,
-        max: Vec3,
-        out: &mut Vec<(Vec3, Voxel)>,
+        min: DVec3,
+        max: DVec3,
+        out: &mut Vec<(DVec3, Voxel)>,
     ) {
         if !node_bounds.intersects_aabb(&AABB { min, max }) {
             return;
@@ -0,0 +1,441 @@
+use crate::plugins::environment::systems::voxels::structure::*;
+use bevy::math::DVec3;
+use bevy::prelude::*;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// Brightest light level for both sky and block light.
+pub const MAX_LIGHT: u8 = 15;
+
+/// More pending edits than this trigger a full relight instead of BFS updates.
+const INCREMENTAL_LIMIT: usize = 4096;
+
+const SECTION: i32 = 16;
+const SECTION_VOLUME: usize = (SECTION * SECTION * SECTION) as usize;
+
+const DIRECTIONS: [IVec3; 6] = [
+    IVec3::NEG_X,
+    IVec3::X,
+    IVec3::NEG_Y,
+    IVec3::Y,
+    IVec3::NEG_Z,
+    IVec3::Z,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Channel {
+    Sky,
+    Block,
+}
+
+/// Per-voxel sky and block light, addressed by integer voxel coordinates.
+///
+/// Light is stored in 16³ sections with the sky level in the high nibble and
+/// the block level in the low nibble. Sections are only allocated where the
+/// flood fill actually wrote something; every other cell falls back to the
+/// column heights: full sky light above the highest solid voxel, dark below.
+#[derive(Debug, Clone)]
+pub struct VoxelLight {
+    sections: HashMap<IVec3, Box<[u8; SECTION_VOLUME]>>,
+    /// First voxel `y` above the highest solid voxel of each (x, z) column.
+    heights: HashMap<IVec2, i32>,
+    /// Texture index → emitted level. A voxel emits when all six faces use
+    /// the same emissive texture. Empty by default, so block light stays dark
+    /// until a texture is registered here.
+    pub emission: HashMap<usize, u8>,
+    /// Voxels inserted or removed since the last update.
+    pending: Vec<IVec3>,
+    built: bool,
+}
+
+impl Default for VoxelLight {
+    fn default() -> Self {
+        Self {
+            sections: HashMap::new(),
+            heights: HashMap::new(),
+            emission: HashMap::new(),
+            pending: Vec::new(),
+            built: false,
+        }
+    }
+}
+
+impl VoxelLight {
+    /// Record an edited voxel. Falls back to a full relight when too many
+    /// edits pile up (imports, world generation).
+    pub fn note_change(&mut self, voxel: IVec3) {
+        if !self.built {
+            return;
+        }
+        if self.pending.len() >= INCREMENTAL_LIMIT {
+            self.invalidate();
+        } else {
+            self.pending.push(voxel);
+        }
+    }
+
+    /// Drop all light data; the next `update_lighting` relights everything.
+    pub fn invalidate(&mut self) {
+        self.sections.clear();
+        self.heights.clear();
+        self.pending.clear();
+        self.built = false;
+    }
+
+    /// `(sky, block)` light of the cell at `voxel`.
+    pub fn get(&self, voxel: IVec3) -> (u8, u8) {
+        let (section, index) = split(voxel);
+        match self.sections.get(&section) {
+            Some(cells) => (cells[index] >> 4, cells[index] & 0x0f),
+            None => (self.default_sky(voxel), 0),
+        }
+    }
+
+    /// Combined light level of the cell at `voxel`.
+    pub fn level(&self, voxel: IVec3) -> u8 {
+        let (sky, block) = self.get(voxel);
+        sky.max(block)
+    }
+
+    fn default_sky(&self, voxel: IVec3) -> u8 {
+        match self.heights.get(&voxel.xz()) {
+            Some(height) if voxel.y < *height => 0,
+            _ => MAX_LIGHT,
+        }
+    }
+
+    fn channel(&self, voxel: IVec3, channel: Channel) -> u8 {
+        let (sky, block) = self.get(voxel);
+        match channel {
+            Channel::Sky => sky,
+            Channel::Block => block,
+        }
+    }
+
+    fn set(&mut self, voxel: IVec3, channel: Channel, level: u8) {
+        let (section, index) = split(voxel);
+        if !self.sections.contains_key(&section) {
+            // Materialise the section with the values it had implicitly.
+            let base = section * SECTION;
+            let mut cells = Box::new([0u8; SECTION_VOLUME]);
+            for (i, cell) in cells.iter_mut().enumerate() {
+                *cell = self.default_sky(base + unsplit(i)) << 4;
+            }
+            self.sections.insert(section, cells);
+        }
+        let cell = &mut self.sections.get_mut(&section).unwrap()[index];
+        *cell = match channel {
+            Channel::Sky => (*cell & 0x0f) | (level << 4),
+            Channel::Block => (*cell & 0xf0) | level,
+        };
+    }
+
+    fn emission_of(&self, voxel: &Voxel) -> u8 {
+        let texture = voxel.textures[0];
+        if voxel.textures.iter().all(|t| *t == texture) {
+            self.emission.get(&texture).copied().unwrap_or(0)
+        } else {
+            0
+        }
+    }
+}
+
+/// Index of the section containing `voxel` and the cell index inside it.
+fn split(voxel: IVec3) -> (IVec3, usize) {
+    let section = voxel.div_euclid(IVec3::splat(SECTION));
+    let local = voxel.rem_euclid(IVec3::splat(SECTION));
+    let index = (local.x + (local.y + local.z * SECTION) * SECTION) as usize;
+    (section, index)
+}
+
+fn unsplit(index: usize) -> IVec3 {
+    let i = index as i32;
+    IVec3::new(i % SECTION, (i / SECTION) % SECTION, i / (SECTION * SECTION))
+}
+
+/// Opaque voxel at `voxel`; transparent voxels let light through like air.
+fn voxel_at(tree: &SparseVoxelOctree, voxel: IVec3) -> Option<Voxel> {
+    tree.get_voxel_at_world_coords(tree.voxel_to_world(voxel))
+        .filter(|v| !v.is_transparent())
+        .copied()
+}
+
+/// Flood-fill state shared by the add and remove passes. Keeps track of the
+/// chunks whose lighting changed so they can be remeshed.
+struct Propagation<'a> {
+    tree: &'a SparseVoxelOctree,
+    light: &'a mut VoxelLight,
+    touched: HashSet<IVec3>,
+}
+
+impl Propagation<'_> {
+    fn set(&mut self, voxel: IVec3, channel: Channel, level: u8) {
+        self.light.set(voxel, channel, level);
+        self.touched.insert(voxel.div_euclid(IVec3::splat(CHUNK_SIZE)));
+    }
+
+    /// Spread light outwards from every queued cell.
+    fn add(&mut self, channel: Channel, mut queue: VecDeque<IVec3>) {
+        while let Some(pos) = queue.pop_front() {
+            let level = self.light.channel(pos, channel);
+            if level <= 1 {
+                continue;
+            }
+            for dir in DIRECTIONS {
+                let next = pos + dir;
+                if voxel_at(self.tree, next).is_some() {
+                    continue;
+                }
+                // Full sunlight travels straight down without fading.
+                let target = if channel == Channel::Sky && dir == IVec3::NEG_Y && level == MAX_LIGHT
+                {
+                    MAX_LIGHT
+                } else {
+                    level - 1
+                };
+                if self.light.channel(next, channel) < target {
+                    self.set(next, channel, target);
+                    queue.push_back(next);
+                }
+            }
+        }
+    }
+
+    /// Darken everything that was lit through the queued cells, then refill
+    /// the hole from the brighter cells found along its border.
+    fn remove(&mut self, channel: Channel, mut queue: VecDeque<(IVec3, u8)>) {
+        let mut refill = VecDeque::new();
+        while let Some((pos, level)) = queue.pop_front() {
+            for dir in DIRECTIONS {
+                let next = pos + dir;
+                let next_level = self.light.channel(next, channel);
+                if next_level == 0 {
+                    continue;
+                }
+                // Only emitters keep light inside solid voxels; they are
+                // sources, so they relight the hole instead of going dark.
+                if voxel_at(self.tree, next).is_some() {
+                    refill.push_back(next);
+                    continue;
+                }
+                let sunbeam = channel == Channel::Sky
+                    && dir == IVec3::NEG_Y
+                    && level == MAX_LIGHT
+                    && next_level == MAX_LIGHT;
+                if next_level < level || sunbeam {
+                    self.set(next, channel, 0);
+                    queue.push_back((next, next_level));
+                } else {
+                    refill.push_back(next);
+                }
+            }
+        }
+        self.add(channel, refill);
+    }
+}
+
+/// Relight the whole octree from scratch.
+///
+/// Column heights come from the occupied chunks, scanned from the top down.
+/// Sky light is then only flooded from cells that sit above their own column
+/// but below a neighbouring one – the entrances to overhangs and caves – and
+/// block light from every emissive voxel.
+fn rebuild(tree: &SparseVoxelOctree, light: &mut VoxelLight) {
+    light.sections.clear();
+    light.heights.clear();
+    light.pending.clear();
+
+    let step = tree.get_spacing_at_depth(tree.max_depth);
+    let mut columns: HashMap<(i32, i32), Vec<i32>> = HashMap::new();
+    for key in &tree.occupied_chunks {
+        columns.entry((key.0, key.2)).or_default().push(key.1);
+    }
+
+    for ((kx, kz), mut ys) in columns {
+        ys.sort_unstable_by(|a, b| b.cmp(a));
+        let mut open = [[true; CHUNK_SIZE as usize]; CHUNK_SIZE as usize];
+        let mut remaining = CHUNK_SIZE * CHUNK_SIZE;
+        for ky in ys {
+            let key = ChunkKey(kx, ky, kz);
+            let snapshot = tree.extract_chunk(key, 0);
+            let base = tree.world_to_voxel(tree.chunk_origin_world(key) + DVec3::splat(step * 0.5));
+            for x in 0..CHUNK_SIZE as usize {
+                for z in 0..CHUNK_SIZE as usize {
+                    if !open[x][z] {
+                        continue;
+                    }
+                    if let Some(y) = (0..CHUNK_SIZE as usize).rev().find(|y| snapshot.voxel(IVec3::new(x as i32, *y as i32, z as i32)).is_some_and(|v| !v.is_transparent())) {
+                        open[x][z] = false;
+                        remaining -= 1;
+                        let column = IVec2::new(base.x + x as i32, base.z + z as i32);
+                        light.heights.insert(column, base.y + y as i32 + 1);
+                    }
+                }
+            }
+            if remaining == 0 {
+                break;
+            }
+        }
+    }
+
+    let mut sky_seeds = VecDeque::new();
+    for (column, height) in &light.heights {
+        let highest_neighbour = [IVec2::X, IVec2::NEG_X, IVec2::Y, IVec2::NEG_Y]
+            .iter()
+            .filter_map(|d| light.heights.get(&(*column + *d)))
+            .max()
+            .copied()
+            .unwrap_or(i32::MIN);
+        for y in *height..highest_neighbour {
+            sky_seeds.push_back(IVec3::new(column.x, y, column.y));
+        }
+    }
+
+    let mut block_seeds = VecDeque::new();
+    let mut emitters = Vec::new();
+    if !light.emission.is_empty() {
+        for (pos, voxel, _depth) in
+            SparseVoxelOctree::collect_voxels_from_node(&tree.root, tree.size, tree.center)
+        {
+            let level = light.emission_of(&voxel);
+            if level > 0 && !voxel.is_transparent() {
+                emitters.push((tree.world_to_voxel(pos), level));
+            }
+        }
+    }
+
+    let mut fill = Propagation {
+        tree,
+        light: &mut *light,
+        touched: HashSet::new(),
+    };
+    for (voxel, level) in emitters {
+        fill.set(voxel, Channel::Block, level);
+        block_seeds.push_back(voxel);
+    }
+    fill.add(Channel::Sky, sky_seeds);
+    fill.add(Channel::Block, block_seeds);
+    light.built = true;
+}
+
+/// Apply the pending edits with the BFS add/remove algorithm. Returns the
+/// chunk-sized cells (voxel coordinates divided by `CHUNK_SIZE`) that changed;
+/// chunk boundaries line up with multiples of `CHUNK_SIZE` voxels.
+fn update(tree: &SparseVoxelOctree, light: &mut VoxelLight) -> HashSet<IVec3> {
+    let pending = std::mem::take(&mut light.pending);
+    let bottom = tree.world_to_voxel(tree.center - DVec3::splat(tree.size * 0.5)).y;
+    let mut fill = Propagation {
+        tree,
+        light: &mut *light,
+        touched: HashSet::new(),
+    };
+
+    for voxel in pending {
+        let (sky, block) = fill.light.get(voxel);
+        fill.touched.insert(voxel.div_euclid(IVec3::splat(CHUNK_SIZE)));
+
+        match voxel_at(tree, voxel) {
+            Some(placed) => {
+                // The cell turned solid: it blocks whatever passed through it.
+                fill.set(voxel, Channel::Sky, 0);
+                fill.set(voxel, Channel::Block, 0);
+                fill.remove(Channel::Sky, VecDeque::from([(voxel, sky)]));
+                fill.remove(Channel::Block, VecDeque::from([(voxel, block)]));
+
+                // Only raise the column afterwards, so the shadow below was
+                // still read as sunlit while it was being removed.
+                let column = voxel.xz();
+                let height = fill.light.heights.get(&column).copied().unwrap_or(i32::MIN);
+                if voxel.y >= height {
+                    fill.light.heights.insert(column, voxel.y + 1);
+                }
+
+                let emission = fill.light.emission_of(&placed);
+                if emission > 0 {
+                    fill.set(voxel, Channel::Block, emission);
+                    fill.add(Channel::Block, VecDeque::from([voxel]));
+                }
+            }
+            None => {
+                // The cell opened up: neighbours flow into it again.
+                let mut sky_seeds: VecDeque<IVec3> =
+                    DIRECTIONS.iter().map(|d| voxel + *d).collect();
+                let block_seeds = sky_seeds.clone();
+
+                // An emitter that was removed takes its light with it.
+                if block > 0 {
+                    fill.set(voxel, Channel::Block, 0);
+                    fill.remove(Channel::Block, VecDeque::from([(voxel, block)]));
+                }
+
+                let column = voxel.xz();
+                if fill.light.heights.get(&column) == Some(&(voxel.y + 1)) {
+                    // Removed the top of a column: sunlight reaches down to the
+                    // next solid voxel.
+                    let mut y = voxel.y;
+                    while y > bottom && voxel_at(tree, IVec3::new(voxel.x, y - 1, voxel.z)).is_none() {
+                        y -= 1;
+                    }
+                    fill.light.heights.insert(column, y);
+                    for y in y..=voxel.y {
+                        let cell = IVec3::new(voxel.x, y, voxel.z);
+                        fill.set(cell, Channel::Sky, MAX_LIGHT);
+                        sky_seeds.push_back(cell);
+                    }
+                }
+
+                fill.add(Channel::Sky, sky_seeds);
+                fill.add(Channel::Block, block_seeds);
+            }
+        }
+    }
+    fill.touched
+}
+
+/// Rebuild the lighting of a tree outside the ECS, e.g. for offline tools.
+pub(crate) fn relight(tree: &mut SparseVoxelOctree) {
+    let mut light = std::mem::take(&mut tree.light);
+    rebuild(tree, &mut light);
+    tree.light = light;
+}
+
+/// Keeps the octree lighting current. Runs before meshing so new and dirty
+/// chunks are always meshed with up-to-date light.
+pub fn update_lighting(
+    mut octrees: Query<(Entity, &mut SparseVoxelOctree)>,
+    spawned: Res<SpawnedChunks>,
+) {
+    for (volume, mut tree) in &mut octrees {
+        // only touch the tree mutably when there is work, otherwise every
+        // volume would be flagged as changed each frame
+        if tree.light.built && tree.light.pending.is_empty() {
+            continue;
+        }
+        let mut light = std::mem::take(&mut tree.light);
+
+        if !light.built {
+            let started = std::time::Instant::now();
+            rebuild(&tree, &mut light);
+            info!(
+                "lighting rebuilt: {} sections in {:.2?}",
+                light.sections.len(),
+                started.elapsed()
+            );
+            // Everything already on screen needs the new light.
+            if let Some(chunks) = spawned.0.get(&volume) {
+                tree.dirty_chunks.extend(chunks.keys().copied());
+            }
+        } else if !light.pending.is_empty() {
+            let changed = update(&tree, &mut light);
+            for cell in changed {
+                let key = tree.world_to_chunk(tree.voxel_to_world(cell * CHUNK_SIZE));
+                // faces of neighbouring chunks sample light across the border
+                if tree.occupied_chunks.contains(&key) {
+                    tree.dirty_chunks.insert(key);
+                }
+                tree.mark_neighbors_dirty_from_key(key);
+            }
+        }
+
+        tree.light = light;
+    }
+}
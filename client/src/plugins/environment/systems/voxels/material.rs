@@ -0,0 +1,123 @@
+use crate::plugins::environment::systems::voxels::atlas::VoxelTextureAtlas;
+use bevy::pbr::{MaterialPipeline, MaterialPipelineKey};
+use bevy::prelude::*;
+use bevy::render::mesh::{MeshVertexAttribute, MeshVertexBufferLayoutRef};
+use bevy::render::render_resource::{
+    AsBindGroup, RenderPipelineDescriptor, ShaderRef, SpecializedMeshPipelineError, VertexFormat,
+};
+
+/// One `u32` per chunk vertex, see [`PackedVertex`].
+pub const ATTRIBUTE_PACKED_VOXEL: MeshVertexAttribute =
+    MeshVertexAttribute::new("Vertex_PackedVoxel", 988_540_917, VertexFormat::Uint32);
+
+/// Face normals in the voxel face order (left, right, bottom, top, back, front).
+pub const FACE_NORMALS: [Vec3; 6] = [
+    Vec3::NEG_X,
+    Vec3::X,
+    Vec3::NEG_Y,
+    Vec3::Y,
+    Vec3::NEG_Z,
+    Vec3::Z,
+];
+
+/// Chunk vertex as stored in [`ATTRIBUTE_PACKED_VOXEL`]:
+///
+/// | bits   | field                                     |
+/// |--------|-------------------------------------------|
+/// | 0‥14   | chunk-local position, 5 bits per axis     |
+/// | 15‥17  | face index into [`FACE_NORMALS`]          |
+/// | 18‥19  | ambient occlusion, 0 (dark) ‥ 3 (open)    |
+/// | 20‥23  | light level 0‥15                          |
+/// | 24‥31  | texture array layer                       |
+///
+/// UVs are not stored; the shader derives them from the position and face.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PackedVertex {
+    pub position: UVec3,
+    pub face: u8,
+    pub ao: u8,
+    pub light: u8,
+    pub layer: u8,
+}
+
+impl PackedVertex {
+    pub fn pack(self) -> u32 {
+        (self.position.x & 0x1f)
+            | (self.position.y & 0x1f) << 5
+            | (self.position.z & 0x1f) << 10
+            | (self.face as u32 & 0x7) << 15
+            | (self.ao as u32 & 0x3) << 18
+            | (self.light as u32 & 0xf) << 20
+            | (self.layer as u32) << 24
+    }
+
+    pub fn unpack(packed: u32) -> Self {
+        Self {
+            position: UVec3::new(packed & 0x1f, (packed >> 5) & 0x1f, (packed >> 10) & 0x1f),
+            face: ((packed >> 15) & 0x7) as u8,
+            ao: ((packed >> 18) & 0x3) as u8,
+            light: ((packed >> 20) & 0xf) as u8,
+            layer: (packed >> 24) as u8,
+        }
+    }
+}
+
+/// Chunk material sampling a 2D texture array. UVs are in voxel units so
+/// textures repeat across greedy quads; light and AO are decoded from the
+/// packed vertex.
+#[derive(Asset, TypePath, AsBindGroup, Debug, Clone)]
+pub struct VoxelMaterial {
+    #[texture(0, dimension = "2d_array")]
+    #[sampler(1)]
+    pub textures: Handle<Image>,
+    pub alpha_mode: AlphaMode,
+}
+
+impl Material for VoxelMaterial {
+    fn vertex_shader() -> ShaderRef {
+        "shaders/voxel.wgsl".into()
+    }
+
+    fn fragment_shader() -> ShaderRef {
+        "shaders/voxel.wgsl".into()
+    }
+
+    fn alpha_mode(&self) -> AlphaMode {
+        self.alpha_mode
+    }
+
+    fn specialize(
+        _pipeline: &MaterialPipeline<Self>,
+        descriptor: &mut RenderPipelineDescriptor,
+        layout: &MeshVertexBufferLayoutRef,
+        _key: MaterialPipelineKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        let vertex_layout = layout
+            .0
+            .get_layout(&[ATTRIBUTE_PACKED_VOXEL.at_shader_location(0)])?;
+        descriptor.vertex.buffers = vec![vertex_layout];
+        Ok(())
+    }
+}
+
+/// The two materials shared by every chunk, created once at startup.
+#[derive(Resource, Clone)]
+pub struct VoxelMaterials {
+    pub opaque: Handle<VoxelMaterial>,
+    pub transparent: Handle<VoxelMaterial>,
+}
+
+impl VoxelMaterials {
+    pub fn new(atlas: &VoxelTextureAtlas, materials: &mut Assets<VoxelMaterial>) -> Self {
+        Self {
+            opaque: materials.add(VoxelMaterial {
+                textures: atlas.layers.clone(),
+                alpha_mode: AlphaMode::Opaque,
+            }),
+            transparent: materials.add(VoxelMaterial {
+                textures: atlas.layers.clone(),
+                alpha_mode: AlphaMode::Blend,
+            }),
+        }
+    }
+}
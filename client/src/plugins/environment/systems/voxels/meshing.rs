@@ -1,11 +1,11 @@
 use crate::plugins::environment::systems::voxels::structure::*;
-use crate::plugins::environment::systems::voxels::atlas::VoxelTextureAtlas;
+use crate::plugins::environment::systems::voxels::material::{ATTRIBUTE_PACKED_VOXEL, PackedVertex};
 use bevy::asset::RenderAssetUsages;
 use bevy::prelude::*;
 use bevy::render::mesh::{Indices, Mesh, PrimitiveTopology, VertexAttributeValues};
 
 /*pub(crate) fn mesh_chunk(
-    buffer: &[[[Option<Voxel>; CHUNK_SIZE as usize]; CHUNK_SIZE as usize]; CHUNK_SIZE as usize],
+    buffer: &ChunkBuffer,
     origin: Vec3,
     step:   f32,
     tree:   &SparseVoxelOctree,
@@ -299,13 +299,14 @@ use bevy::render::mesh::{Indices, Mesh, PrimitiveTopology, VertexAttributeValues
     mesh
 }*/
 
+/// Greedy-mesh one chunk from its snapshot, which also supplies the border
+/// voxels and light, so this runs fine off the main thread. The vertices are
+/// chunk-local voxel units packed as [`PackedVertex`]; the chunk entity's
+/// transform does the placement.
 pub(crate) fn mesh_chunk(
-    buffer: &[[[Option<Voxel>; CHUNK_SIZE as usize]; CHUNK_SIZE as usize]; CHUNK_SIZE as usize],
-    origin: Vec3,
-    step: f32,
-    tree: &SparseVoxelOctree,
+    snapshot: &ChunkSnapshot,
     pool: &mut MeshBufferPool,
-    atlas: &VoxelTextureAtlas,
+    pass: MeshPass,
 ) -> Option<Mesh> {
     // ────────────────────────────────────────────────────────────────────────────
     // Helpers
@@ -314,53 +315,69 @@ pub(crate) fn mesh_chunk(
     const N: usize = CHUNK_SIZE as usize;
     const MASK_LEN: usize = N * N;
 
-    // Safe voxel query that falls back to the octree for out‑of‑chunk requests.
-    let get_voxel = |x: i32, y: i32, z: i32| -> Option<Voxel> {
-        if (0..CHUNK_SIZE).contains(&x)
-            && (0..CHUNK_SIZE).contains(&y)
-            && (0..CHUNK_SIZE).contains(&z)
-        {
-            buffer[x as usize][y as usize][z as usize]
-        } else {
-            let world = origin + Vec3::new(x as f32 * step, y as f32 * step, z as f32 * step);
-            tree.get_voxel_at_world_coords(world).copied()
-        }
-    };
+    // Voxel query over the chunk and its one-voxel border.
+    let get_voxel =
+        |x: i32, y: i32, z: i32| -> Option<Voxel> { snapshot.voxel(IVec3::new(x, y, z)) };
 
     // Push a single quad (4 vertices, 6 indices).  `base` is the lower‑left
-    // corner in world space; `u`/`v` are the tangent vectors (length 1); `size`
-    // is expressed in world units along those axes; `n` is the face normal.
+    // corner in chunk-local voxel units; `u`/`v` are the tangent axes and
+    // `width`/`height` the quad size along them, in voxels.
     // Preallocate vertex buffers for better performance, reusing the pool.
     pool.clear();
     let voxel_count = N * N * N;
-    pool.positions.reserve(voxel_count * 4);
-    pool.normals.reserve(voxel_count * 4);
-    pool.uvs.reserve(voxel_count * 4);
+    pool.vertices.reserve(voxel_count * 4);
     pool.indices.reserve(voxel_count * 6);
 
-    let positions = &mut pool.positions;
-    let normals = &mut pool.normals;
-    let uvs = &mut pool.uvs;
+    let vertices = &mut pool.vertices;
     let indices = &mut pool.indices;
 
-    let mut push_quad = |base: Vec3, size: Vec2, n: Vec3, u: Vec3, v: Vec3, tex_id: usize| {
-        let i0 = positions.len() as u32;
-        positions.extend_from_slice(&[
-            (base).into(),
-            (base + u * size.x).into(),
-            (base + u * size.x + v * size.y).into(),
-            (base + v * size.y).into(),
-        ]);
-        normals.extend_from_slice(&[[n.x, n.y, n.z]; 4]);
-        let uv_rect = atlas.uv_rect(tex_id);
-        uvs.extend_from_slice(&uv_rect);
+    // `ao` holds the occlusion level (0 = darkest, 3 = open) of the corners in
+    // the order base, base+u, base+u+v, base+v.
+    let mut push_quad = |base: IVec3,
+                         width: i32,
+                         height: i32,
+                         face: usize,
+                         u: IVec3,
+                         v: IVec3,
+                         tex_id: usize,
+                         ao: [u8; 4],
+                         light: u8| {
+        let i0 = vertices.len() as u32;
+        let corners = [
+            base,
+            base + u * width,
+            base + u * width + v * height,
+            base + v * height,
+        ];
+        for (corner, ao) in corners.into_iter().zip(ao) {
+            vertices.push(
+                PackedVertex {
+                    position: corner.as_uvec3(),
+                    face: face as u8,
+                    ao,
+                    light,
+                    layer: tex_id as u8,
+                }
+                .pack(),
+            );
+        }
 
-        if n.x + n.y + n.z >= 0.0 {
-            indices.extend_from_slice(&[i0, i0 + 1, i0 + 2, i0 + 2, i0 + 3, i0]);
+        // Split along the diagonal whose corners are brighter, otherwise the
+        // interpolated occlusion shows up as a visible crease.
+        let flip = ao[0] + ao[2] < ao[1] + ao[3];
+        let tris = if flip {
+            [i0 + 1, i0 + 2, i0 + 3, i0 + 3, i0, i0 + 1]
+        } else {
+            [i0, i0 + 1, i0 + 2, i0 + 2, i0 + 3, i0]
+        };
+
+        // Odd face indices point along +X/+Y/+Z.
+        if face % 2 == 1 {
+            indices.extend_from_slice(&tris);
         } else {
-            // Flip winding for faces with a negative normal component sum so the
-            // result is still counter‑clockwise.
-            indices.extend_from_slice(&[i0, i0 + 3, i0 + 2, i0 + 2, i0 + 1, i0]);
+            // Flip winding for faces with a negative normal so the result is
+            // still counter‑clockwise.
+            indices.extend(tris.iter().rev());
         }
     };
 
@@ -371,11 +388,20 @@ pub(crate) fn mesh_chunk(
     // Axes: 0→X, 1→Y, 2→Z.  For each axis we process the negative and positive
     // faces (dir = −1 / +1).
     for (axis, dir) in [(0, -1), (0, 1), (1, -1), (1, 1), (2, -1), (2, 1)] {
-        // Mapping of (u,v) axes and their unit vectors in world space.
-        let (u_axis, v_axis, face_normal, u_vec, v_vec) = match (axis, dir) {
-            (0, d) => (1, 2, Vec3::new(d as f32, 0.0, 0.0), Vec3::Y, Vec3::Z),
-            (1, d) => (2, 0, Vec3::new(0.0, d as f32, 0.0), Vec3::Z, Vec3::X),
-            (2, d) => (0, 1, Vec3::new(0.0, 0.0, d as f32), Vec3::X, Vec3::Y),
+        // Mapping of (u,v) axes and their unit vectors.
+        let (u_axis, v_axis, u_vec, v_vec) = match axis {
+            0 => (1, 2, IVec3::Y, IVec3::Z),
+            1 => (2, 0, IVec3::Z, IVec3::X),
+            2 => (0, 1, IVec3::X, IVec3::Y),
+            _ => unreachable!(),
+        };
+        let face_idx = match (axis, dir) {
+            (0, -1) => 0,
+            (0, 1) => 1,
+            (1, -1) => 2,
+            (1, 1) => 3,
+            (2, -1) => 4,
+            (2, 1) => 5,
             _ => unreachable!(),
         };
 
@@ -385,7 +411,9 @@ pub(crate) fn mesh_chunk(
         for slice in 0..=N {
             // Build the face mask for this slice using a fixed-size array to
             // avoid heap allocations.
-            let mut mask = [None::<usize>; MASK_LEN];
+            // Each face stores its texture, per-corner AO and the light level
+            // of the cell in front of it; only faces where all match are merged.
+            let mut mask = [None::<(usize, [u8; 4], u8)>; MASK_LEN];
             let mut visited = [false; MASK_LEN];
             let idx = |u: usize, v: usize| -> usize { u * N + v };
 
@@ -404,17 +432,44 @@ pub(crate) fn mesh_chunk(
                     neighbor[v_axis] = v as i32;
 
                     if let Some(vox) = get_voxel(cell[0], cell[1], cell[2]) {
-                        if get_voxel(neighbor[0], neighbor[1], neighbor[2]).is_none() {
-                            let face_idx = match (axis, dir) {
-                                (0, -1) => 0,
-                                (0, 1) => 1,
-                                (1, -1) => 2,
-                                (1, 1) => 3,
-                                (2, -1) => 4,
-                                (2, 1) => 5,
-                                _ => unreachable!(),
+                        let other = get_voxel(neighbor[0], neighbor[1], neighbor[2]);
+                        // Opaque faces show through any translucent neighbour;
+                        // translucent faces hide behind opaque voxels and
+                        // between two voxels of the same kind (water, glass).
+                        let visible = match pass {
+                            MeshPass::Opaque => {
+                                !vox.is_transparent() && other.is_none_or(|o| o.is_transparent())
+                            }
+                            MeshPass::Transparent => {
+                                vox.is_transparent()
+                                    && other.is_none_or(|o| o.is_transparent() && o != vox)
+                            }
+                        };
+                        if visible {
+                            // Occluders live in the layer the face looks into.
+                            let solid = |du: i32, dv: i32| -> u8 {
+                                let mut p = neighbor;
+                                p[u_axis] += du;
+                                p[v_axis] += dv;
+                                get_voxel(p[0], p[1], p[2]).is_some_and(|o| !o.is_transparent())
+                                    as u8
+                            };
+                            let corner_ao = |du: i32, dv: i32| -> u8 {
+                                let (side1, side2) = (solid(du, 0), solid(0, dv));
+                                if side1 == 1 && side2 == 1 {
+                                    0
+                                } else {
+                                    3 - (side1 + side2 + solid(du, dv))
+                                }
                             };
-                            mask[idx(u, v)] = Some(vox.textures[face_idx]);
+                            let ao = [
+                                corner_ao(-1, -1),
+                                corner_ao(1, -1),
+                                corner_ao(1, 1),
+                                corner_ao(-1, 1),
+                            ];
+                            let light = snapshot.light(IVec3::from(neighbor));
+                            mask[idx(u, v)] = Some((vox.textures[face_idx], ao, light));
                         }
                     }
                 }
@@ -426,12 +481,13 @@ pub(crate) fn mesh_chunk(
                     if visited[idx(u0, v0)] {
                         continue;
                     }
-                    let Some(tex_id) = mask[idx(u0, v0)] else { continue };
+                    let Some(face) = mask[idx(u0, v0)] else { continue };
+                    let (tex_id, ao, light) = face;
 
                     // Determine the rectangle width.
                     let mut width = 1;
                     while u0 + width < N
-                        && mask[idx(u0 + width, v0)] == Some(tex_id)
+                        && mask[idx(u0 + width, v0)] == Some(face)
                         && !visited[idx(u0 + width, v0)]
                     {
                         width += 1;
@@ -441,7 +497,7 @@ pub(crate) fn mesh_chunk(
                     let mut height = 1;
                     'h: while v0 + height < N {
                         for du in 0..width {
-                            if mask[idx(u0 + du, v0 + height)] != Some(tex_id)
+                            if mask[idx(u0 + du, v0 + height)] != Some(face)
                                 || visited[idx(u0 + du, v0 + height)]
                             {
                                 break 'h;
@@ -457,29 +513,23 @@ pub(crate) fn mesh_chunk(
                         }
                     }
 
-                    // Compute world‑space base corner.
-                    let mut base = origin;
-                    match axis {
-                        0 => {
-                            base.x += step * slice as f32;
-                            base.y += step * u0 as f32;
-                            base.z += step * v0 as f32;
-                        }
-                        1 => {
-                            base.x += step * v0 as f32;
-                            base.y += step * slice as f32;
-                            base.z += step * u0 as f32;
-                        }
-                        2 => {
-                            base.x += step * u0 as f32;
-                            base.y += step * v0 as f32;
-                            base.z += step * slice as f32;
-                        }
-                        _ => unreachable!(),
-                    }
-
-                    let size = Vec2::new(width as f32 * step, height as f32 * step);
-                    push_quad(base, size, face_normal, u_vec, v_vec, tex_id);
+                    // Chunk-local base corner.
+                    let mut base = IVec3::ZERO;
+                    base[axis] = slice as i32;
+                    base[u_axis] = u0 as i32;
+                    base[v_axis] = v0 as i32;
+
+                    push_quad(
+                        base,
+                        width as i32,
+                        height as i32,
+                        face_idx,
+                        u_vec,
+                        v_vec,
+                        tex_id,
+                        ao,
+                        light,
+                    );
                 }
             }
         }
@@ -492,23 +542,22 @@ pub(crate) fn mesh_chunk(
         return None;
     }
 
+    let mesh = packed_mesh(vertices.clone(), indices.clone());
+    pool.clear();
+    Some(mesh)
+}
+
+/// Chunk mesh from packed vertices and their indices; shared with the GPU
+/// mesher's readback.
+pub(crate) fn packed_mesh(vertices: Vec<u32>, indices: Vec<u32>) -> Mesh {
     let mut mesh = Mesh::new(
         PrimitiveTopology::TriangleList,
         RenderAssetUsages::default(),
     );
     mesh.insert_attribute(
-        Mesh::ATTRIBUTE_POSITION,
-        VertexAttributeValues::Float32x3(positions.clone()),
+        ATTRIBUTE_PACKED_VOXEL,
+        VertexAttributeValues::Uint32(vertices),
     );
-    mesh.insert_attribute(
-        Mesh::ATTRIBUTE_NORMAL,
-        VertexAttributeValues::Float32x3(normals.clone()),
-    );
-    mesh.insert_attribute(
-        Mesh::ATTRIBUTE_UV_0,
-        VertexAttributeValues::Float32x2(uvs.clone()),
-    );
-    mesh.insert_indices(Indices::U32(indices.clone()));
-    pool.clear();
-    Some(mesh)
+    mesh.insert_indices(Indices::U32(indices));
+    mesh
 }
@@ -1,32 +1,39 @@
 use bevy::prelude::*;
+use bevy::render::render_resource::DownlevelFlags;
+use bevy::render::renderer::{RenderAdapter, RenderDevice};
 use bevy_app_compute::prelude::*;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
 
-use super::structure::{CHUNK_SIZE, MeshBufferPool, SparseVoxelOctree};
+use super::meshing::{mesh_chunk, packed_mesh};
+use super::occlusion::FaceConnectivity;
+use super::structure::{
+    ChunkKey, ChunkMeshingCfg, ChunkMeshingStats, ChunkSnapshot, MeshBufferPool, MeshPass,
+    MeshedChunk, MeshingBackend, PADDED, SparseVoxelOctree,
+};
 
-#[repr(C)]
-#[derive(ShaderType, Copy, Clone, Default)]
-pub struct Params {
-    pub origin: Vec3,
-    pub step: f32,
-    pub axis: u32,
-    pub dir: i32,
-    pub slice: u32,
-    pub _pad: u32,
-}
+/// Chunks meshed per dispatch. Must match `SLOTS` in `greedy_meshing.wgsl`.
+pub const GPU_MESHING_SLOTS: usize = 4;
+
+/// Cells uploaded per chunk snapshot; two `u32` words each.
+pub(crate) const CELLS: usize = (PADDED * PADDED * PADDED) as usize;
+/// Quads per chunk and pass before the chunk falls back to the CPU. Must match
+/// `MAX_QUADS` in `greedy_meshing.wgsl`.
+const MAX_QUADS: usize = 8192;
+pub(crate) const MAX_VERTICES: usize = MAX_QUADS * 4;
+pub(crate) const MAX_INDICES: usize = MAX_QUADS * 6;
+/// One output region per chunk slot and mesh pass.
+pub(crate) const REGIONS: usize = GPU_MESHING_SLOTS * 2;
 
 #[repr(C)]
 #[derive(ShaderType, Copy, Clone, Default)]
-pub struct VertexGpu {
-    pub pos: Vec3,
-    pub normal: Vec3,
-    pub uv: Vec2,
+pub struct Params {
+    pub slots: u32,
+    pub _pad0: u32,
+    pub _pad1: u32,
+    pub _pad2: u32,
 }
 
-const MAX_VOXELS: usize = (CHUNK_SIZE as usize) * (CHUNK_SIZE as usize) * (CHUNK_SIZE as usize);
-const MAX_QUADS: usize = MAX_VOXELS * 6;
-const MAX_VERTICES: usize = MAX_QUADS * 4;
-const MAX_INDICES: usize = MAX_QUADS * 6;
-
 #[derive(TypePath)]
 struct GreedyMeshingShader;
 
@@ -37,40 +44,235 @@ impl ComputeShader for GreedyMeshingShader {
 }
 
 /// GPU worker executing greedy meshing for chunks.
+///
+/// Each dispatch runs one workgroup per (slot, face, pass); the vertex and
+/// index buffers are split into fixed regions per (slot, pass) and read back
+/// through staging buffers together with their counts.
 #[derive(Resource)]
 pub struct GpuMeshingWorker;
 
 impl ComputeWorker for GpuMeshingWorker {
     fn build(world: &mut World) -> AppComputeWorker<Self> {
-        // Allocate large temporary arrays on the heap to avoid stack overflows
-        let voxels = Box::new([0u32; MAX_VOXELS]);
-        let vertices = Box::new([VertexGpu::default(); MAX_VERTICES]);
-        let indices = Box::new([0u32; MAX_INDICES]);
-
         AppComputeWorkerBuilder::new(world)
-            .add_storage("voxels", voxels.as_ref())
+            .add_storage("cells", &vec![0u32; GPU_MESHING_SLOTS * CELLS * 2])
             .add_uniform("params", &Params::default())
-            .add_storage("vertices", vertices.as_ref())
-            .add_storage("indices", indices.as_ref())
-            .add_storage("counts", &[0u32; 2])
+            .add_staging("vertices", &vec![0u32; REGIONS * MAX_VERTICES])
+            .add_staging("indices", &vec![0u32; REGIONS * MAX_INDICES])
+            .add_staging("counts", &vec![0u32; REGIONS * 2])
             .add_pass::<GreedyMeshingShader>(
-                [1, 1, 1],
-                &["voxels", "params", "vertices", "indices", "counts"],
+                [GPU_MESHING_SLOTS as u32, 6, 2],
+                &["cells", "params", "vertices", "indices", "counts"],
             )
             .one_shot()
             .build()
     }
 }
 
-/// Placeholder system that will dispatch the compute worker for dirty chunks.
+/// Chunks waiting for, or being meshed by, the compute worker.
+#[derive(Resource, Default)]
+pub struct GpuMeshingQueue {
+    /// (volume, key, lod, LOD seams, generation) per waiting chunk
+    pending: VecDeque<(Entity, ChunkKey, u32, u8, u64)>,
+    pending_set: HashSet<(Entity, ChunkKey)>,
+    /// (volume, key, lod, LOD seams, generation, face connectivity) per
+    /// occupied slot of the dispatch in flight
+    in_flight: Vec<(Entity, ChunkKey, u32, u8, u64, FaceConnectivity)>,
+    /// newest request per chunk; read-back meshes of older requests, or of
+    /// chunks cancelled meanwhile, are dropped
+    latest: HashMap<(Entity, ChunkKey), u64>,
+    next_generation: u64,
+    /// when the dispatch in flight was submitted
+    dispatched_at: Option<Instant>,
+    /// read-back meshes, consumed by `rebuild_dirty_chunks`
+    pub finished: Vec<MeshedChunk>,
+}
+
+impl GpuMeshingQueue {
+    /// Queue a chunk for meshing with skirts on the faces in `seams`; a chunk
+    /// already waiting keeps its place but takes the new lod and seams.
+    pub fn request(&mut self, volume: Entity, key: ChunkKey, lod: u32, seams: u8) {
+        let generation = self.next_generation;
+        self.next_generation += 1;
+        self.latest.insert((volume, key), generation);
+        if self.pending_set.insert((volume, key)) {
+            self.pending.push_back((volume, key, lod, seams, generation));
+        } else if let Some(entry) = self.pending.iter_mut().find(|e| (e.0, e.1) == (volume, key)) {
+            *entry = (volume, key, lod, seams, generation);
+        }
+    }
+
+    /// Whether a chunk is waiting or being meshed and has not been read back.
+    pub fn is_queued(&self, volume: Entity, key: ChunkKey) -> bool {
+        self.latest.contains_key(&(volume, key))
+    }
+
+    /// Drop a chunk, e.g. because it was unloaded: whether it is waiting, in
+    /// flight or read back but not attached yet.
+    pub fn cancel(&mut self, volume: Entity, key: ChunkKey) {
+        self.latest.remove(&(volume, key));
+        if self.pending_set.remove(&(volume, key)) {
+            self.pending.retain(|e| (e.0, e.1) != (volume, key));
+        }
+        self.finished.retain(|m| (m.volume, m.key) != (volume, key));
+    }
+}
+
+/// Encode a chunk snapshot as the compute shader expects: x-major cells of
+/// two words,
+/// `[tex0 | tex1 << 8 | tex2 << 16 | tex3 << 24, tex4 | tex5 << 8 | light << 16 | transparent << 20 | present << 21]`.
+pub(crate) fn encode_chunk_cells(snapshot: &ChunkSnapshot, out: &mut [u32]) {
+    for (i, (voxel, light)) in snapshot.voxels.iter().zip(snapshot.light.iter()).enumerate() {
+        let (w0, w1) = match voxel {
+            Some(v) => {
+                let t = v.textures.map(|t| t as u32 & 0xff);
+                (
+                    t[0] | t[1] << 8 | t[2] << 16 | t[3] << 24,
+                    t[4] | t[5] << 8 | (v.is_transparent() as u32) << 20 | 1 << 21,
+                )
+            }
+            None => (0, 0),
+        };
+        out[i * 2] = w0;
+        out[i * 2 + 1] = w1 | (*light as u32 & 0xf) << 16;
+    }
+}
+
+/// Switch to CPU meshing when the adapter cannot run compute shaders.
+pub fn detect_gpu_meshing(
+    mut cfg: ResMut<ChunkMeshingCfg>,
+    device: Option<Res<RenderDevice>>,
+    adapter: Option<Res<RenderAdapter>>,
+) {
+    if cfg.backend != MeshingBackend::Gpu {
+        return;
+    }
+    let supported = match (device, adapter) {
+        (Some(device), Some(adapter)) => {
+            device.limits().max_compute_workgroup_size_x != 0
+                && adapter
+                    .get_downlevel_capabilities()
+                    .flags
+                    .contains(DownlevelFlags::COMPUTE_SHADERS)
+        }
+        _ => false,
+    };
+    if !supported {
+        warn!("no compute-capable adapter, falling back to CPU chunk meshing");
+        cfg.backend = MeshingBackend::Cpu;
+    }
+}
+
+/// Reads back the previous dispatch, then uploads the next batch of pending
+/// chunks and dispatches the compute worker.
+///
+/// A chunk whose quads overflow its output region is meshed on the CPU
+/// instead, so the result is always complete.
 pub fn queue_gpu_meshing(
     mut worker: ResMut<AppComputeWorker<GpuMeshingWorker>>,
-    _octrees: Query<&SparseVoxelOctree>,
-    _pool: ResMut<MeshBufferPool>,
+    mut queue: ResMut<GpuMeshingQueue>,
+    octrees: Query<&SparseVoxelOctree>,
+    mut pool: ResMut<MeshBufferPool>,
+    mut stats: ResMut<ChunkMeshingStats>,
 ) {
-    if !worker.ready() {
+    if !queue.in_flight.is_empty() {
+        if !worker.ready() {
+            return;
+        }
+        let counts: Vec<u32> = worker.read_vec("counts");
+        let vertices: Vec<u32> = worker.read_vec("vertices");
+        let indices: Vec<u32> = worker.read_vec("indices");
+
+        let in_flight = std::mem::take(&mut queue.in_flight);
+        // the dispatch is shared, so each chunk is charged its slot's share
+        let mesh_time = queue
+            .dispatched_at
+            .take()
+            .map_or(Duration::ZERO, |t| t.elapsed() / in_flight.len() as u32);
+        for (slot, (volume, key, lod, seams, generation, connectivity)) in
+            in_flight.into_iter().enumerate()
+        {
+            // cancelled or requested again while in flight
+            if queue.latest.get(&(volume, key)) != Some(&generation) {
+                continue;
+            }
+            queue.latest.remove(&(volume, key));
+            // the volume may have been despawned while its chunks were meshed
+            let Ok(tree) = octrees.get(volume) else {
+                continue;
+            };
+            let mut meshes = [None, None];
+            for (pass_idx, pass) in [MeshPass::Opaque, MeshPass::Transparent]
+                .into_iter()
+                .enumerate()
+            {
+                let region = slot * 2 + pass_idx;
+                let vertex_count = counts[region * 2] as usize;
+                let index_count = counts[region * 2 + 1] as usize;
+                meshes[pass_idx] = if vertex_count > MAX_VERTICES || index_count > MAX_INDICES {
+                    let mut snapshot = tree.extract_chunk(key, lod);
+                    snapshot.open_sides(seams);
+                    mesh_chunk(&snapshot, &mut pool, pass)
+                } else if index_count == 0 {
+                    None
+                } else {
+                    let v0 = region * MAX_VERTICES;
+                    let i0 = region * MAX_INDICES;
+                    Some(packed_mesh(
+                        vertices[v0..v0 + vertex_count].to_vec(),
+                        indices[i0..i0 + index_count].to_vec(),
+                    ))
+                };
+            }
+            let [opaque, transparent] = meshes;
+            queue.finished.push(MeshedChunk {
+                volume,
+                key,
+                lod,
+                opaque,
+                transparent,
+                connectivity,
+                mesh_time,
+            });
+        }
+    }
+
+    if queue.pending.is_empty() {
+        return;
+    }
+
+    let mut cells = vec![0u32; GPU_MESHING_SLOTS * CELLS * 2];
+    while queue.in_flight.len() < GPU_MESHING_SLOTS {
+        let Some((volume, key, lod, seams, generation)) = queue.pending.pop_front() else {
+            break;
+        };
+        queue.pending_set.remove(&(volume, key));
+        let Ok(tree) = octrees.get(volume) else {
+            queue.latest.remove(&(volume, key));
+            continue;
+        };
+        let slot = queue.in_flight.len();
+        let started = Instant::now();
+        let mut snapshot = tree.extract_chunk(key, lod);
+        snapshot.open_sides(seams);
+        encode_chunk_cells(&snapshot, &mut cells[slot * CELLS * 2..(slot + 1) * CELLS * 2]);
+        let connectivity = FaceConnectivity::from_snapshot(&snapshot);
+        stats.extract_time += started.elapsed().as_secs_f32();
+        stats.extracted += 1;
+        queue.in_flight.push((volume, key, lod, seams, generation, connectivity));
+    }
+    if queue.in_flight.is_empty() {
         return;
     }
-    // TODO: populate the worker buffers with chunk data before dispatching.
+
+    worker.write_slice("cells", &cells);
+    worker.write(
+        "params",
+        &Params {
+            slots: queue.in_flight.len() as u32,
+            ..default()
+        },
+    );
+    worker.write_slice("counts", &[0u32; REGIONS * 2]);
     worker.execute();
+    queue.dispatched_at = Some(Instant::now());
 }
@@ -0,0 +1,632 @@
+use crate::plugins::environment::systems::voxels::lighting::relight;
+use crate::plugins::environment::systems::voxels::material::{
+    ATTRIBUTE_PACKED_VOXEL, FACE_NORMALS, PackedVertex,
+};
+use crate::plugins::environment::systems::voxels::meshing::mesh_chunk;
+use crate::plugins::environment::systems::voxels::meshing_gpu::{
+    CELLS, GPU_MESHING_SLOTS, MAX_INDICES, MAX_VERTICES, REGIONS, encode_chunk_cells,
+};
+use crate::plugins::environment::systems::voxels::structure::*;
+use bevy::math::DVec3;
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, VertexAttributeValues};
+use bevy::tasks::block_on;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::BTreeSet;
+use wgpu::util::DeviceExt;
+
+/// One unit square of chunk surface. Greedy quads are split back into these
+/// so meshes can be compared independently of merging and vertex order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct UnitFace {
+    pub face: u8,
+    /// Minimum corner on the face plane, chunk-local.
+    pub corner: [i32; 3],
+    pub layer: u8,
+    pub light: u8,
+    pub ao: [u8; 4],
+}
+
+pub type FaceSet = BTreeSet<UnitFace>;
+
+/// A chunk to mesh, with the octree that supplies its border and light.
+pub struct ParityCase {
+    pub name: String,
+    pub tree: SparseVoxelOctree,
+    pub key: ChunkKey,
+    /// Unit faces per pass (opaque, transparent) for the hand-built cases.
+    pub expected: Option<[usize; 2]>,
+}
+
+impl ParityCase {
+    /// `voxels` are chunk-local and may include the one-voxel border.
+    fn new(
+        name: impl Into<String>,
+        voxels: Vec<(IVec3, Voxel)>,
+        expected: Option<[usize; 2]>,
+    ) -> Self {
+        let mut tree = SparseVoxelOctree::new(6, 64.0, false, false, false);
+        let key = tree.world_to_chunk(DVec3::splat(0.5));
+        let origin = tree.chunk_origin_world(key);
+        let step = tree.get_spacing_at_depth(tree.max_depth);
+        let batch: Vec<(DVec3, Voxel)> = voxels
+            .into_iter()
+            .map(|(p, v)| (origin + (p.as_dvec3() + DVec3::splat(0.5)) * step, v))
+            .collect();
+        tree.insert_batch(&batch);
+        relight(&mut tree);
+        Self {
+            name: name.into(),
+            tree,
+            key,
+            expected,
+        }
+    }
+
+    fn snapshot(&self) -> ChunkSnapshot {
+        self.tree.extract_chunk(self.key, 0)
+    }
+}
+
+const STONE: usize = 2;
+const GLOW: usize = 4;
+const GLASS: usize = 6;
+const WATER: usize = 7;
+const LEAVES: usize = 8;
+
+fn uniform(texture: usize) -> Voxel {
+    Voxel {
+        textures: [texture; 6],
+    }
+}
+
+fn cube(min: IVec3, max: IVec3) -> impl Iterator<Item = IVec3> {
+    (min.x..max.x).flat_map(move |x| {
+        (min.y..max.y).flat_map(move |y| (min.z..max.z).map(move |z| IVec3::new(x, y, z)))
+    })
+}
+
+/// Hand-built edge cases with known face counts.
+pub fn adversarial_cases() -> Vec<ParityCase> {
+    let n = CHUNK_SIZE;
+    let stone = uniform(STONE);
+    let at = |p: [i32; 3], v: Voxel| (IVec3::from(p), v);
+    let mixed = Voxel {
+        textures: [0, 1, 3, 2, 5, 3],
+    };
+
+    vec![
+        ParityCase::new("empty", vec![], Some([0, 0])),
+        ParityCase::new("single", vec![at([5, 5, 5], stone)], Some([6, 0])),
+        ParityCase::new(
+            "pair",
+            vec![at([5, 5, 5], stone), at([6, 5, 5], stone)],
+            Some([10, 0]),
+        ),
+        ParityCase::new(
+            "full",
+            cube(IVec3::ZERO, IVec3::splat(n)).map(|p| (p, stone)).collect(),
+            Some([6 * 256, 0]),
+        ),
+        ParityCase::new(
+            "mixed_face_slab",
+            cube(IVec3::ZERO, IVec3::new(n, 1, n)).map(|p| (p, mixed)).collect(),
+            Some([2 * 256 + 4 * 16, 0]),
+        ),
+        // Nothing merges; overflows the GPU output region.
+        ParityCase::new(
+            "checkerboard",
+            cube(IVec3::ZERO, IVec3::splat(n))
+                .filter(|p| (p.x + p.y + p.z) % 2 == 0)
+                .map(|p| (p, stone))
+                .collect(),
+            Some([2048 * 6, 0]),
+        ),
+        ParityCase::new(
+            "glass_cube",
+            cube(IVec3::splat(4), IVec3::splat(6)).map(|p| (p, uniform(GLASS))).collect(),
+            Some([0, 24]),
+        ),
+        ParityCase::new(
+            "water_against_glass",
+            vec![at([5, 5, 5], uniform(GLASS)), at([6, 5, 5], uniform(WATER))],
+            Some([0, 12]),
+        ),
+        ParityCase::new(
+            "glass_on_stone",
+            vec![at([5, 5, 5], stone), at([5, 6, 5], uniform(GLASS))],
+            Some([6, 5]),
+        ),
+        // The mesher also emits the inward faces of border voxels.
+        ParityCase::new("border_voxel", vec![at([-1, 5, 5], stone)], Some([1, 0])),
+        ParityCase::new(
+            "border_shell",
+            cube(IVec3::splat(-1), IVec3::splat(n + 1))
+                .filter(|p| p.min_element() < 0 || p.max_element() >= n)
+                .map(|p| (p, stone))
+                .collect(),
+            Some([6 * 256, 0]),
+        ),
+        ParityCase::new(
+            "lit_cave",
+            cube(IVec3::ZERO, IVec3::splat(n))
+                .filter(|p| p.min_element() == 0 || p.max_element() == n - 1)
+                .map(|p| (p, stone))
+                .chain([at([8, 8, 8], uniform(GLOW))])
+                .collect(),
+            Some([6 * 256 + 6 * 14 * 14 + 6, 0]),
+        ),
+    ]
+}
+
+/// Random chunks, border included, drawn from a small palette so that greedy
+/// merging, transparency and emitters all come up.
+pub fn random_cases(count: usize, seed: u64) -> Vec<ParityCase> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let choices = [STONE, 0, 1, 3, GLOW, GLASS, WATER, LEAVES];
+    (0..count)
+        .map(|i| {
+            let palette: Vec<Voxel> = (0..rng.gen_range(1..=4))
+                .map(|_| {
+                    if rng.gen_bool(0.2) {
+                        Voxel {
+                            textures: std::array::from_fn(|_| choices[rng.gen_range(0..4)]),
+                        }
+                    } else {
+                        uniform(choices[rng.gen_range(0..choices.len())])
+                    }
+                })
+                .collect();
+            let density = rng.gen_range(0.05..0.95);
+            let voxels = cube(IVec3::splat(-1), IVec3::splat(CHUNK_SIZE + 1))
+                .filter_map(|p| {
+                    rng.gen_bool(density)
+                        .then(|| (p, palette[rng.gen_range(0..palette.len())]))
+                })
+                .collect::<Vec<_>>();
+            ParityCase::new(format!("random_{i}"), voxels, None)
+        })
+        .collect()
+}
+
+/// Split packed quads into unit faces. Overlapping coverage, inconsistent
+/// quads and back-facing triangles are reported in `problems`.
+pub fn face_set(vertices: &[u32], indices: &[u32], problems: &mut Vec<String>) -> FaceSet {
+    let mut faces = FaceSet::new();
+    let vertices: Vec<PackedVertex> = vertices.iter().map(|p| PackedVertex::unpack(*p)).collect();
+
+    for quad in vertices.chunks_exact(4) {
+        let first = quad[0];
+        if quad
+            .iter()
+            .any(|v| v.face != first.face || v.layer != first.layer || v.light != first.light)
+        {
+            problems.push(format!("quad with mixed attributes at {}", first.position));
+            continue;
+        }
+        let axis = first.face as usize / 2;
+        let (u_axis, v_axis) = [(1, 2), (2, 0), (0, 1)][axis];
+        let base = first.position.as_ivec3();
+        let width = quad[1].position.as_ivec3()[u_axis] - base[u_axis];
+        let height = quad[3].position.as_ivec3()[v_axis] - base[v_axis];
+        if width <= 0 || height <= 0 {
+            problems.push(format!("degenerate quad at {base}"));
+            continue;
+        }
+        let ao = [quad[0].ao, quad[1].ao, quad[2].ao, quad[3].ao];
+        for du in 0..width {
+            for dv in 0..height {
+                let mut corner = base;
+                corner[u_axis] += du;
+                corner[v_axis] += dv;
+                let unit = UnitFace {
+                    face: first.face,
+                    corner: corner.to_array(),
+                    layer: first.layer,
+                    light: first.light,
+                    ao,
+                };
+                if !faces.insert(unit) {
+                    problems.push(format!("face {} at {corner} covered twice", first.face));
+                }
+            }
+        }
+    }
+
+    for tri in indices.chunks_exact(3) {
+        let Some(v) = tri
+            .iter()
+            .map(|i| vertices.get(*i as usize))
+            .collect::<Option<Vec<_>>>()
+        else {
+            problems.push("index out of range".into());
+            continue;
+        };
+        let [p0, p1, p2] = [0, 1, 2].map(|k| v[k].position.as_vec3());
+        let normal = FACE_NORMALS[v[0].face as usize];
+        if (p1 - p0).cross(p2 - p0).dot(normal) <= 0.0 {
+            problems.push(format!("triangle at {p0} faces away from face {}", v[0].face));
+        }
+    }
+    faces
+}
+
+/// Every visible unit face without merging, following the same rules as
+/// `mesh_chunk`. The golden reference for the greedy output.
+pub fn reference_faces(snapshot: &ChunkSnapshot, pass: MeshPass) -> FaceSet {
+    let n = CHUNK_SIZE;
+    let get = |p: IVec3| snapshot.voxel(p);
+    let solid = |p: IVec3| get(p).is_some_and(|v| !v.is_transparent()) as u8;
+
+    let mut faces = FaceSet::new();
+    for face in 0..6usize {
+        let axis = face / 2;
+        let dir = if face % 2 == 1 { 1 } else { -1 };
+        let (u_axis, v_axis) = [(1, 2), (2, 0), (0, 1)][axis];
+        for slice in 0..=n {
+            for u in 0..n {
+                for v in 0..n {
+                    let mut cell = IVec3::ZERO;
+                    cell[axis] = slice + if dir == 1 { -1 } else { 0 };
+                    cell[u_axis] = u;
+                    cell[v_axis] = v;
+                    let mut front = cell;
+                    front[axis] += dir;
+
+                    let Some(vox) = get(cell) else { continue };
+                    let other = get(front);
+                    let visible = match pass {
+                        MeshPass::Opaque => {
+                            !vox.is_transparent() && other.is_none_or(|o| o.is_transparent())
+                        }
+                        MeshPass::Transparent => {
+                            vox.is_transparent()
+                                && other.is_none_or(|o| o.is_transparent() && o != vox)
+                        }
+                    };
+                    if !visible {
+                        continue;
+                    }
+
+                    let ao = [(-1, -1), (1, -1), (1, 1), (-1, 1)].map(|(du, dv)| {
+                        let mut side1 = front;
+                        side1[u_axis] += du;
+                        let mut side2 = front;
+                        side2[v_axis] += dv;
+                        let mut diagonal = side1;
+                        diagonal[v_axis] += dv;
+                        let (s1, s2) = (solid(side1), solid(side2));
+                        if s1 == 1 && s2 == 1 {
+                            0
+                        } else {
+                            3 - (s1 + s2 + solid(diagonal))
+                        }
+                    });
+                    let mut corner = cell;
+                    corner[axis] = slice;
+                    faces.insert(UnitFace {
+                        face: face as u8,
+                        corner: corner.to_array(),
+                        layer: vox.textures[face] as u8,
+                        light: snapshot.light(front),
+                        ao,
+                    });
+                }
+            }
+        }
+    }
+    faces
+}
+
+fn packed_parts(mesh: Option<Mesh>) -> (Vec<u32>, Vec<u32>) {
+    let Some(mesh) = mesh else {
+        return (Vec::new(), Vec::new());
+    };
+    match (mesh.attribute(ATTRIBUTE_PACKED_VOXEL), mesh.indices()) {
+        (Some(VertexAttributeValues::Uint32(v)), Some(Indices::U32(i))) => (v.clone(), i.clone()),
+        _ => (Vec::new(), Vec::new()),
+    }
+}
+
+/// The WGSL greedy mesher on a standalone wgpu device, preferring a software
+/// adapter so the harness also runs on machines without a GPU.
+pub struct GpuMesher {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    pipeline: wgpu::ComputePipeline,
+    pub adapter: String,
+}
+
+impl GpuMesher {
+    pub fn new() -> Result<Self, String> {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
+        let adapter = [true, false]
+            .into_iter()
+            .find_map(|force_fallback_adapter| {
+                block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+                    force_fallback_adapter,
+                    ..default()
+                }))
+            })
+            .ok_or("no wgpu adapter available")?;
+        if !adapter
+            .get_downlevel_capabilities()
+            .flags
+            .contains(wgpu::DownlevelFlags::COMPUTE_SHADERS)
+        {
+            return Err(format!("{} cannot run compute shaders", adapter.get_info().name));
+        }
+
+        let (device, queue) = block_on(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                label: Some("mesh parity"),
+                required_features: wgpu::Features::empty(),
+                required_limits: adapter.limits(),
+                memory_hints: wgpu::MemoryHints::default(),
+            },
+            None,
+        ))
+        .map_err(|e| e.to_string())?;
+
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("greedy_meshing"),
+            source: wgpu::ShaderSource::Wgsl(
+                include_str!("../../../../../assets/shaders/greedy_meshing.wgsl").into(),
+            ),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("greedy_meshing"),
+            layout: None,
+            module: &module,
+            entry_point: Some("main"),
+            compilation_options: default(),
+            cache: None,
+        });
+        if let Some(err) = block_on(device.pop_error_scope()) {
+            return Err(format!("greedy_meshing.wgsl: {err}"));
+        }
+
+        Ok(Self {
+            device,
+            queue,
+            pipeline,
+            adapter: adapter.get_info().name,
+        })
+    }
+
+    /// Mesh up to [`GPU_MESHING_SLOTS`] cases in one dispatch. Returns packed
+    /// (vertices, indices) per case and pass, or `None` where the output
+    /// region overflowed.
+    pub fn mesh(&self, cases: &[&ParityCase]) -> Vec<[Option<(Vec<u32>, Vec<u32>)>; 2]> {
+        assert!(cases.len() <= GPU_MESHING_SLOTS);
+        let mut cells = vec![0u32; GPU_MESHING_SLOTS * CELLS * 2];
+        for (slot, case) in cases.iter().enumerate() {
+            encode_chunk_cells(
+                &case.snapshot(),
+                &mut cells[slot * CELLS * 2..(slot + 1) * CELLS * 2],
+            );
+        }
+
+        let storage = wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC;
+        let init = |label, contents: &[u32], usage| {
+            self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(label),
+                contents: bytemuck::cast_slice(contents),
+                usage,
+            })
+        };
+        let cells = init("cells", &cells, wgpu::BufferUsages::STORAGE);
+        let params = init(
+            "params",
+            &[cases.len() as u32, 0, 0, 0],
+            wgpu::BufferUsages::UNIFORM,
+        );
+        let vertices = init("vertices", &vec![0; REGIONS * MAX_VERTICES], storage);
+        let indices = init("indices", &vec![0; REGIONS * MAX_INDICES], storage);
+        let counts = init("counts", &[0; REGIONS * 2], storage);
+
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("greedy_meshing"),
+            layout: &self.pipeline.get_bind_group_layout(0),
+            entries: &[&cells, &params, &vertices, &indices, &counts]
+                .iter()
+                .enumerate()
+                .map(|(binding, buffer)| wgpu::BindGroupEntry {
+                    binding: binding as u32,
+                    resource: buffer.as_entire_binding(),
+                })
+                .collect::<Vec<_>>(),
+        });
+
+        let mut encoder = self.device.create_command_encoder(&default());
+        {
+            let mut pass = encoder.begin_compute_pass(&default());
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups(GPU_MESHING_SLOTS as u32, 6, 2);
+        }
+        let readback: Vec<wgpu::Buffer> = [&vertices, &indices, &counts]
+            .into_iter()
+            .map(|src| {
+                let dst = self.device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("readback"),
+                    size: src.size(),
+                    usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                });
+                encoder.copy_buffer_to_buffer(src, 0, &dst, 0, src.size());
+                dst
+            })
+            .collect();
+        self.queue.submit([encoder.finish()]);
+
+        for buffer in &readback {
+            buffer.slice(..).map_async(wgpu::MapMode::Read, |_| {});
+        }
+        self.device.poll(wgpu::Maintain::Wait);
+        let [vertices, indices, counts] = [0, 1, 2].map(|i| {
+            let data: Vec<u32> = readback[i]
+                .slice(..)
+                .get_mapped_range()
+                .chunks_exact(4)
+                .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect();
+            readback[i].unmap();
+            data
+        });
+
+        (0..cases.len())
+            .map(|slot| {
+                [0, 1].map(|pass| {
+                    let region = slot * 2 + pass;
+                    let vertex_count = counts[region * 2] as usize;
+                    let index_count = counts[region * 2 + 1] as usize;
+                    if vertex_count > MAX_VERTICES || index_count > MAX_INDICES {
+                        return None;
+                    }
+                    let v0 = region * MAX_VERTICES;
+                    let i0 = region * MAX_INDICES;
+                    Some((
+                        vertices[v0..v0 + vertex_count].to_vec(),
+                        indices[i0..i0 + index_count].to_vec(),
+                    ))
+                })
+            })
+            .collect()
+    }
+}
+
+/// Outcome of a parity run; `failures` has one line per differing chunk/pass.
+pub struct ParityReport {
+    pub cases: usize,
+    pub failures: Vec<String>,
+    /// Adapter the GPU half ran on, or why it was skipped.
+    pub gpu: Result<String, String>,
+    /// Chunks whose GPU output overflowed and would be meshed on the CPU.
+    pub overflowed: Vec<String>,
+}
+
+fn describe(faces: &FaceSet) -> String {
+    faces
+        .iter()
+        .take(3)
+        .map(|f| format!("{f:?}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Check `mesh_chunk` against the unmerged reference (and the expected counts
+/// of the hand-built cases), then compare it with the WGSL mesher by covered
+/// face set when a wgpu adapter is available.
+pub fn run_parity(random: usize, seed: u64, gpu: bool) -> ParityReport {
+    let mut cases = adversarial_cases();
+    cases.extend(random_cases(random, seed));
+
+    let mut failures = Vec::new();
+    let mut pool = MeshBufferPool::default();
+    let passes = [MeshPass::Opaque, MeshPass::Transparent];
+
+    // CPU half: golden test of the greedy mesher.
+    let cpu: Vec<[FaceSet; 2]> = cases
+        .iter()
+        .map(|case| {
+            let snapshot = case.snapshot();
+            passes.map(|pass| {
+                let mesh = mesh_chunk(&snapshot, &mut pool, pass);
+                let (vertices, indices) = packed_parts(mesh);
+                let mut problems = Vec::new();
+                let faces = face_set(&vertices, &indices, &mut problems);
+                let reference = reference_faces(&snapshot, pass);
+                for problem in problems {
+                    failures.push(format!("{} {pass:?} cpu: {problem}", case.name));
+                }
+                if faces != reference {
+                    failures.push(format!(
+                        "{} {pass:?} cpu differs from reference: missing [{}] extra [{}]",
+                        case.name,
+                        describe(&reference.difference(&faces).copied().collect()),
+                        describe(&faces.difference(&reference).copied().collect()),
+                    ));
+                }
+                faces
+            })
+        })
+        .collect();
+    for (case, faces) in cases.iter().zip(&cpu) {
+        let counts = [faces[0].len(), faces[1].len()];
+        if let Some(expected) = case.expected.filter(|e| *e != counts) {
+            failures.push(format!(
+                "{}: expected {expected:?} unit faces, cpu produced {counts:?}",
+                case.name
+            ));
+        }
+    }
+
+    // GPU half.
+    let mut overflowed = Vec::new();
+    let gpu = if gpu {
+        GpuMesher::new()
+    } else {
+        Err("disabled".into())
+    };
+    if let Ok(mesher) = &gpu {
+        let refs: Vec<&ParityCase> = cases.iter().collect();
+        for (batch, cpu_batch) in refs
+            .chunks(GPU_MESHING_SLOTS)
+            .zip(cpu.chunks(GPU_MESHING_SLOTS))
+        {
+            for ((case, results), cpu_faces) in batch.iter().zip(mesher.mesh(batch)).zip(cpu_batch) {
+                for ((pass, result), expected) in passes.iter().zip(results).zip(cpu_faces) {
+                    let Some((vertices, indices)) = result else {
+                        overflowed.push(format!("{} {pass:?}", case.name));
+                        continue;
+                    };
+                    let mut problems = Vec::new();
+                    let faces = face_set(&vertices, &indices, &mut problems);
+                    for problem in problems {
+                        failures.push(format!("{} {pass:?} gpu: {problem}", case.name));
+                    }
+                    if &faces != expected {
+                        failures.push(format!(
+                            "{} {pass:?} gpu differs from cpu: {} only on cpu [{}], {} only on gpu [{}]",
+                            case.name,
+                            expected.difference(&faces).count(),
+                            describe(&expected.difference(&faces).copied().collect()),
+                            faces.difference(expected).count(),
+                            describe(&faces.difference(expected).copied().collect()),
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    ParityReport {
+        cases: cases.len(),
+        failures,
+        gpu: gpu.map(|m| m.adapter),
+        overflowed,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cpu_mesher_matches_reference() {
+        let report = run_parity(64, 0, false);
+        assert!(report.failures.is_empty(), "{}", report.failures.join("\n"));
+    }
+
+    #[test]
+    fn adversarial_cases_match_expected_counts() {
+        let cases = adversarial_cases();
+        assert!(cases.iter().all(|case| case.expected.is_some()));
+        let report = run_parity(0, 0, false);
+        assert_eq!(report.cases, cases.len());
+        assert!(report.failures.is_empty(), "{}", report.failures.join("\n"));
+    }
+}
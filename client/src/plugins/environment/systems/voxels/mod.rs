@@ -1,13 +1,20 @@
+pub mod body;
 pub mod debug;
+pub mod features;
+pub mod formats;
 pub mod helper;
+pub mod lighting;
 pub mod octree;
 pub mod structure;
 
 mod chunk;
 pub mod culling;
 pub mod lod;
+pub mod material;
 mod meshing;
 pub mod meshing_gpu;
+pub mod meshing_parity;
+pub mod occlusion;
 pub mod queue_systems;
 pub mod render_chunks;
 pub mod atlas;
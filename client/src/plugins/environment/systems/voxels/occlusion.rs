@@ -0,0 +1,227 @@
+use crate::plugins::environment::systems::voxels::structure::*;
+use bevy::math::{Affine3A, DVec3};
+use bevy::prelude::*;
+use bevy::render::primitives::{Aabb, Frustum};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// Turning the camera further than this (as the cosine of the angle) since
+/// the last pass recomputes which chunks are reachable.
+const TURN_THRESHOLD: f32 = 0.97;
+
+/// Which faces of a chunk can see each other through its non-opaque cells,
+/// one bit per (face, face) pair in face order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FaceConnectivity(u64);
+
+impl FaceConnectivity {
+    /// Every face sees every other one, as in an empty chunk.
+    pub const ALL: Self = Self((1 << 36) - 1);
+
+    pub fn connects(self, from: usize, to: usize) -> bool {
+        self.0 & 1 << (from * 6 + to) != 0
+    }
+
+    /// Connect every pair of faces in the `faces` bit mask.
+    fn join(&mut self, faces: u8) {
+        for a in 0..6 {
+            for b in 0..6 {
+                if faces & 1 << a != 0 && faces & 1 << b != 0 {
+                    self.0 |= 1 << (a * 6 + b);
+                }
+            }
+        }
+    }
+
+    /// Flood fill the non-opaque cells of the chunk; every region connects
+    /// the faces it touches.
+    pub fn from_snapshot(snapshot: &ChunkSnapshot) -> Self {
+        let n = CHUNK_SIZE;
+        let index = |p: IVec3| ((p.x * n + p.y) * n + p.z) as usize;
+        let open = |p: IVec3| !snapshot.voxel(p).is_some_and(|v| !v.is_transparent());
+
+        let mut connectivity = Self(0);
+        let mut visited = vec![false; (n * n * n) as usize];
+        let mut stack = Vec::new();
+        for x in 0..n {
+            for y in 0..n {
+                for z in 0..n {
+                    let start = IVec3::new(x, y, z);
+                    if visited[index(start)] || !open(start) {
+                        continue;
+                    }
+                    visited[index(start)] = true;
+                    stack.push(start);
+                    let mut faces = 0u8;
+                    while let Some(p) = stack.pop() {
+                        for (face, (dx, dy, dz)) in NEIGHBOR_OFFSETS.iter().enumerate() {
+                            let q = p + IVec3::new(*dx as i32, *dy as i32, *dz as i32);
+                            if q.cmplt(IVec3::ZERO).any() || q.cmpge(IVec3::splat(n)).any() {
+                                faces |= 1 << face;
+                            } else if !visited[index(q)] && open(q) {
+                                visited[index(q)] = true;
+                                stack.push(q);
+                            }
+                        }
+                    }
+                    connectivity.join(faces);
+                }
+            }
+        }
+        connectivity
+    }
+}
+
+/// CPU occlusion culling through chunk face connectivity.
+///
+/// Starting at the camera chunk, a breadth-first walk steps into neighbouring
+/// chunks that lie in the frustum, only through faces connected to the face it
+/// entered by and never back towards the camera. Chunks within
+/// `ChunkCullingCfg::occlusion_distance_chunks` the walk cannot reach are
+/// neither meshed nor drawn. Chunks that have not been meshed yet count as
+/// fully open.
+///
+/// Each octree entity has its own, walked in the volume's octree space.
+#[derive(Component, Default)]
+pub struct ChunkOcclusion {
+    /// face connectivity of every meshed chunk
+    connectivity: HashMap<ChunkKey, FaceConnectivity>,
+    /// connectivity changed since the last walk
+    stale: bool,
+    /// camera chunk and forward direction, in octree space, of the last walk
+    camera: Option<(ChunkKey, Vec3)>,
+    radius: i32,
+    reachable: HashSet<ChunkKey>,
+    /// bumped whenever the reachable set changes
+    pub revision: u64,
+}
+
+impl ChunkOcclusion {
+    pub fn set_connectivity(&mut self, key: ChunkKey, connectivity: FaceConnectivity) {
+        if self.connectivity.insert(key, connectivity) != Some(connectivity) {
+            self.stale = true;
+        }
+    }
+
+    /// Forget an unloaded chunk; it counts as fully open again.
+    pub fn forget(&mut self, key: ChunkKey) {
+        if self.connectivity.remove(&key).is_some() {
+            self.stale = true;
+        }
+    }
+
+    /// Whether the chunk is close enough to be tested and hidden behind other
+    /// chunks. Chunks further out are never reported as occluded.
+    pub fn is_occluded(&self, key: ChunkKey) -> bool {
+        let Some((centre, _)) = self.camera else {
+            return false;
+        };
+        chunk_distance(key, centre) <= self.radius && !self.reachable.contains(&key)
+    }
+}
+
+/// Distance between two chunks in chunks, along the furthest axis.
+fn chunk_distance(a: ChunkKey, b: ChunkKey) -> i32 {
+    (a.0 - b.0).abs().max((a.1 - b.1).abs()).max((a.2 - b.2).abs())
+}
+
+/// Whether any part of the chunk lies inside the camera frustum, which is
+/// relative to the floating origin at world position `origin`.
+pub fn chunk_in_frustum(
+    tree: &SparseVoxelOctree,
+    volume: &VolumeTransform,
+    key: ChunkKey,
+    frustum: &Frustum,
+    origin: DVec3,
+) -> bool {
+    let half = CHUNK_SIZE as f32 * tree.get_spacing_at_depth(tree.max_depth) as f32 * 0.5;
+    let centre = (volume.to_world(tree.chunk_center_world(key)) - origin).as_vec3();
+    let world_from_chunk = Affine3A::from_mat3_translation(volume.0.matrix3.as_mat3(), centre);
+    let aabb = Aabb::from_min_max(Vec3::splat(-half), Vec3::splat(half));
+    frustum.intersects_obb(&aabb, &world_from_chunk, true, false)
+}
+
+/// Walk the chunks reachable from the camera whenever the camera changes
+/// chunk, turns noticeably, or a chunk's connectivity changes, for every
+/// volume.
+pub fn update_chunk_occlusion(
+    cfg: Res<ChunkCullingCfg>,
+    camera: Res<StreamingCamera>,
+    cam_q: Query<(&GlobalTransform, &Frustum), With<Camera>>,
+    mut volumes: Query<(&SparseVoxelOctree, &VolumeTransform, &mut ChunkOcclusion)>,
+) {
+    let Ok((cam_tf, frustum)) = cam_q.get_single() else {
+        return;
+    };
+    for (tree, volume, mut occlusion) in &mut volumes {
+        let centre = tree.world_to_chunk(volume.to_local(camera.position));
+        let forward = volume
+            .direction_to_local(cam_tf.forward().as_dvec3())
+            .normalize_or_zero()
+            .as_vec3();
+        let radius = cfg.occlusion_distance_chunks;
+
+        let moved = match occlusion.camera {
+            Some((prev, prev_forward)) => {
+                prev != centre || prev_forward.dot(forward) < TURN_THRESHOLD
+            }
+            None => true,
+        };
+        if !moved && !occlusion.stale && occlusion.radius == radius {
+            continue;
+        }
+
+        let mut reachable = HashSet::from([centre]);
+        let mut queue = VecDeque::from([(centre, None::<usize>, 0u8)]);
+        while let Some((key, entered, directions)) = queue.pop_front() {
+            let connectivity = occlusion
+                .connectivity
+                .get(&key)
+                .copied()
+                .unwrap_or(FaceConnectivity::ALL);
+            for (face, (dx, dy, dz)) in NEIGHBOR_OFFSETS.iter().enumerate() {
+                // never turn back towards the camera
+                if directions & 1 << (face ^ 1) != 0 {
+                    continue;
+                }
+                if entered.is_some_and(|from| !connectivity.connects(from, face)) {
+                    continue;
+                }
+                let next = ChunkKey(key.0 + *dx as i32, key.1 + *dy as i32, key.2 + *dz as i32);
+                if chunk_distance(next, centre) > radius
+                    || reachable.contains(&next)
+                    || !chunk_in_frustum(tree, volume, next, frustum, camera.origin)
+                {
+                    continue;
+                }
+                reachable.insert(next);
+                queue.push_back((next, Some(face ^ 1), directions | 1 << face));
+            }
+        }
+
+        occlusion.camera = Some((centre, forward));
+        occlusion.stale = false;
+        occlusion.radius = radius;
+        if occlusion.reachable != reachable {
+            occlusion.reachable = reachable;
+            occlusion.revision += 1;
+        }
+    }
+}
+
+/// Hide spawned chunks the camera cannot see through the chunks in between.
+pub fn hide_occluded_chunks(
+    volumes: Query<&ChunkOcclusion>,
+    mut chunks: Query<(&Chunk, &mut Visibility)>,
+) {
+    for (chunk, mut visibility) in &mut chunks {
+        let occluded = volumes
+            .get(chunk.volume)
+            .is_ok_and(|occlusion| occlusion.is_occluded(chunk.key));
+        let wanted = if occluded {
+            Visibility::Hidden
+        } else {
+            Visibility::Inherited
+        };
+        visibility.set_if_neq(wanted);
+    }
+}
@@ -1,5 +1,5 @@
 use crate::plugins::environment::systems::voxels::structure::{
-    ChunkKey, DirtyVoxel, OctreeNode, Ray, SparseVoxelOctree, Voxel, AABB, CHUNK_SIZE,
+    ChunkKey, DirtyVoxel, OctreeNode, Prefab, Ray, SparseVoxelOctree, Voxel, AABB, CHUNK_SIZE,
     NEIGHBOR_OFFSETS,
 };
 use bevy::asset::Assets;
@@ -8,6 +8,7 @@ use bevy::prelude::*;
 use bevy::render::mesh::{Indices, PrimitiveTopology, VertexAttributeValues};
 use bevy::render::render_asset::RenderAssetUsages;
 use bincode;
+use serde::Deserialize;
 use std::collections::{HashMap, HashSet};
 use std::io;
 use std::path::Path;
@@ -16,7 +17,7 @@ impl SparseVoxelOctree {
     /// Creates a new octree with the specified max depth, size, and wireframe visibility.
     pub fn new(
         max_depth: u32,
-        size: f32,
+        size: f64,
         show_wireframe: bool,
         show_world_grid: bool,
         show_chunks: bool,
@@ -25,15 +26,18 @@ impl SparseVoxelOctree {
             root: OctreeNode::new(),
             max_depth,
             size,
-            center: Vec3::ZERO,
+            center: DVec3::ZERO,
             show_wireframe,
             show_world_grid,
             dirty: Vec::new(),
             dirty_chunks: Default::default(),
             occupied_chunks: Default::default(),
+            light: Default::default(),
+            voxel_count: 0,
+            voxel_sum: DVec3::ZERO,
         }
     }
-    pub fn insert(&mut self, position: Vec3, voxel: Voxel) {
+    pub fn insert(&mut self, position: DVec3, voxel: Voxel) {
         // Align to the center of the voxel at max_depth
         let mut aligned = self.normalize_to_voxel_at_depth(position, self.max_depth);
         let mut world_center = self.denormalize_voxel_center(aligned);
@@ -53,21 +57,78 @@ impl SparseVoxelOctree {
         self.dirty_chunks.insert(key);
         self.mark_neighbor_chunks_dirty(position);
         self.occupied_chunks.insert(key);
+        self.light.note_change(self.world_to_voxel(position));
 
-        Self::insert_recursive(&mut self.root, aligned, voxel, self.max_depth);
+        if Self::insert_recursive(&mut self.root, aligned, voxel, self.max_depth) {
+            self.voxel_count += 1;
+            self.voxel_sum += world_center;
+        }
+        Self::refresh_lod_path(&mut self.root, aligned, self.max_depth);
+    }
+
+    /// Insert many voxels at once. The root is grown once to fit the whole
+    /// batch and chunk bookkeeping is done once per touched chunk instead of
+    /// once per voxel.
+    pub fn insert_batch(&mut self, voxels: &[(DVec3, Voxel)]) {
+        if voxels.is_empty() {
+            return;
+        }
+
+        let (min, max) = voxels.iter().fold(
+            (DVec3::splat(f64::MAX), DVec3::splat(f64::MIN)),
+            |(lo, hi), (pos, _)| (lo.min(*pos), hi.max(*pos)),
+        );
+        for corner in [min, max] {
+            loop {
+                let aligned = self.normalize_to_voxel_at_depth(corner, self.max_depth);
+                let world_center = self.denormalize_voxel_center(aligned);
+                if self.contains(world_center.x, world_center.y, world_center.z) {
+                    break;
+                }
+                self.expand_root(world_center.x, world_center.y, world_center.z);
+            }
+        }
+
+        let mut touched = HashSet::new();
+        for (position, voxel) in voxels {
+            let aligned = self.normalize_to_voxel_at_depth(*position, self.max_depth);
+            self.dirty.push(DirtyVoxel { position: aligned });
+            touched.insert(self.world_to_chunk(*position));
+            self.light.note_change(self.world_to_voxel(*position));
+            if Self::insert_recursive(&mut self.root, aligned, *voxel, self.max_depth) {
+                self.voxel_count += 1;
+                self.voxel_sum += self.denormalize_voxel_center(aligned);
+            }
+            Self::refresh_lod_path(&mut self.root, aligned, self.max_depth);
+        }
+
+        self.occupied_chunks.extend(touched.iter().copied());
+        for key in touched {
+            self.dirty_chunks.insert(key);
+            self.mark_neighbors_dirty_from_key(key);
+        }
+    }
+
+    /// Insert every voxel of `prefab`, offset from the voxel containing `anchor`.
+    pub fn insert_prefab(&mut self, anchor: DVec3, prefab: &Prefab) {
+        let origin = self.world_to_voxel(anchor);
+        let voxels: Vec<(DVec3, Voxel)> = prefab
+            .voxels
+            .iter()
+            .map(|(offset, voxel)| (self.voxel_to_world(origin + *offset), *voxel))
+            .collect();
+        self.insert_batch(&voxels);
     }
 
+    /// Returns whether the cell was empty before.
     fn insert_recursive(
         mut node: &mut OctreeNode,
-        mut position: Vec3,
+        mut position: DVec3,
         voxel: Voxel,
         mut depth: u32,
-    ) {
-        let epsilon = 1e-6;
+    ) -> bool {
         while depth > 0 {
-            let index = ((position.x >= 0.5 - epsilon) as usize)
-                + ((position.y >= 0.5 - epsilon) as usize * 2)
-                + ((position.z >= 0.5 - epsilon) as usize * 4);
+            let (index, child) = Self::child_slot(position);
 
             if node.children.is_none() {
                 node.children = Some(Box::new(core::array::from_fn(|_| OctreeNode::new())));
@@ -75,31 +136,41 @@ impl SparseVoxelOctree {
             }
 
             if let Some(ref mut children) = node.children {
-                let adjust_coord = |coord: f32| {
-                    if coord >= 0.5 - epsilon {
-                        (coord - 0.5) * 2.0
-                    } else {
-                        coord * 2.0
-                    }
-                };
-
-                position = Vec3::new(
-                    adjust_coord(position.x),
-                    adjust_coord(position.y),
-                    adjust_coord(position.z),
-                );
-
+                position = child;
                 node = &mut children[index];
             }
 
             depth -= 1;
         }
 
-        node.voxel = Some(voxel);
         node.is_leaf = true;
+        node.voxel.replace(voxel).is_none()
     }
 
-    pub fn remove(&mut self, position: Vec3) {
+    /// Recompute the LOD voxels of the internal nodes on the path to the voxel
+    /// at normalized `position`, deepest first.
+    fn refresh_lod_path(node: &mut OctreeNode, position: DVec3, depth: u32) {
+        if depth == 0 {
+            return;
+        }
+        if let Some(children) = node.children.as_mut() {
+            let (index, child) = Self::child_slot(position);
+            Self::refresh_lod_path(&mut children[index], child, depth - 1);
+        }
+        node.refresh_lod();
+    }
+
+    /// Recompute the LOD voxels of every internal node below `node`.
+    fn refresh_lod_all(node: &mut OctreeNode) {
+        if let Some(children) = node.children.as_mut() {
+            for child in children.iter_mut() {
+                Self::refresh_lod_all(child);
+            }
+        }
+        node.refresh_lod();
+    }
+
+    pub fn remove(&mut self, position: DVec3) {
         let aligned = self.normalize_to_voxel_at_depth(position, self.max_depth);
 
         self.dirty.push(DirtyVoxel { position: aligned });
@@ -108,14 +179,13 @@ impl SparseVoxelOctree {
         let key = self.world_to_chunk(position);
         self.dirty_chunks.insert(key);
         self.mark_neighbor_chunks_dirty(position);
+        self.light.note_change(self.world_to_voxel(position));
 
-        Self::remove_recursive(
-            &mut self.root,
-            aligned.x,
-            aligned.y,
-            aligned.z,
-            self.max_depth,
-        );
+        if Self::remove_recursive(&mut self.root, aligned, self.max_depth) {
+            self.voxel_count -= 1;
+            self.voxel_sum -= self.denormalize_voxel_center(aligned);
+        }
+        Self::refresh_lod_path(&mut self.root, aligned, self.max_depth);
 
         if !self.chunk_has_any_voxel(key) {
             self.occupied_chunks.remove(&key);
@@ -127,7 +197,7 @@ impl SparseVoxelOctree {
         self.dirty_chunks.clear();
     }
 
-    fn mark_neighbor_chunks_dirty(&mut self, position: Vec3) {
+    fn mark_neighbor_chunks_dirty(&mut self, position: DVec3) {
         let key = self.world_to_chunk(position);
         let step = self.get_spacing_at_depth(self.max_depth);
         let half = self.size * 0.5;
@@ -175,7 +245,7 @@ impl SparseVoxelOctree {
     }
 
     /// Insert a sphere of voxels with the given radius (in voxels) and center.
-    pub fn insert_sphere(&mut self, center: Vec3, radius: i32, voxel: Voxel) {
+    pub fn insert_sphere(&mut self, center: DVec3, radius: i32, voxel: Voxel) {
         let step = self.get_spacing_at_depth(self.max_depth);
         let r2 = radius * radius;
 
@@ -186,11 +256,7 @@ impl SparseVoxelOctree {
                 for z in -radius..=radius {
                     let dz2 = z * z;
                     if dx2 + dy2 + dz2 <= r2 {
-                        let pos = Vec3::new(
-                            center.x + x as f32 * step,
-                            center.y + y as f32 * step,
-                            center.z + z as f32 * step,
-                        );
+                        let pos = center + IVec3::new(x, y, z).as_dvec3() * step;
                         self.insert(pos, voxel);
                     }
                 }
@@ -199,7 +265,7 @@ impl SparseVoxelOctree {
     }
 
     /// Remove all voxels inside a sphere with the given radius (in voxels).
-    pub fn remove_sphere(&mut self, center: Vec3, radius: i32) {
+    pub fn remove_sphere(&mut self, center: DVec3, radius: i32) {
         let step = self.get_spacing_at_depth(self.max_depth);
         let r2 = radius * radius;
 
@@ -210,11 +276,7 @@ impl SparseVoxelOctree {
                 for z in -radius..=radius {
                     let dz2 = z * z;
                     if dx2 + dy2 + dz2 <= r2 {
-                        let pos = Vec3::new(
-                            center.x + x as f32 * step,
-                            center.y + y as f32 * step,
-                            center.z + z as f32 * step,
-                        );
+                        let pos = center + IVec3::new(x, y, z).as_dvec3() * step;
                         self.remove(pos);
                     }
                 }
@@ -222,14 +284,7 @@ impl SparseVoxelOctree {
         }
     }
 
-    fn remove_recursive(
-        mut node: &mut OctreeNode,
-        mut x: f32,
-        mut y: f32,
-        mut z: f32,
-        mut depth: u32,
-    ) -> bool {
-        let epsilon = 1e-6;
+    fn remove_recursive(mut node: &mut OctreeNode, mut position: DVec3, mut depth: u32) -> bool {
         let mut stack: Vec<(*mut OctreeNode, usize)> = Vec::new();
 
         while depth > 0 {
@@ -237,24 +292,12 @@ impl SparseVoxelOctree {
                 return false;
             }
 
-            let index = ((x >= 0.5 - epsilon) as usize)
-                + ((y >= 0.5 - epsilon) as usize * 2)
-                + ((z >= 0.5 - epsilon) as usize * 4);
-
-            let adjust_coord = |coord: f32| {
-                if coord >= 0.5 - epsilon {
-                    (coord - 0.5) * 2.0
-                } else {
-                    coord * 2.0
-                }
-            };
+            let (index, child) = Self::child_slot(position);
 
             stack.push((node as *mut _, index));
             let children = unsafe { node.children.as_mut().unwrap() };
             node = &mut children[index];
-            x = adjust_coord(x);
-            y = adjust_coord(y);
-            z = adjust_coord(z);
+            position = child;
             depth -= 1;
         }
 
@@ -291,7 +334,7 @@ impl SparseVoxelOctree {
 
     /// Grow the octree so that the given world-space point fits within the root.
     /// The previous root becomes a child of the new root without re-inserting every voxel.
-    fn expand_root(&mut self, x: f32, y: f32, z: f32) {
+    fn expand_root(&mut self, x: f64, y: f64, z: f64) {
         info!("Root expanding ...");
 
         let old_root = std::mem::replace(&mut self.root, OctreeNode::new());
@@ -333,11 +376,11 @@ impl SparseVoxelOctree {
 
     /// Helper: Collect all voxels from a given octree node recursively.
     /// The coordinate system here assumes the node covers [–old_size/2, +old_size/2] in each axis.
-    fn collect_voxels_from_node(
+    pub(crate) fn collect_voxels_from_node(
         node: &OctreeNode,
-        old_size: f32,
-        center: Vec3,
-    ) -> Vec<(Vec3, Voxel, u32)> {
+        old_size: f64,
+        center: DVec3,
+    ) -> Vec<(DVec3, Voxel, u32)> {
         let mut voxels = Vec::new();
         Self::collect_voxels_recursive(
             node,
@@ -353,17 +396,17 @@ impl SparseVoxelOctree {
 
     fn collect_voxels_recursive(
         node: &OctreeNode,
-        x: f32,
-        y: f32,
-        z: f32,
-        size: f32,
+        x: f64,
+        y: f64,
+        z: f64,
+        size: f64,
         depth: u32,
-        out: &mut Vec<(Vec3, Voxel, u32)>,
+        out: &mut Vec<(DVec3, Voxel, u32)>,
     ) {
         if node.is_leaf {
             if let Some(voxel) = node.voxel {
                 // Compute the center of this node's region.
-                let center = Vec3::new(x + size / 2.0, y + size / 2.0, z + size / 2.0);
+                let center = DVec3::new(x + size / 2.0, y + size / 2.0, z + size / 2.0);
                 out.push((center, voxel, depth));
             }
         }
@@ -386,12 +429,12 @@ impl SparseVoxelOctree {
         }
     }
 
-    pub fn traverse(&self) -> Vec<(Vec3, u32)> {
+    pub fn traverse(&self) -> Vec<(DVec3, u32)> {
         let mut voxels = Vec::new();
         // Start at the normalized center (0.5, 0.5, 0.5) rather than (0,0,0)
         Self::traverse_recursive(
             &self.root,
-            Vec3::splat(0.5), // normalized center of the root cell
+            DVec3::splat(0.5), // normalized center of the root cell
             1.0,              // full normalized cell size
             0,
             &mut voxels,
@@ -402,10 +445,10 @@ impl SparseVoxelOctree {
 
     fn traverse_recursive(
         node: &OctreeNode,
-        local_center: Vec3,
-        size: f32,
+        local_center: DVec3,
+        size: f64,
         depth: u32,
-        out: &mut Vec<(Vec3, u32)>,
+        out: &mut Vec<(DVec3, u32)>,
         octree: &SparseVoxelOctree,
     ) {
         // If a leaf contains a voxel, record its world-space center
@@ -424,40 +467,26 @@ impl SparseVoxelOctree {
                 let dx = if (i & 1) != 0 { offset } else { -offset };
                 let dy = if (i & 2) != 0 { offset } else { -offset };
                 let dz = if (i & 4) != 0 { offset } else { -offset };
-                let child_center = local_center + Vec3::new(dx, dy, dz);
+                let child_center = local_center + DVec3::new(dx, dy, dz);
 
                 Self::traverse_recursive(child, child_center, new_size, depth + 1, out, octree);
             }
         }
     }
 
-    /// Retrieve a voxel from the octree if it exists (x,y,z in [-0.5..+0.5] range).
-    pub fn get_voxel_at(&self, x: f32, y: f32, z: f32) -> Option<&Voxel> {
-        Self::get_voxel_recursive(&self.root, x, y, z)
+    /// Retrieve a voxel from the octree if it exists (normalized position in
+    /// the `[0, 1]` range).
+    pub fn get_voxel_at(&self, position: DVec3) -> Option<&Voxel> {
+        Self::get_voxel_recursive(&self.root, position)
     }
 
-    fn get_voxel_recursive(node: &OctreeNode, x: f32, y: f32, z: f32) -> Option<&Voxel> {
+    fn get_voxel_recursive(node: &OctreeNode, position: DVec3) -> Option<&Voxel> {
         if node.is_leaf {
             return node.voxel.as_ref();
         }
         if let Some(children) = &node.children {
-            let epsilon = 1e-6;
-            let index = ((x >= 0.5 - epsilon) as usize)
-                + ((y >= 0.5 - epsilon) as usize * 2)
-                + ((z >= 0.5 - epsilon) as usize * 4);
-            let adjust_coord = |coord: f32| {
-                if coord >= 0.5 - epsilon {
-                    (coord - 0.5) * 2.0
-                } else {
-                    coord * 2.0
-                }
-            };
-            Self::get_voxel_recursive(
-                &children[index],
-                adjust_coord(x),
-                adjust_coord(y),
-                adjust_coord(z),
-            )
+            let (index, child) = Self::child_slot(position);
+            Self::get_voxel_recursive(&children[index], child)
         } else {
             None
         }
@@ -467,26 +496,22 @@ impl SparseVoxelOctree {
     /// The offsets are directions (-1, 0, 1) for x, y, z.
     pub fn has_neighbor(
         &self,
-        position: Vec3,
+        position: DVec3,
         offset_x: i32,
         offset_y: i32,
         offset_z: i32,
         depth: u32,
     ) -> bool {
         let aligned = self.normalize_to_voxel_at_depth(position, depth);
-        let voxel_count = 2_u32.pow(depth) as f32;
+        let voxel_count = 2_u64.pow(depth) as f64;
         // Normalized voxel size is 1/voxel_count
         let norm_voxel_size = 1.0 / voxel_count;
 
-        let neighbor = Vec3::new(
-            aligned.x + (offset_x as f32) * norm_voxel_size,
-            aligned.y + (offset_y as f32) * norm_voxel_size,
-            aligned.z + (offset_z as f32) * norm_voxel_size,
-        );
+        let offset = IVec3::new(offset_x, offset_y, offset_z).as_dvec3();
+        let neighbor = aligned + offset * norm_voxel_size;
 
         // Convert the normalized neighbor coordinate back to world space
-        let half_size = self.size * 0.5;
-        let neighbor_world = neighbor * self.size - Vec3::splat(half_size) + self.center;
+        let neighbor_world = self.denormalize_voxel_center(neighbor);
 
         if !self.contains(neighbor_world.x, neighbor_world.y, neighbor_world.z) {
             return false;
@@ -496,12 +521,12 @@ impl SparseVoxelOctree {
     }
 
     /// Performs a raycast against the octree and returns the first intersected voxel.
-    pub fn raycast(&self, ray: &Ray) -> Option<(f32, f32, f32, u32, Vec3)> {
+    pub fn raycast(&self, ray: &Ray) -> Option<(DVec3, u32, Vec3)> {
         // Start from the root node
         let half_size = self.size / 2.0;
         let root_bounds = AABB {
-            min: self.center - Vec3::splat(half_size),
-            max: self.center + Vec3::splat(half_size),
+            min: self.center - DVec3::splat(half_size),
+            max: self.center + DVec3::splat(half_size),
         };
         self.raycast_recursive(&self.root, ray, &root_bounds, 0)
     }
@@ -512,7 +537,7 @@ impl SparseVoxelOctree {
         ray: &Ray,
         bounds: &AABB,
         depth: u32,
-    ) -> Option<(f32, f32, f32, u32, Vec3)> {
+    ) -> Option<(DVec3, u32, Vec3)> {
         // Check if the ray intersects this node's bounding box
         if let Some((t_enter, _, normal)) = self.ray_intersects_aabb_with_normal(ray, bounds) {
             // If this is a leaf node and contains a voxel, return it
@@ -521,13 +546,7 @@ impl SparseVoxelOctree {
                 let hit_position = ray.origin + ray.direction * t_enter;
 
                 // Return the hit position along with depth and normal
-                return Some((
-                    hit_position.x as f32,
-                    hit_position.y as f32,
-                    hit_position.z as f32,
-                    depth,
-                    normal,
-                ));
+                return Some((hit_position, depth, normal));
             }
 
             // If the node has children, traverse them
@@ -544,14 +563,8 @@ impl SparseVoxelOctree {
                 // Return the closest hit, if any
                 if !hits.is_empty() {
                     hits.sort_by(|a, b| {
-                        let dist_a = ((a.0 as f32 - ray.origin.x).powi(2)
-                            + (a.1 as f32 - ray.origin.y).powi(2)
-                            + (a.2 as f32 - ray.origin.z).powi(2))
-                        .sqrt();
-                        let dist_b = ((b.0 as f32 - ray.origin.x).powi(2)
-                            + (b.1 as f32 - ray.origin.y).powi(2)
-                            + (b.2 as f32 - ray.origin.z).powi(2))
-                        .sqrt();
+                        let dist_a = a.0.distance(ray.origin);
+                        let dist_b = b.0.distance(ray.origin);
                         dist_a.partial_cmp(&dist_b).unwrap()
                     });
                     return Some(hits[0]);
@@ -568,25 +581,126 @@ impl SparseVoxelOctree {
         std::fs::write(path, data)
     }
 
-    /// Load an octree from a file and rebuild runtime caches.
+    /// Load an octree from a file and rebuild runtime caches. Files saved
+    /// before the placement moved to double precision are still accepted.
     pub fn load_from_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
         let bytes = std::fs::read(path)?;
-        let mut tree: Self =
-            bincode::deserialize(&bytes).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        let mut tree: Self = match bincode::deserialize(&bytes) {
+            Ok(tree) => tree,
+            Err(e) => bincode::deserialize::<LegacyOctree>(&bytes)
+                .map(Self::from)
+                .map_err(|_| io::Error::new(io::ErrorKind::Other, e))?,
+        };
         tree.rebuild_cache();
         Ok(tree)
     }
 
-    /// Rebuild runtime caches like occupied_chunks after loading.
+    /// Rebuild runtime caches like occupied_chunks, the voxel count and the
+    /// LOD voxels of internal nodes after loading.
     pub fn rebuild_cache(&mut self) {
         self.dirty.clear();
         self.dirty_chunks.clear();
         self.occupied_chunks.clear();
+        self.voxel_count = 0;
+        self.voxel_sum = DVec3::ZERO;
+        Self::refresh_lod_all(&mut self.root);
 
         let voxels = Self::collect_voxels_from_node(&self.root, self.size, self.center);
         for (pos, _voxel, _depth) in voxels {
             let key = self.world_to_chunk(pos);
             self.occupied_chunks.insert(key);
+            self.voxel_count += 1;
+            self.voxel_sum += pos;
+        }
+    }
+}
+
+/// Serialized layout of [`SparseVoxelOctree`] from before its placement was
+/// stored in double precision.
+#[derive(Deserialize)]
+struct LegacyOctree {
+    root: OctreeNode,
+    max_depth: u32,
+    size: f32,
+    center: Vec3,
+    show_wireframe: bool,
+    show_world_grid: bool,
+}
+
+impl From<LegacyOctree> for SparseVoxelOctree {
+    fn from(legacy: LegacyOctree) -> Self {
+        let mut tree = Self::new(
+            legacy.max_depth,
+            legacy.size as f64,
+            legacy.show_wireframe,
+            legacy.show_world_grid,
+            false,
+        );
+        tree.root = legacy.root;
+        tree.center = legacy.center.as_dvec3();
+        tree
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Count and position sum of every voxel, walked from scratch.
+    fn recount(tree: &SparseVoxelOctree) -> (u64, DVec3) {
+        SparseVoxelOctree::collect_voxels_from_node(&tree.root, tree.size, tree.center)
+            .into_iter()
+            .fold((0, DVec3::ZERO), |(count, sum), (pos, _, _)| (count + 1, sum + pos))
+    }
+
+    fn assert_counts(tree: &SparseVoxelOctree, step: &str) {
+        let (count, sum) = recount(tree);
+        assert_eq!(tree.voxel_count, count, "{step}");
+        assert!(tree.voxel_sum.abs_diff_eq(sum, 1e-9), "{step}: {} != {sum}", tree.voxel_sum);
+    }
+
+    #[test]
+    fn voxel_count_and_sum_follow_edits() {
+        let mut tree = SparseVoxelOctree::new(6, 64.0, false, false, false);
+        let stone = Voxel::new([3; 6]);
+        let sand = Voxel::new([4; 6]);
+
+        for x in -3..3 {
+            tree.insert(DVec3::new(x as f64 + 0.5, 2.5, -7.5), stone);
         }
+        assert_eq!(tree.voxel_count, 6);
+        assert_counts(&tree, "insert");
+
+        tree.insert(DVec3::new(0.5, 2.5, -7.5), sand);
+        assert_eq!(tree.voxel_count, 6);
+        assert_counts(&tree, "overwrite");
+
+        tree.remove(DVec3::new(10.5, 10.5, 10.5));
+        assert_eq!(tree.voxel_count, 6);
+        assert_counts(&tree, "remove an empty cell");
+
+        tree.remove(DVec3::new(-2.5, 2.5, -7.5));
+        assert_eq!(tree.voxel_count, 5);
+        assert_counts(&tree, "remove");
+
+        let batch = [
+            (DVec3::new(1.5, 2.5, -7.5), sand),
+            (DVec3::new(1.5, 3.5, -7.5), sand),
+            (DVec3::new(1.5, 3.5, -7.5), stone),
+        ];
+        tree.insert_batch(&batch);
+        assert_eq!(tree.voxel_count, 6);
+        assert_counts(&tree, "batch");
+
+        // far outside the root, so it has to grow first
+        let (depth, size) = (tree.max_depth, tree.size);
+        tree.insert(DVec3::new(100.5, -40.5, 70.5), stone);
+        assert!(tree.max_depth > depth && tree.size > size);
+        assert_eq!(tree.voxel_count, 7);
+        assert_counts(&tree, "expand_root");
+
+        tree.insert_batch(&[(DVec3::new(-300.5, 0.5, 0.5), sand)]);
+        assert_eq!(tree.voxel_count, 8);
+        assert_counts(&tree, "batch expand_root");
     }
 }
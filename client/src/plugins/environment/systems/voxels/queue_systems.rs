@@ -1,75 +1,239 @@
 use crate::plugins::environment::systems::voxels::structure::*;
+use crate::plugins::environment::systems::voxels::meshing_gpu::GpuMeshingQueue;
+use crate::plugins::environment::systems::voxels::occlusion::{ChunkOcclusion, chunk_in_frustum};
+use bevy::diagnostic::{Diagnostic, DiagnosticPath, Diagnostics};
+use bevy::math::DAffine3;
 use bevy::prelude::*;
+use bevy::render::primitives::Frustum;
+use bevy::tasks::AsyncComputeTaskPool;
+use big_space::prelude::{GridCell, Grids};
 use rayon::prelude::*;
+use std::collections::HashMap;
+use std::time::Instant;
+
+/// Chunks waiting in the [`ChunkQueue`].
+pub const CHUNK_QUEUE_LENGTH: DiagnosticPath = DiagnosticPath::const_new("chunks/queue_length");
+/// Chunk meshes finished per second.
+pub const CHUNKS_MESHED_PER_SECOND: DiagnosticPath =
+    DiagnosticPath::const_new("chunks/meshed_per_second");
+/// Average time spent meshing one chunk, in milliseconds.
+pub const CHUNK_MESH_TIME: DiagnosticPath = DiagnosticPath::const_new("chunks/mesh_time_ms");
+/// Current [`ChunkBudget::per_frame`].
+pub const CHUNK_BUDGET: DiagnosticPath = DiagnosticPath::const_new("chunks/budget_per_frame");
+
+/// Weight of the newest sample in the smoothed costs and budget.
+const SMOOTHING: f32 = 0.1;
+
+/// Every chunk diagnostic, for `App::register_diagnostic`.
+pub fn chunk_diagnostics() -> [Diagnostic; 4] {
+    [
+        Diagnostic::new(CHUNK_QUEUE_LENGTH),
+        Diagnostic::new(CHUNKS_MESHED_PER_SECOND),
+        Diagnostic::new(CHUNK_MESH_TIME).with_suffix("ms"),
+        Diagnostic::new(CHUNK_BUDGET),
+    ]
+}
+
+/// Track the camera's world position from its big_space grid cell.
+pub fn track_streaming_camera(
+    grids: Grids<'_, '_>,
+    camera_q: Query<(Entity, &GridCell, &Transform), With<Camera>>,
+    mut camera: ResMut<StreamingCamera>,
+) {
+    let Ok((cam_ent, cell, tf)) = camera_q.get_single() else {
+        return;
+    };
+    let Some(grid) = grids.parent_grid(cam_ent) else {
+        return;
+    };
+    camera.position = grid.grid_position_double(cell, tf);
+    camera.origin = grid.grid_position_double(cell, &Transform::IDENTITY);
+}
+
+/// Place every voxel volume in world space from its grid cell and transform.
+pub fn track_voxel_volumes(
+    grids: Grids<'_, '_>,
+    mut volumes: Query<(Entity, &GridCell, &Transform, &mut VolumeTransform)>,
+) {
+    for (ent, cell, tf, mut volume) in &mut volumes {
+        let Some(grid) = grids.parent_grid(ent) else {
+            continue;
+        };
+        volume.0 = DAffine3::from_scale_rotation_translation(
+            tf.scale.as_dvec3(),
+            tf.rotation.as_dquat(),
+            grid.grid_position_double(cell, tf),
+        );
+    }
+}
 
 /// enqueue chunks that *should* be visible but are not yet spawned
-/// enqueue chunks that *should* be visible but are not yet spawned
+///
+/// Chunks inside the camera frustum come first, each group ordered by
+/// distance weighted by how far the chunk lies off the view direction.
+/// Chunks hidden by [`ChunkOcclusion`] are not queued at all. The queue is
+/// rebuilt for all volumes as soon as the camera chunk or occlusion of any one
+/// of them changes.
 pub fn enqueue_visible_chunks(
     mut queue: ResMut<ChunkQueue>,
     spawned: Res<SpawnedChunks>,
+    empty: Res<EmptyChunks>,
+    tasks: Res<ChunkMeshTasks>,
+    gpu: Res<GpuMeshingQueue>,
     mut prev_cam: ResMut<PrevCameraChunk>,
+    mut seen_revision: Local<HashMap<Entity, u64>>,
     cfg: Res<ChunkCullingCfg>,
-    cam_q: Query<&GlobalTransform, With<Camera>>,
-    tree_q: Query<&SparseVoxelOctree>,
+    camera: Res<StreamingCamera>,
+    cam_q: Query<(&GlobalTransform, &Frustum), With<Camera>>,
+    volumes: Query<(Entity, &SparseVoxelOctree, &VolumeTransform, &ChunkOcclusion)>,
 ) {
-    let Ok(tree) = tree_q.get_single() else {
-        return;
-    };
-    let Ok(cam_tf) = cam_q.get_single() else {
+    let Ok((cam_tf, frustum)) = cam_q.get_single() else {
         return;
     };
-    let cam_pos = cam_tf.translation();
-    let centre = tree.world_to_chunk(cam_pos);
+    let forward = *cam_tf.forward();
 
-    if prev_cam.0 == Some(centre) {
+    // the occlusion pass also reruns when the camera turns
+    let centres: HashMap<Entity, ChunkKey> = volumes
+        .iter()
+        .map(|(ent, tree, volume, _)| (ent, tree.world_to_chunk(volume.to_local(camera.position))))
+        .collect();
+    let revisions: HashMap<Entity, u64> =
+        volumes.iter().map(|(ent, _, _, occlusion)| (ent, occlusion.revision)).collect();
+    if prev_cam.0 == centres && *seen_revision == revisions {
         return;
     }
-    prev_cam.0 = Some(centre);
+    prev_cam.0 = centres;
+    *seen_revision = revisions;
 
     let r = cfg.view_distance_chunks;
-
-    let mut keys: Vec<(ChunkKey, i32)> = tree
-        .occupied_chunks
-        .par_iter()
-        .filter_map(|key| {
+    let mut keys: Vec<(Entity, ChunkKey, bool, f32)> = Vec::new();
+    for (ent, tree, volume, occlusion) in &volumes {
+        let centre = prev_cam.0[&ent];
+        let cam_local = volume.to_local(camera.position);
+        let chunk_size = CHUNK_SIZE as f64 * tree.get_spacing_at_depth(tree.max_depth);
+        keys.par_extend(tree.occupied_chunks.par_iter().filter_map(|key| {
             let dx = key.0 - centre.0;
             let dy = key.1 - centre.1;
             let dz = key.2 - centre.2;
             if dx.abs() > r || dy.abs() > r || dz.abs() > r {
                 return None;
             }
-            if spawned.0.contains_key(key) {
+            if spawned.get(ent, *key).is_some() || occlusion.is_occluded(*key) {
                 return None;
             }
-            Some((*key, dx * dx + dy * dy + dz * dz))
-        })
-        .collect();
+            // already being meshed, or meshed without faces and not edited since
+            if tasks.latest.contains_key(&(ent, *key))
+                || gpu.is_queued(ent, *key)
+                || empty.0.contains(&(ent, *key))
+            {
+                return None;
+            }
+            let local_centre = tree.chunk_center_world(*key);
+            let distance = (local_centre.distance(cam_local) / chunk_size) as f32;
+            let offset = (volume.to_world(local_centre) - camera.position).as_vec3();
+            // 1 straight ahead, 2 sideways, 3 behind the camera
+            let facing = 2.0 - offset.normalize_or_zero().dot(forward);
+            let outside = !chunk_in_frustum(tree, volume, *key, frustum, camera.origin);
+            Some((ent, *key, outside, distance * facing))
+        }));
+    }
 
-    keys.sort_by_key(|(_, d)| *d);
+    keys.sort_by(|a, b| a.2.cmp(&b.2).then(a.3.total_cmp(&b.3)));
 
+    // chunks outside the frustum only load while there is room under the
+    // chunk cap, otherwise they would just evict each other
+    let mut room = cfg.max_chunks.saturating_sub(spawned.len());
     queue.keys.clear();
     queue.set.clear();
-    for (key, _) in keys {
-        queue.keys.push_back(key);
-        queue.set.insert(key);
+    for (ent, key, outside, _) in keys {
+        if outside && room == 0 {
+            continue;
+        }
+        room = room.saturating_sub(1);
+        queue.keys.push_back((ent, key));
+        queue.set.insert((ent, key));
     }
 }
 
-/// move a limited number of keys from the queue into the octree’s dirty set
+/// move a limited number of keys from the queue into their octree’s dirty set
 pub fn process_chunk_queue(
     mut queue: ResMut<ChunkQueue>,
     budget: Res<ChunkBudget>,
     mut tree_q: Query<&mut SparseVoxelOctree>,
 ) {
-    let Ok(mut tree) = tree_q.get_single_mut() else {
-        return;
-    };
     for _ in 0..budget.per_frame {
-        if let Some(key) = queue.keys.pop_front() {
-            queue.set.remove(&key);
-            tree.dirty_chunks.insert(key);
-        } else {
+        let Some((volume, key)) = queue.keys.pop_front() else {
             break;
+        };
+        queue.set.remove(&(volume, key));
+        if let Ok(mut tree) = tree_q.get_mut(volume) {
+            tree.dirty_chunks.insert(key);
         }
     }
 }
+
+/// Start timing the main-thread work of a frame; runs in `First`.
+pub fn begin_chunk_frame(mut stats: ResMut<ChunkMeshingStats>) {
+    stats.frame_started = Some(Instant::now());
+}
+
+/// Finish timing the main-thread work of a frame; runs in `Last`.
+pub fn end_chunk_frame(mut stats: ResMut<ChunkMeshingStats>) {
+    if let Some(started) = stats.frame_started.take() {
+        stats.frame_work = started.elapsed().as_secs_f32();
+    }
+}
+
+/// Adapt the per-frame chunk budget to the measured frame work and publish
+/// the chunk diagnostics.
+///
+/// A chunk costs its main-thread extraction plus its share of the meshing
+/// spread over the async compute threads. The last frame's work from `First`
+/// to `Last` without its extraction tells how much of `target_frame_time` is
+/// left for chunk work, and the budget eases towards as many chunks as fit in
+/// it. The frame delta is not used since with vsync it always sits at the
+/// refresh interval, however little work the frame did.
+pub fn adapt_chunk_budget(
+    time: Res<Time>,
+    queue: Res<ChunkQueue>,
+    mut budget: ResMut<ChunkBudget>,
+    mut stats: ResMut<ChunkMeshingStats>,
+    mut diagnostics: Diagnostics,
+) {
+    let frame = time.delta_secs();
+    if frame <= 0.0 {
+        return;
+    }
+
+    if stats.extracted > 0 {
+        let sample = stats.extract_time / stats.extracted as f32;
+        stats.avg_extract += (sample - stats.avg_extract) * SMOOTHING;
+    }
+    if stats.meshed > 0 {
+        let sample = stats.mesh_time / stats.meshed as f32;
+        stats.avg_mesh += (sample - stats.avg_mesh) * SMOOTHING;
+    }
+
+    let workers = AsyncComputeTaskPool::get().thread_num().max(1) as f32;
+    let cost = stats.avg_extract + stats.avg_mesh / workers;
+    let headroom = budget.target_frame_time - (stats.frame_work - stats.frame_extract);
+    let (min, max) = (budget.min_per_frame as f32, budget.max_per_frame as f32);
+    let wanted = if cost > 0.0 { (headroom / cost).clamp(min, max) } else { max };
+    if stats.allowance == 0.0 {
+        stats.allowance = budget.per_frame as f32;
+    }
+    stats.allowance += (wanted - stats.allowance) * SMOOTHING;
+    budget.per_frame = (stats.allowance.round() as usize)
+        .clamp(budget.min_per_frame, budget.max_per_frame);
+
+    diagnostics.add_measurement(&CHUNK_QUEUE_LENGTH, || queue.keys.len() as f64);
+    diagnostics.add_measurement(&CHUNKS_MESHED_PER_SECOND, || stats.meshed as f64 / frame as f64);
+    diagnostics.add_measurement(&CHUNK_MESH_TIME, || stats.avg_mesh as f64 * 1000.0);
+    diagnostics.add_measurement(&CHUNK_BUDGET, || budget.per_frame as f64);
+
+    stats.frame_extract = stats.extract_time;
+    stats.extract_time = 0.0;
+    stats.extracted = 0;
+    stats.mesh_time = 0.0;
+    stats.meshed = 0;
+}
@@ -1,129 +1,258 @@
-use crate::plugins::big_space::big_space_plugin::RootGrid;
-use crate::plugins::environment::systems::voxels::atlas::VoxelTextureAtlas;
+use crate::plugins::environment::systems::voxels::material::{VoxelMaterial, VoxelMaterials};
 use crate::plugins::environment::systems::voxels::meshing::mesh_chunk;
+use crate::plugins::environment::systems::voxels::meshing_gpu::GpuMeshingQueue;
+use crate::plugins::environment::systems::voxels::occlusion::{ChunkOcclusion, FaceConnectivity};
 use crate::plugins::environment::systems::voxels::structure::*;
 use bevy::pbr::wireframe::Wireframe;
 use bevy::prelude::*;
 use bevy::render::mesh::Mesh;
-use big_space::prelude::GridCell;
+use bevy::render::primitives::Aabb;
+use bevy::tasks::{AsyncComputeTaskPool, block_on, futures_lite::future};
+use big_space::prelude::Grid;
 use itertools::Itertools;
 use rayon::prelude::*;
 use std::collections::HashMap;
+use std::time::Instant;
 use std::fmt::format;
 
 /// rebuilds meshes only for chunks flagged dirty by the octree
+///
+/// Every chunk is meshed twice: opaque faces live on the chunk entity itself,
+/// translucent faces on a `TransparentChunkMesh` child with an alpha-blended
+/// material. A chunk entity without opaque faces simply has no `Mesh3d`.
+///
+/// Meshes are chunk-local: each chunk is a child of its octree entity and sits
+/// in the cell of the volume's grid containing its origin. Its transform
+/// carries the offset within that cell and the voxel size, so precision does
+/// not depend on the distance from the volume's origin, and moving or rotating
+/// the volume moves its chunks along. Both passes use the shared
+/// [`VoxelMaterials`].
+///
+/// Chunk faces towards a neighbour at another LOD get skirts (see
+/// [`ChunkSnapshot::open_sides`]) so the seam between them shows no gaps.
+///
+/// Dirty chunks are snapshotted here and meshed on the `AsyncComputeTaskPool`;
+/// finished tasks are attached on a later frame. A result is dropped when the
+/// chunk was edited again after its snapshot, since a newer task is on its way.
+///
+/// With [`MeshingBackend::Gpu`] dirty chunks are handed to the compute mesher
+/// instead and attached here once their meshes have been read back.
 pub fn rebuild_dirty_chunks(
     mut commands: Commands,
-    mut octrees: Query<&mut SparseVoxelOctree>,
+    mut octrees: Query<(Entity, &mut SparseVoxelOctree, &Grid, &mut ChunkOcclusion)>,
     mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    chunk_q: Query<(
-        Entity,
-        &Chunk,
-        &Mesh3d,
-        &MeshMaterial3d<StandardMaterial>,
-        &ChunkLod,
-    )>,
+    chunk_q: Query<(Entity, &Chunk, Option<&Mesh3d>, &ChunkLod)>,
+    transparent_q: Query<(Entity, &TransparentChunkMesh, &Mesh3d)>,
     mut spawned: ResMut<SpawnedChunks>,
-    mut pool: ResMut<MeshBufferPool>,
-    root: Res<RootGrid>,
-    atlas: Res<VoxelTextureAtlas>,
+    mut empty: ResMut<EmptyChunks>,
+    mut tasks: ResMut<ChunkMeshTasks>,
+    materials: Res<VoxelMaterials>,
+    cfg: Res<ChunkMeshingCfg>,
+    mut gpu: ResMut<GpuMeshingQueue>,
+    time: Res<Time>,
+    mut stats: ResMut<ChunkMeshingStats>,
 ) {
-    // map ChunkKey → (entity, mesh-handle, material-handle)
-    let existing: HashMap<ChunkKey, (Entity, Handle<Mesh>, Handle<StandardMaterial>, u32)> =
-        chunk_q
-            .iter()
-            .map(|(e, c, m, mat, lod)| (c.key, (e, m.0.clone(), mat.0.clone(), lod.0)))
-            .collect();
+    // map (volume, ChunkKey) → (entity, opaque mesh-handle, lod)
+    let existing: HashMap<(Entity, ChunkKey), (Entity, Option<Handle<Mesh>>, u32)> = chunk_q
+        .iter()
+        .map(|(e, c, m, lod)| ((c.volume, c.key), (e, m.map(|m| m.0.clone()), lod.0)))
+        .collect();
+    // map (volume, ChunkKey) → (child entity, transparent mesh-handle)
+    let transparent: HashMap<(Entity, ChunkKey), (Entity, Handle<Mesh>)> = transparent_q
+        .iter()
+        .map(|(e, t, m)| ((t.volume, t.key), (e, m.0.clone())))
+        .collect();
 
-    for mut tree in &mut octrees {
-        if tree.dirty_chunks.is_empty() {
-            continue;
+    // compute-meshed chunks arrive a frame or more after their dispatch
+    let mut built: Vec<MeshedChunk> = gpu.finished.drain(..).collect();
+    for meshed in &built {
+        stats.meshed += 1;
+        stats.mesh_time += meshed.mesh_time.as_secs_f32();
+    }
+
+    let ChunkMeshTasks { latest, running, .. } = &mut *tasks;
+    running.retain_mut(|(volume, key, generation, task)| {
+        let Some(meshed) = block_on(future::poll_once(task)) else {
+            return true;
+        };
+        stats.meshed += 1;
+        stats.mesh_time += meshed.mesh_time.as_secs_f32();
+        if latest.get(&(*volume, *key)) == Some(generation) {
+            latest.remove(&(*volume, *key));
+            built.push(meshed);
         }
-        let dirty_keys: Vec<_> = tree.dirty_chunks.iter().copied().collect();
+        false
+    });
 
+    for (volume, mut tree, _, _) in &mut octrees {
+        let dirty_keys: Vec<_> = tree.dirty_chunks.iter().copied().collect();
         for key in dirty_keys {
-            let lod = existing.get(&key).map(|v| v.3).unwrap_or(0);
-            let mut buf = [[[None; CHUNK_SIZE as usize]; CHUNK_SIZE as usize]; CHUNK_SIZE as usize];
-
-            let half = tree.size * 0.5;
-            let step = tree.get_spacing_at_depth(tree.max_depth);
-            let origin = Vec3::new(
-                tree.center.x - half + key.0 as f32 * CHUNK_SIZE as f32 * step,
-                tree.center.y - half + key.1 as f32 * CHUNK_SIZE as f32 * step,
-                tree.center.z - half + key.2 as f32 * CHUNK_SIZE as f32 * step,
-            );
-
-            let mult = 1 << lod;
-            for gx in (0..CHUNK_SIZE).step_by(mult as usize) {
-                for gy in (0..CHUNK_SIZE).step_by(mult as usize) {
-                    for gz in (0..CHUNK_SIZE).step_by(mult as usize) {
-                        let center = origin
-                            + Vec3::new(
-                                (gx + mult / 2) as f32 * step,
-                                (gy + mult / 2) as f32 * step,
-                                (gz + mult / 2) as f32 * step,
-                            );
-                        if let Some(v) = tree.get_voxel_at_world_coords(center) {
-                            for lx in 0..mult {
-                                for ly in 0..mult {
-                                    for lz in 0..mult {
-                                        let ix = gx + lx;
-                                        let iy = gy + ly;
-                                        let iz = gz + lz;
-                                        if ix < CHUNK_SIZE && iy < CHUNK_SIZE && iz < CHUNK_SIZE {
-                                            buf[ix as usize][iy as usize][iz as usize] = Some(*v);
-                                        }
-                                    }
-                                }
-                            }
+            empty.0.remove(&(volume, key));
+            let lod = existing.get(&(volume, key)).map(|v| v.2).unwrap_or(0);
+            let seams = lod_seams(volume, key, lod, &existing);
+            match cfg.backend {
+                MeshingBackend::Gpu => gpu.request(volume, key, lod, seams),
+                MeshingBackend::Cpu => {
+                    let started = Instant::now();
+                    let mut snapshot = tree.extract_chunk(key, lod);
+                    snapshot.open_sides(seams);
+                    stats.extract_time += started.elapsed().as_secs_f32();
+                    stats.extracted += 1;
+                    let generation = tasks.next_generation;
+                    tasks.next_generation += 1;
+                    tasks.latest.insert((volume, key), generation);
+                    let task = AsyncComputeTaskPool::get().spawn(async move {
+                        let started = Instant::now();
+                        let mut pool = MeshBufferPool::default();
+                        let opaque = mesh_chunk(&snapshot, &mut pool, MeshPass::Opaque);
+                        let transparent = mesh_chunk(&snapshot, &mut pool, MeshPass::Transparent);
+                        MeshedChunk {
+                            volume,
+                            key,
+                            lod,
+                            opaque,
+                            transparent,
+                            connectivity: FaceConnectivity::from_snapshot(&snapshot),
+                            mesh_time: started.elapsed(),
                         }
-                    }
+                    });
+                    tasks.running.push((volume, key, generation, task));
                 }
             }
+        }
+        tree.clear_dirty_flags();
+    }
 
-            if let Some((ent, mesh_h, _mat_h, _)) = existing.get(&key).cloned() {
-                match mesh_chunk(&buf, origin, step, &tree, &mut pool, &atlas) {
-                    Some(new_mesh) => {
-                        if let Some(mesh) = meshes.get_mut(&mesh_h) {
-                            *mesh = new_mesh;
-                        }
-                        spawned.0.insert(key, ent);
-                    }
-                    None => {
-                        meshes.remove(&mesh_h);
-                        commands.entity(ent).despawn_recursive();
-                        spawned.0.remove(&key);
-                    }
+    for MeshedChunk {
+        volume,
+        key,
+        lod,
+        opaque: opaque_mesh,
+        transparent: transparent_mesh,
+        connectivity,
+        ..
+    } in built
+    {
+        // the volume may have been despawned while its chunks were meshed
+        let Ok((_, tree, grid, mut occlusion)) = octrees.get_mut(volume) else {
+            continue;
+        };
+        occlusion.set_connectivity(key, connectivity);
+        let old_opaque = existing.get(&(volume, key)).and_then(|v| v.1.clone());
+        let old_transparent = transparent.get(&(volume, key)).cloned();
+
+        if opaque_mesh.is_none() && transparent_mesh.is_none() {
+            empty.0.insert((volume, key));
+            if let Some((ent, _, _)) = existing.get(&(volume, key)) {
+                for mesh_h in old_opaque.iter().chain(old_transparent.iter().map(|t| &t.1)) {
+                    meshes.remove(mesh_h);
                 }
-            } else if let Some(mesh) = mesh_chunk(&buf, origin, step, &tree, &mut pool, &atlas) {
-                let mesh_h = meshes.add(mesh);
-                let mat_h = materials.add(StandardMaterial {
-                    base_color_texture: Some(atlas.handle.clone()),
-                    ..Default::default()
-                });
+                commands.entity(*ent).despawn_recursive();
+                spawned.remove(volume, key);
+            }
+            continue;
+        }
 
-                commands.entity(root.0).with_children(|p| {
-                    let e = p
+        let step = tree.get_spacing_at_depth(tree.max_depth) as f32;
+        let (cell, local) = grid.translation_to_grid(tree.chunk_origin_world(key));
+        let transform = Transform::from_translation(local).with_scale(Vec3::splat(step));
+        let bounds = Aabb::from_min_max(Vec3::ZERO, Vec3::splat(CHUNK_SIZE as f32));
+        let ent = match existing.get(&(volume, key)) {
+            Some((ent, _, _)) => {
+                // the root may have grown since the chunk was spawned
+                commands.entity(*ent).insert((cell, transform));
+                *ent
+            }
+            None => {
+                let mut ent = Entity::PLACEHOLDER;
+                commands.entity(volume).with_children(|p| {
+                    ent = p
                         .spawn((
-                            Mesh3d::from(mesh_h.clone()),
-                            MeshMaterial3d(mat_h.clone()),
-                            Transform::default(),
-                            GridCell::ZERO,
+                            transform,
+                            Visibility::default(),
+                            bounds,
+                            cell,
                             Chunk {
+                                volume,
                                 key,
                                 voxels: Vec::new(),
                                 dirty: false,
                             },
                             ChunkLod(lod),
+                            ChunkLastVisible(time.elapsed_secs()),
                             /*Wireframe,*/
                         ))
                         .id();
-                    spawned.0.insert(key, e);
                 });
+                ent
+            }
+        };
+        spawned.insert(volume, key, ent);
+
+        match (opaque_mesh, old_opaque) {
+            (Some(new_mesh), Some(mesh_h)) => {
+                if let Some(mesh) = meshes.get_mut(&mesh_h) {
+                    *mesh = new_mesh;
+                }
             }
+            (Some(new_mesh), None) => {
+                commands.entity(ent).insert((
+                    Mesh3d(meshes.add(new_mesh)),
+                    MeshMaterial3d(materials.opaque.clone()),
+                ));
+            }
+            (None, Some(mesh_h)) => {
+                meshes.remove(&mesh_h);
+                commands
+                    .entity(ent)
+                    .remove::<(Mesh3d, MeshMaterial3d<VoxelMaterial>)>();
+            }
+            (None, None) => {}
         }
 
-        tree.clear_dirty_flags();
+        match (transparent_mesh, old_transparent) {
+            (Some(new_mesh), Some((_, mesh_h))) => {
+                if let Some(mesh) = meshes.get_mut(&mesh_h) {
+                    *mesh = new_mesh;
+                }
+            }
+            (Some(new_mesh), None) => {
+                let mesh_h = meshes.add(new_mesh);
+                commands.entity(ent).with_children(|p| {
+                    p.spawn((
+                        Mesh3d(mesh_h),
+                        MeshMaterial3d(materials.transparent.clone()),
+                        Transform::default(),
+                        bounds,
+                        TransparentChunkMesh { volume, key },
+                    ));
+                });
+            }
+            (None, Some((child, mesh_h))) => {
+                meshes.remove(&mesh_h);
+                commands.entity(child).despawn_recursive();
+            }
+            (None, None) => {}
+        }
     }
 }
+
+/// Faces of `key` whose neighbouring chunk in the same volume is meshed at
+/// another LOD, one bit per face in face order.
+fn lod_seams(
+    volume: Entity,
+    key: ChunkKey,
+    lod: u32,
+    existing: &HashMap<(Entity, ChunkKey), (Entity, Option<Handle<Mesh>>, u32)>,
+) -> u8 {
+    NEIGHBOR_OFFSETS
+        .iter()
+        .enumerate()
+        .fold(0, |seams, (face, (dx, dy, dz))| {
+            let neighbour = ChunkKey(key.0 + *dx as i32, key.1 + *dy as i32, key.2 + *dz as i32);
+            match existing.get(&(volume, neighbour)) {
+                Some((_, _, other)) if *other != lod => seams | 1 << face,
+                _ => seams,
+            }
+        })
+}
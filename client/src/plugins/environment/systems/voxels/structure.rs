@@ -1,7 +1,13 @@
+use crate::plugins::environment::systems::voxels::atlas::is_transparent_texture;
+use crate::plugins::environment::systems::voxels::lighting::VoxelLight;
+use crate::plugins::environment::systems::voxels::occlusion::{ChunkOcclusion, FaceConnectivity};
+use bevy::math::{DAffine3, DVec3};
 use bevy::prelude::*;
+use bevy::tasks::Task;
 use rand::Rng;
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
 
 /// Represents a single voxel with texture indices for each face.
 #[derive(Debug, Clone, Copy, Component, PartialEq, Serialize, Deserialize)]
@@ -18,9 +24,16 @@ impl Default for Voxel {
     }
 }
 
+/// A small voxel model placed relative to an anchor voxel (trees, rocks, …).
+#[derive(Debug, Clone, Default)]
+pub struct Prefab {
+    /// Voxel offsets from the anchor, measured in voxels.
+    pub voxels: Vec<(IVec3, Voxel)>,
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct DirtyVoxel {
-    pub position: Vec3,
+    pub position: DVec3,
 }
 
 /// Represents a node in the sparse voxel octree.
@@ -30,15 +43,26 @@ pub struct OctreeNode {
     pub children: Option<Box<[OctreeNode; 8]>>,
     pub voxel: Option<Voxel>,
     pub is_leaf: bool,
+    /// Aggregate of the children for LOD meshing, kept up to date on edits.
+    /// Only meaningful on internal nodes.
+    #[serde(skip)]
+    pub lod: Option<Voxel>,
 }
 /// Represents the root of the sparse voxel octree.
-/// Represents the root of the sparse voxel octree.
+///
+/// The root's world placement is kept in double precision, so voxels stay
+/// addressable when the root has grown to planetary size.
+///
+/// Every octree entity is one voxel volume: a big_space grid whose transform,
+/// rotation included, places the octree's coordinates in the world. Its
+/// chunks are spawned as children in that grid.
 #[derive(Debug, Component, Serialize, Deserialize, Clone)]
+#[require(VolumeTransform, ChunkOcclusion)]
 pub struct SparseVoxelOctree {
     pub root: OctreeNode,
     pub max_depth: u32,
-    pub size: f32,
-    pub center: Vec3,
+    pub size: f64,
+    pub center: DVec3,
     pub show_wireframe: bool,
     pub show_world_grid: bool,
 
@@ -48,6 +72,14 @@ pub struct SparseVoxelOctree {
     pub dirty_chunks: HashSet<ChunkKey>,
     #[serde(skip)]
     pub occupied_chunks: HashSet<ChunkKey>,
+    #[serde(skip)]
+    pub light: VoxelLight,
+    /// Number of voxels, kept current by every edit.
+    #[serde(skip)]
+    pub voxel_count: u64,
+    /// Sum of the voxel centres in octree space, kept current by every edit.
+    #[serde(skip)]
+    pub voxel_sum: DVec3,
 }
 
 impl OctreeNode {
@@ -57,12 +89,40 @@ impl OctreeNode {
             children: None,
             voxel: None,
             is_leaf: true,
+            lod: None,
         }
     }
 
     pub fn is_empty(&self) -> bool {
         self.voxel.is_none() && self.children.is_none()
     }
+
+    /// The voxel standing in for this node's whole cell: its own voxel for a
+    /// leaf, the aggregated LOD voxel otherwise.
+    pub fn representative(&self) -> Option<Voxel> {
+        if self.is_leaf { self.voxel } else { self.lod }
+    }
+
+    /// Recompute the LOD voxel from the children. The cell counts as solid
+    /// when at least half of its children are, which keeps one-voxel walls and
+    /// floors from vanishing at coarser levels, and takes the most common
+    /// material among them. Opaque materials win over translucent ones since
+    /// they are what is seen from afar.
+    pub fn refresh_lod(&mut self) {
+        self.lod = self.children.as_ref().and_then(|children| {
+            let solid: Vec<Voxel> = children.iter().filter_map(Self::representative).collect();
+            if solid.len() < 4 {
+                return None;
+            }
+            let opaque: Vec<Voxel> =
+                solid.iter().copied().filter(|v| !v.is_transparent()).collect();
+            let candidates = if opaque.is_empty() { &solid } else { &opaque };
+            candidates
+                .iter()
+                .max_by_key(|v| candidates.iter().filter(|o| o == v).count())
+                .copied()
+        });
+    }
 }
 
 impl Voxel {
@@ -71,6 +131,12 @@ impl Voxel {
         Self { textures }
     }
 
+    /// Voxels whose faces all use translucent atlas tiles (glass, water,
+    /// leaves) let light through and are meshed in the transparent pass.
+    pub fn is_transparent(&self) -> bool {
+        self.textures.iter().all(|t| is_transparent_texture(*t))
+    }
+
     /// Generate a voxel with a red top, black bottom and random colors on
     /// all remaining faces. Assumes the atlas uses index 0 for red, index 1
     /// for black and indices >=2 for random colors.
@@ -98,21 +164,150 @@ pub const NEIGHBOR_OFFSETS: [(f32, f32, f32); 6] = [
 
 #[derive(Debug)]
 pub struct Ray {
-    pub origin: Vec3,
-    pub direction: Vec3,
+    pub origin: DVec3,
+    pub direction: DVec3,
 }
 
 #[derive(Clone)]
 pub struct AABB {
-    pub min: Vec3,
-    pub max: Vec3,
+    pub min: DVec3,
+    pub max: DVec3,
 }
 
 pub const CHUNK_SIZE: i32 = 16; // 16×16×16 voxels
 pub const CHUNK_POW: u32 = 4;
 
+/// Which faces `mesh_chunk` emits: opaque voxels, or translucent ones that
+/// are drawn with alpha blending on top.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MeshPass {
+    Opaque,
+    Transparent,
+}
+
+/// Marks the child entity holding a chunk's transparent mesh.
+#[derive(Component)]
+pub struct TransparentChunkMesh {
+    pub volume: Entity,
+    pub key: ChunkKey,
+}
+
+/// Octree space to world space of a voxel volume, in double precision.
+/// Updated every frame from the volume's grid cell and transform by
+/// `track_voxel_volumes`.
+#[derive(Component, Debug, Clone, Copy, Default)]
+pub struct VolumeTransform(pub DAffine3);
+
+impl VolumeTransform {
+    pub fn to_world(&self, local: DVec3) -> DVec3 {
+        self.0.transform_point3(local)
+    }
+
+    pub fn to_local(&self, world: DVec3) -> DVec3 {
+        self.0.inverse().transform_point3(world)
+    }
+
+    /// Direction from world space into octree space; not normalized when
+    /// the volume is scaled.
+    pub fn direction_to_local(&self, world: DVec3) -> DVec3 {
+        self.0.inverse().transform_vector3(world)
+    }
+}
+
+/// Lets a voxel volume fly around as a ship or vehicle.
+///
+/// `move_voxel_bodies` moves the volume's transform by its velocities every
+/// frame; its chunks are children of the volume, so they follow without being
+/// remeshed, and they keep their LOD while the body moves. Edits go through
+/// raycasts transformed into body space by the [`VolumeTransform`].
+///
+/// The mass is the voxel count the octree keeps current on every edit; it is
+/// copied over by `update_voxel_body_mass`.
+#[derive(Component, Debug, Clone, Default)]
+pub struct VoxelBody {
+    /// Velocity in the parent grid, in world units per second.
+    pub linear_velocity: DVec3,
+    /// Rotation per second around the centre of mass, as a scaled axis in the
+    /// parent grid.
+    pub angular_velocity: Vec3,
+    /// Number of voxels in the body.
+    pub mass: u64,
+    /// Mean voxel centre, in octree space.
+    pub center_of_mass: DVec3,
+}
+
+impl VoxelBody {
+    pub fn new(linear_velocity: DVec3, angular_velocity: Vec3) -> Self {
+        Self {
+            linear_velocity,
+            angular_velocity,
+            ..default()
+        }
+    }
+}
+
+/// Edge of a chunk snapshot: the chunk plus a one-voxel border on each side.
+pub const PADDED: i32 = CHUNK_SIZE + 2;
+
+/// Self-contained copy of one chunk for meshing off the main thread, filled
+/// by [`SparseVoxelOctree::extract_chunk`]: its voxels plus a one-voxel border
+/// taken from the neighbours, and the light level of every cell. Cells are
+/// addressed chunk-locally in `-1..=CHUNK_SIZE` and stored x-major.
+pub struct ChunkSnapshot {
+    pub voxels: Box<[Option<Voxel>]>,
+    pub light: Box<[u8]>,
+}
+
+impl ChunkSnapshot {
+    pub fn empty() -> Self {
+        let len = (PADDED * PADDED * PADDED) as usize;
+        Self {
+            voxels: vec![None; len].into_boxed_slice(),
+            light: vec![0; len].into_boxed_slice(),
+        }
+    }
+
+    pub fn index(p: IVec3) -> usize {
+        let q = p + IVec3::ONE;
+        ((q.x * PADDED + q.y) * PADDED + q.z) as usize
+    }
+
+    pub fn voxel(&self, p: IVec3) -> Option<Voxel> {
+        self.voxels[Self::index(p)]
+    }
+
+    pub fn light(&self, p: IVec3) -> u8 {
+        self.light[Self::index(p)]
+    }
+
+    /// Empty the border slab behind every face set in `sides` (one bit per
+    /// face, in face order). The mesher then closes the chunk surface there
+    /// with skirt faces, which hide the gaps towards a neighbour meshed at a
+    /// different LOD.
+    pub fn open_sides(&mut self, sides: u8) {
+        for face in 0..6 {
+            if sides & (1 << face) == 0 {
+                continue;
+            }
+            let axis = face / 2;
+            let layer = if face % 2 == 0 { -1 } else { CHUNK_SIZE };
+            for a in -1..=CHUNK_SIZE {
+                for b in -1..=CHUNK_SIZE {
+                    let mut p = IVec3::ZERO;
+                    p[axis] = layer;
+                    p[(axis + 1) % 3] = a;
+                    p[(axis + 2) % 3] = b;
+                    self.voxels[Self::index(p)] = None;
+                }
+            }
+        }
+    }
+}
+
 #[derive(Component)]
 pub struct Chunk {
+    /// octree entity the chunk belongs to, also its parent
+    pub volume: Entity,
     pub key: ChunkKey,
     pub voxels: Vec<(IVec3, Voxel)>, // local coords 0‥15
     pub dirty: bool,
@@ -121,46 +316,188 @@ pub struct Chunk {
 #[derive(Component, Debug, Clone, Copy)]
 pub struct ChunkLod(pub u32);
 
+/// Elapsed time in seconds at which the chunk was last inside the frustum and
+/// not occluded; the least recently visible chunks are evicted first.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct ChunkLastVisible(pub f32);
+
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ChunkKey(pub i32, pub i32, pub i32);
 
 /// maximum amount of *new* chunk meshes we are willing to create each frame
+///
+/// `per_frame` is adapted every frame by `adapt_chunk_budget` so the
+/// main-thread work of a frame settles at `target_frame_time`, given the
+/// measured cost of one chunk.
 #[derive(Resource)]
 pub struct ChunkBudget {
     pub per_frame: usize,
+    pub min_per_frame: usize,
+    pub max_per_frame: usize,
+    /// seconds
+    pub target_frame_time: f32,
 }
 impl Default for ChunkBudget {
     fn default() -> Self {
-        Self { per_frame: 4 } // tweak to taste
+        Self {
+            per_frame: 4, // tweak to taste
+            min_per_frame: 1,
+            max_per_frame: 64,
+            target_frame_time: 1.0 / 60.0,
+        }
     }
 }
 
-/// FIFO queue with chunk keys that still need meshing
+/// Chunk extraction and meshing cost, gathered over a frame and smoothed
+/// across frames. All times are in seconds.
+#[derive(Resource, Default)]
+pub struct ChunkMeshingStats {
+    /// main-thread extraction time spent this frame
+    pub extract_time: f32,
+    pub extracted: usize,
+    /// meshing time of the chunks that finished this frame
+    pub mesh_time: f32,
+    pub meshed: usize,
+    /// average extraction time per chunk
+    pub avg_extract: f32,
+    /// average meshing time per chunk
+    pub avg_mesh: f32,
+    /// unrounded `ChunkBudget::per_frame`
+    pub allowance: f32,
+    /// when the main schedule of this frame started
+    pub frame_started: Option<Instant>,
+    /// main-thread time of the last frame, from `First` to `Last`; unlike
+    /// the frame delta it is not stretched by waiting for vsync
+    pub frame_work: f32,
+    /// extraction time included in `frame_work`
+    pub frame_extract: f32,
+}
+
+/// FIFO queue with the (volume, chunk key) pairs that still need meshing
 #[derive(Resource, Default)]
 pub struct ChunkQueue {
-    pub keys: VecDeque<ChunkKey>,
-    pub set: HashSet<ChunkKey>,
+    pub keys: VecDeque<(Entity, ChunkKey)>,
+    pub set: HashSet<(Entity, ChunkKey)>,
+}
+
+/// which mesher turns dirty chunks into meshes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MeshingBackend {
+    /// greedy mesher on the async compute task pool, one task per chunk
+    #[default]
+    Cpu,
+    /// compute shader in `greedy_meshing.wgsl`; falls back to the CPU when the
+    /// adapter has no compute support
+    Gpu,
+}
+
+#[derive(Resource, Default)]
+pub struct ChunkMeshingCfg {
+    pub backend: MeshingBackend,
+}
+
+/// Both pass meshes of one chunk, ready to be attached to its entity, and the
+/// face connectivity used for occlusion culling.
+pub struct MeshedChunk {
+    pub volume: Entity,
+    pub key: ChunkKey,
+    pub lod: u32,
+    pub opaque: Option<Mesh>,
+    pub transparent: Option<Mesh>,
+    pub connectivity: FaceConnectivity,
+    /// time spent meshing this chunk
+    pub mesh_time: Duration,
+}
+
+/// CPU meshing tasks in flight on the `AsyncComputeTaskPool`.
+///
+/// Every task is tagged with a generation; only the newest generation of a
+/// chunk is applied, so a chunk edited while its task was running drops the
+/// outdated result.
+#[derive(Resource, Default)]
+pub struct ChunkMeshTasks {
+    pub next_generation: u64,
+    /// newest generation requested per (volume, chunk)
+    pub latest: HashMap<(Entity, ChunkKey), u64>,
+    pub running: Vec<(Entity, ChunkKey, u64, Task<MeshedChunk>)>,
+}
+
+/// map “which chunk key already has an entity in the world?”, per volume
+#[derive(Resource, Default)]
+pub struct SpawnedChunks(pub HashMap<Entity, HashMap<ChunkKey, Entity>>);
+
+impl SpawnedChunks {
+    pub fn get(&self, volume: Entity, key: ChunkKey) -> Option<Entity> {
+        self.0.get(&volume).and_then(|chunks| chunks.get(&key)).copied()
+    }
+
+    pub fn insert(&mut self, volume: Entity, key: ChunkKey, chunk: Entity) {
+        self.0.entry(volume).or_default().insert(key, chunk);
+    }
+
+    pub fn remove(&mut self, volume: Entity, key: ChunkKey) -> Option<Entity> {
+        let chunks = self.0.get_mut(&volume)?;
+        let chunk = chunks.remove(&key);
+        if chunks.is_empty() {
+            self.0.remove(&volume);
+        }
+        chunk
+    }
+
+    /// Spawned chunks over all volumes.
+    pub fn len(&self) -> usize {
+        self.0.values().map(HashMap::len).sum()
+    }
 }
 
-/// map “which chunk key already has an entity in the world?”
+/// (volume, chunk key) pairs whose last mesh had no faces, e.g. chunks buried
+/// inside solid terrain. They have no entity, so streaming would queue them
+/// again on every camera move; an entry is dropped once the chunk turns dirty.
 #[derive(Resource, Default)]
-pub struct SpawnedChunks(pub HashMap<ChunkKey, Entity>);
+pub struct EmptyChunks(pub HashSet<(Entity, ChunkKey)>);
 
 /// how big the cube around the player is, measured in chunks
 #[derive(Resource)]
 pub struct ChunkCullingCfg {
+    /// chunks within this distance are loaded
     pub view_distance_chunks: i32,
+    /// chunks beyond this distance are unloaded; larger than
+    /// `view_distance_chunks` so chunks near the edge do not flicker in and
+    /// out as the camera moves back and forth
+    pub unload_distance_chunks: i32,
+    /// most chunk entities kept at once; past it the least recently visible
+    /// chunks are unloaded and chunks outside the frustum are not loaded
+    pub max_chunks: usize,
+    /// chunks up to this far are hidden when other chunks block the view;
+    /// 0 turns occlusion culling off
+    pub occlusion_distance_chunks: i32,
 }
 impl Default for ChunkCullingCfg {
     fn default() -> Self {
         Self {
             view_distance_chunks: 6,
+            unload_distance_chunks: 8,
+            max_chunks: 4096,
+            occlusion_distance_chunks: 6,
         }
     }
 }
 
+/// camera chunk of every volume when its chunks were last enqueued
 #[derive(Resource, Default)]
-pub struct PrevCameraChunk(pub Option<ChunkKey>);
+pub struct PrevCameraChunk(pub HashMap<Entity, ChunkKey>);
+
+/// The camera in world space. Streaming works in each volume's octree space
+/// (see [`VolumeTransform`]) while transforms and frusta are relative to the
+/// floating origin, so both are derived from the camera's grid cell once per
+/// frame.
+#[derive(Resource, Default)]
+pub struct StreamingCamera {
+    /// camera position
+    pub position: DVec3,
+    /// where render-space zero, the centre of the floating origin's cell, lies
+    pub origin: DVec3,
+}
 
 #[derive(Resource, Clone)]
 pub struct ChunkOffsets(pub Vec<IVec3>);
@@ -184,18 +521,15 @@ impl ChunkOffsets {
 /// storage avoids frequent allocations when rebuilding many chunks.
 #[derive(Resource, Default)]
 pub struct MeshBufferPool {
-    pub positions: Vec<[f32; 3]>,
-    pub normals: Vec<[f32; 3]>,
-    pub uvs: Vec<[f32; 2]>,
+    /// Packed vertices, see `material::PackedVertex`.
+    pub vertices: Vec<u32>,
     pub indices: Vec<u32>,
 }
 
 impl MeshBufferPool {
     /// Clears all buffers while keeping the allocated capacity.
     pub fn clear(&mut self) {
-        self.positions.clear();
-        self.normals.clear();
-        self.uvs.clear();
+        self.vertices.clear();
         self.indices.clear();
     }
 }
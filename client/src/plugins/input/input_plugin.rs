@@ -2,6 +2,7 @@
 use bevy::app::{App, Plugin, PreUpdate, Startup};
 use bevy::ecs::schedule::IntoScheduleConfigs;
 use bevy::prelude::Update;
+use crate::plugins::environment::systems::voxels::queue_systems::track_voxel_volumes;
 use crate::plugins::input::systems::voxels::VoxelEditMode;
 
 pub struct InputPlugin;
@@ -15,7 +16,9 @@ impl Plugin for InputPlugin {
                 crate::plugins::input::systems::ui::ui_system,
                 //crate::plugins::input::systems::network::network_system,
                 crate::plugins::input::systems::movement::movement_system,
+                // raycasts use this frame's volume transforms
                 crate::plugins::input::systems::voxels::voxel_system
+                    .after(track_voxel_volumes),
 
             ),
 
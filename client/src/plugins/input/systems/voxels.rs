@@ -22,11 +22,12 @@ const EDIT_SPHERE_RADIUS: i32 = 8;
 pub fn voxel_system(
     keyboard_input: Res<ButtonInput<KeyCode>>,
     mouse_button_input: Res<ButtonInput<MouseButton>>,
-    mut octree_query: Query<&mut SparseVoxelOctree>,
+    mut octree_query: Query<(Entity, &mut SparseVoxelOctree, &VolumeTransform, Option<&Name>)>,
 
     mut query: Query<(&mut Transform, &mut CameraController)>,
     mut windows: Query<&mut Window>,
     mut edit_mode: ResMut<VoxelEditMode>,
+    camera: Res<StreamingCamera>,
 ) {
     let Ok(mut window) = windows.get_single_mut() else {
         return;
@@ -39,26 +40,38 @@ pub fn voxel_system(
     // 5) Octree Keys
     // =======================
     if keyboard_input.just_pressed(KeyCode::F2) {
-        for mut octree in octree_query.iter_mut() {
+        for (_, mut octree, _, _) in octree_query.iter_mut() {
             octree.show_wireframe = !octree.show_wireframe;
         }
     }
     if keyboard_input.just_pressed(KeyCode::F3) {
-        for mut octree in octree_query.iter_mut() {
+        for (_, mut octree, _, _) in octree_query.iter_mut() {
             octree.show_world_grid = !octree.show_world_grid;
         }
     }
 
     if keyboard_input.just_pressed(KeyCode::KeyQ) && window.cursor_options.visible == false {
-        for mut octree in octree_query.iter_mut() {
-            octree.insert(transform.translation, Voxel::random_sides());
+        // only the volume whose centre is closest to the camera
+        let nearest = octree_query
+            .iter_mut()
+            .map(|(_, octree, volume, _)| {
+                let distance = volume.to_world(octree.center).distance(camera.position);
+                (distance, octree, volume)
+            })
+            .min_by(|a, b| a.0.total_cmp(&b.0));
+        if let Some((_, mut octree, volume)) = nearest {
+            octree.insert(volume.to_local(camera.position), Voxel::random_sides());
         }
     }
     if keyboard_input.just_pressed(KeyCode::F4) {
-        let path = Path::new("octree.bin");
-        for octree in octree_query.iter() {
-            if let Err(e) = octree.save_to_file(path) {
-                error!("failed to save octree: {e}");
+        // one file per volume, named volumes keep the file they load from
+        for (entity, octree, _, name) in octree_query.iter() {
+            let path = match name {
+                Some(name) => format!("{name}.bin"),
+                None => format!("octree_{}.bin", entity.index()),
+            };
+            if let Err(e) = octree.save_to_file(Path::new(&path)) {
+                error!("failed to save octree to {path}: {e}");
             }
         }
     }
@@ -80,45 +93,52 @@ pub fn voxel_system(
     {
         // Get the mouse position in normalized device coordinates (-1 to 1)
         if let Some(_) = window.cursor_position() {
-            // Set the ray direction to the camera's forward vector
-            let ray_origin = transform.translation;
-            let ray_direction = transform.forward().normalize();
+            // Cast from the camera's absolute position along its forward vector,
+            // transformed into each volume's octree space; only the nearest hit
+            // is edited
+            let ray_direction = transform.forward().normalize().as_dvec3();
 
-            let ray = Ray {
-                origin: ray_origin,
-                direction: ray_direction,
-            };
+            let mut nearest = None;
+            for (entity, octree, volume, _) in octree_query.iter() {
+                let ray = Ray {
+                    origin: volume.to_local(camera.position),
+                    direction: volume.direction_to_local(ray_direction).normalize(),
+                };
+                if let Some(hit) = octree.raycast(&ray) {
+                    let distance = volume.to_world(hit.0).distance(camera.position);
+                    if nearest.is_none_or(|(best, _, _)| distance < best) {
+                        nearest = Some((distance, entity, hit));
+                    }
+                }
+            }
 
-            for mut octree in octree_query.iter_mut() {
-                if let Some((hit_x, hit_y, hit_z, depth, normal)) = octree.raycast(&ray) {
+            if let Some((_, entity, (hit_position, depth, normal))) = nearest {
+                if let Ok((_, mut octree, _, _)) = octree_query.get_mut(entity) {
+                    let normal = normal.as_dvec3();
                     match *edit_mode {
                         VoxelEditMode::Single => {
                             if mouse_button_input.just_pressed(MouseButton::Right) {
                                 let voxel_size = octree.get_spacing_at_depth(depth);
-                                let hit_position = Vec3::new(hit_x as f32, hit_y as f32, hit_z as f32);
                                 let epsilon = voxel_size * 0.1;
-                                let offset_position = hit_position - (normal * Vec3::splat(epsilon));
+                                let offset_position = hit_position - (normal * epsilon);
                                 octree.remove(offset_position);
                             } else if mouse_button_input.just_pressed(MouseButton::Left) {
                                 let voxel_size = octree.get_spacing_at_depth(depth);
-                                let hit_position = Vec3::new(hit_x as f32, hit_y as f32, hit_z as f32);
                                 let epsilon = voxel_size * 0.1;
-                                let offset_position = hit_position + (normal * Vec3::splat(epsilon));
+                                let offset_position = hit_position + (normal * epsilon);
                                 octree.insert(offset_position, Voxel::random_sides());
                             }
                         }
                         VoxelEditMode::Sphere => {
                             if mouse_button_input.just_pressed(MouseButton::Right) {
                                 let voxel_size = octree.get_spacing_at_depth(depth);
-                                let hit_position = Vec3::new(hit_x as f32, hit_y as f32, hit_z as f32);
                                 let epsilon = voxel_size * 0.1;
-                                let offset = hit_position - normal * Vec3::splat(epsilon);
+                                let offset = hit_position - normal * epsilon;
                                 octree.remove_sphere(offset, EDIT_SPHERE_RADIUS);
                             } else if mouse_button_input.just_pressed(MouseButton::Left) {
                                 let voxel_size = octree.get_spacing_at_depth(depth);
-                                let hit_position = Vec3::new(hit_x as f32, hit_y as f32, hit_z as f32);
                                 let epsilon = voxel_size * 0.1;
-                                let offset = hit_position + normal * Vec3::splat(epsilon);
+                                let offset = hit_position + normal * epsilon;
                                 octree.insert_sphere(offset, EDIT_SPHERE_RADIUS, Voxel::random_sides());
                             }
                         }